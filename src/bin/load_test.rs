@@ -0,0 +1,40 @@
+//压力测试单独作为一个程序,不随服务器一起发布
+use clap::Parser;
+use log::{error, info, warn};
+
+use rws_rjr::{
+    cli::{LoadTestArgs, EXIT_OK, EXIT_START_ERROR},
+    load_test::load_test,
+    logger,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "用大量Dummy客户端对本机的Relay服务器进行压力测试")]
+struct Cli {
+    #[command(flatten)]
+    args: LoadTestArgs,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    logger::init_shell().unwrap();
+
+    let exit_code = match load_test(cli.args).await {
+        Ok(report) => {
+            info!("压力测试完成\n{}", report);
+            if report.errors() > 0 {
+                warn!(
+                    "压力测试中出现{}个错误,详细原因可以用debug日志查看",
+                    report.errors()
+                );
+            }
+            EXIT_OK
+        }
+        Err(e) => {
+            error!("压力测试失败:{}", e);
+            EXIT_START_ERROR
+        }
+    };
+    std::process::exit(exit_code);
+}
@@ -0,0 +1,135 @@
+pub mod format;
+pub mod replay;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use chrono::Local;
+use log::{info, warn};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+
+use crate::{connection::shared_connection::SharedConnection, server::CaptureConfig};
+
+use self::format::{CaptureRecord, Direction, CAPTURE_MAGIC};
+
+lazy_static! {
+    static ref CAPTURE: RwLock<Option<mpsc::Sender<CaptureRecord>>> = RwLock::new(None);
+    //写入跟不上时被丢弃的记录数
+    static ref DROPPED: AtomicU64 = AtomicU64::new(0);
+}
+
+//写入线程积压的最大记录数,超过后直接丢弃,不拖慢收发
+static CAPTURE_CHANNEL_SIZE: usize = 4096;
+
+pub fn apply_capture_config(capture_config: &CaptureConfig) -> anyhow::Result<()> {
+    //旧的发送端被丢弃后写入任务会写完剩余记录再退出
+    let mut capture = CAPTURE.write().expect("write capture error");
+    *capture = None;
+    if !capture_config.enable {
+        return Ok(());
+    }
+
+    let path = capture_path(Path::new(&capture_config.file));
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    info!("正在抓包到{}", path.display());
+
+    let (record_tx, record_rx) = mpsc::channel(CAPTURE_CHANNEL_SIZE);
+    tokio::spawn(async move {
+        if let Err(e) = write_capture(File::from_std(file), record_rx).await {
+            warn!("写入抓包文件{}失败:{}", path.display(), e);
+        }
+    });
+    *capture = Some(record_tx);
+    Ok(())
+}
+
+//抓包未开启时只有一次读锁的开销,开启时数据不复制
+pub fn capture_packet(
+    direction: Direction,
+    shared_con: &SharedConnection,
+    packet_type: u32,
+    payload: &Bytes,
+) {
+    let capture = CAPTURE.read().expect("read capture error");
+    let Some(record_tx) = capture.as_ref() else {
+        return;
+    };
+
+    let shared_data = &shared_con.shared_data;
+    let record = CaptureRecord {
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        direction,
+        addr: shared_con.addr_string(),
+        room_id: shared_data
+            .player_info
+            .room_id
+            .read()
+            .expect("read room id error")
+            .clone(),
+        packet_type,
+        payload: payload.clone(),
+    };
+    if record_tx.try_send(record).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+//每次开启抓包写入新的文件,文件名加上时间避免覆盖之前的抓包
+fn capture_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(Local::now().format("-%Y%m%d-%H%M%S").to_string());
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+async fn write_capture(
+    file: File,
+    mut record_rx: mpsc::Receiver<CaptureRecord>,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(file);
+    writer.write_all(CAPTURE_MAGIC).await?;
+
+    let mut buf = Vec::new();
+    while let Some(record) = record_rx.recv().await {
+        buf.clear();
+        record.encode(&mut buf);
+        //把积压的记录一起写入后再flush
+        while let Ok(record) = record_rx.try_recv() {
+            record.encode(&mut buf);
+        }
+        writer.write_all(&buf).await?;
+        writer.flush().await?;
+
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("抓包写入过慢,丢弃了{}条记录", dropped);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::io::{self, ErrorKind, Read};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+//文件头,最后两位是格式版本
+pub const CAPTURE_MAGIC: &[u8; 8] = b"RJRCAP01";
+//单个数据包的上限,防止损坏的文件申请过大的内存
+const MAX_PAYLOAD_LENGTH: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    //客户端发给服务器
+    In,
+    //服务器发给客户端
+    Out,
+}
+
+//抓包文件中的一条记录,所有整数均为大端
+//u64时间戳(毫秒) | u8方向 | u16+地址 | u16+房间号(空为不在房间) | u32类型 | u32+数据
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub timestamp_ms: u64,
+    pub direction: Direction,
+    pub addr: String,
+    pub room_id: Option<String>,
+    pub packet_type: u32,
+    pub payload: Bytes,
+}
+
+impl CaptureRecord {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.timestamp_ms);
+        buf.put_u8(match self.direction {
+            Direction::In => 0,
+            Direction::Out => 1,
+        });
+        put_string(buf, &self.addr);
+        put_string(buf, self.room_id.as_deref().unwrap_or_default());
+        buf.put_u32(self.packet_type);
+        buf.put_u32(self.payload.len() as u32);
+        buf.put_slice(&self.payload);
+    }
+
+    //文件正好在记录之间结束时返回None
+    pub fn decode<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut timestamp = [0; 8];
+        match reader.read_exact(&mut timestamp) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let direction = match read_u8(reader)? {
+            0 => Direction::In,
+            1 => Direction::Out,
+            direction => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown direction {}", direction),
+                ))
+            }
+        };
+        let addr = read_string(reader)?;
+        let room_id = Some(read_string(reader)?).filter(|room_id| !room_id.is_empty());
+        let packet_type = read_u32(reader)?;
+
+        let payload_length = read_u32(reader)? as usize;
+        if payload_length > MAX_PAYLOAD_LENGTH {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("payload too long: {}", payload_length),
+            ));
+        }
+        let mut payload = BytesMut::zeroed(payload_length);
+        reader.read_exact(&mut payload)?;
+
+        Ok(Some(Self {
+            timestamp_ms: u64::from_be_bytes(timestamp),
+            direction,
+            addr,
+            room_id,
+            packet_type,
+            payload: payload.freeze(),
+        }))
+    }
+}
+
+//读取整个抓包文件
+pub fn read_capture<R: Read>(reader: &mut R) -> io::Result<Vec<CaptureRecord>> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CAPTURE_MAGIC {
+        return Err(io::Error::new(ErrorKind::InvalidData, "not a capture file"));
+    }
+
+    let mut records = Vec::new();
+    while let Some(record) = CaptureRecord::decode(reader)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.put_u16(s.len() as u16);
+    buf.put_slice(s.as_bytes());
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut length = [0; 2];
+    reader.read_exact(&mut length)?;
+    let mut buf = vec![0; u16::from_be_bytes(length) as usize];
+    reader.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<CaptureRecord> {
+        vec![
+            CaptureRecord {
+                timestamp_ms: 1_700_000_000_000,
+                direction: Direction::In,
+                addr: "127.0.0.1:50000".to_string(),
+                room_id: None,
+                packet_type: 160,
+                payload: Bytes::from_static(b"hello"),
+            },
+            CaptureRecord {
+                timestamp_ms: 1_700_000_000_100,
+                direction: Direction::Out,
+                addr: "[::1]:50001".to_string(),
+                room_id: Some("S1234".to_string()),
+                //未知类型也要原样保存
+                packet_type: 0xdead_beef,
+                payload: Bytes::new(),
+            },
+        ]
+    }
+
+    fn capture_file(records: &[CaptureRecord]) -> Vec<u8> {
+        let mut buf = CAPTURE_MAGIC.to_vec();
+        for record in records {
+            record.encode(&mut buf);
+        }
+        buf
+    }
+
+    #[test]
+    fn round_trip() {
+        let records = records();
+        let buf = capture_file(&records);
+        assert_eq!(read_capture(&mut &buf[..]).unwrap(), records);
+        assert!(read_capture(&mut &CAPTURE_MAGIC[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(read_capture(&mut &b"RJRCAP99"[..]).is_err());
+        assert!(read_capture(&mut &b"RJR"[..]).is_err());
+
+        //记录中间截断
+        let buf = capture_file(&records());
+        assert!(read_capture(&mut &buf[..buf.len() - 1]).is_err());
+
+        let mut buf = capture_file(&records()[..1]);
+        buf[CAPTURE_MAGIC.len() + 8] = 2;
+        assert!(read_capture(&mut &buf[..]).is_err());
+
+        let mut buf = CAPTURE_MAGIC.to_vec();
+        buf.put_u64(0);
+        buf.put_u8(0);
+        put_string(&mut buf, "");
+        put_string(&mut buf, "");
+        buf.put_u32(160);
+        buf.put_u32(MAX_PAYLOAD_LENGTH as u32 + 1);
+        assert!(read_capture(&mut &buf[..]).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use log::{info, warn};
+use tokio::{sync::Notify, time::Instant};
+
+use crate::{
+    dummy::{shared_dummy::SharedDummy, Dummy, DummyConf, DummyVersion},
+    packet::{
+        message::{PacketMessage, RelayBecomeServer, RelayHallCommand},
+        Packet, PacketType,
+    },
+};
+
+use super::format::{read_capture, CaptureRecord, Direction};
+
+//等待房主拿到新房间号的最长时间
+static ROOM_MAPPING_TIMEOUT: Duration = Duration::from_secs(5);
+//发送完最后一个数据包后等待服务器处理的时间
+static REPLAY_TAIL: Duration = Duration::from_secs(2);
+
+//抓包中的房间号在回放时会变化,记录旧房间号到新房间号的对应
+#[derive(Default)]
+struct RoomMapping {
+    //抓包中由房主创建的房间,其他房间号不需要等待
+    host_rooms: HashSet<String>,
+    rooms: RwLock<HashMap<String, String>>,
+    notify: Notify,
+}
+
+impl RoomMapping {
+    async fn wait_for(&self, old_id: &str) -> Option<String> {
+        let deadline = Instant::now() + ROOM_MAPPING_TIMEOUT;
+        loop {
+            let notified = self.notify.notified();
+            if let Some(new_id) = self.rooms.read().expect("read rooms error").get(old_id) {
+                return Some(new_id.clone());
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+//把抓包中客户端发出的数据包按原来的时间间隔通过Dummy发给target
+//每个抓包中的连接对应一个Dummy,服务器发出的数据包只用来确定房间号
+pub async fn replay(path: &Path, target: &str, speed: f64) -> anyhow::Result<()> {
+    let records = read_capture(&mut std::io::BufReader::new(std::fs::File::open(path)?))?;
+    let Some(first) = records.first() else {
+        anyhow::bail!("抓包文件中没有记录");
+    };
+    let start_ms = first.timestamp_ms;
+
+    //每个连接作为房主创建的房间
+    let mut host_rooms = HashMap::new();
+    for record in records.iter() {
+        if record.direction == Direction::Out
+            && record.packet_type == PacketType::RELAY_BECOME_SERVER as u32
+        {
+            if let Some(room_id) = &record.room_id {
+                host_rooms.insert(record.addr.clone(), room_id.clone());
+            }
+        }
+    }
+
+    let room_mapping = Arc::new(RoomMapping {
+        host_rooms: host_rooms.values().cloned().collect(),
+        ..Default::default()
+    });
+    let mut dummies: HashMap<String, Arc<SharedDummy>> = HashMap::new();
+    let mut sent = 0;
+    let start = Instant::now();
+
+    let mut skipped = 0;
+    for record in records
+        .iter()
+        .filter(|record| record.direction == Direction::In)
+    {
+        //Packet只能表示已知的类型,未知类型不能原样发送,改成其他类型会改变回放的内容
+        let Ok(packet_type) = PacketType::try_from(record.packet_type) else {
+            warn!(addr = record.addr.as_str(); "跳过未知类型{}的数据包", record.packet_type);
+            skipped += 1;
+            continue;
+        };
+        let offset = Duration::from_millis(record.timestamp_ms.saturating_sub(start_ms));
+        tokio::time::sleep_until(start + offset.div_f64(speed)).await;
+
+        let dummy = match dummies.get(&record.addr) {
+            Some(dummy) => dummy.clone(),
+            None => {
+                //心跳回复也在抓包文件里,不再自动回复
+                let dummy = Dummy::new_shared(
+                    DummyConf {
+                        auto_heartbeat: false,
+                        ..DummyConf::new(
+                            format!("replay-{}", dummies.len()),
+                            target.to_string(),
+                            DummyVersion::Version1_15,
+                        )
+                    },
+                    target,
+                )
+                .await?;
+                info!(addr = record.addr.as_str(); "回放连接已建立");
+                tokio::spawn(watch_new_room(
+                    dummy.clone(),
+                    host_rooms.get(&record.addr).cloned(),
+                    room_mapping.clone(),
+                ));
+                dummies.insert(record.addr.clone(), dummy.clone());
+                dummy
+            }
+        };
+
+        let payload = match rewrite_join_command(record, &room_mapping).await {
+            Some(payload) => payload,
+            None => record.payload.clone(),
+        };
+        let packet = Packet::new(packet_type, payload);
+        dummy.send_packet(packet).await;
+        sent += 1;
+    }
+
+    tokio::time::sleep(REPLAY_TAIL).await;
+    info!(
+        "回放完成,共{}个连接,{}个数据包,跳过{}个未知类型的数据包",
+        dummies.len(),
+        sent,
+        skipped
+    );
+    Ok(())
+}
+
+//记录房主在回放中拿到的新房间号,同时读掉服务器发来的数据
+async fn watch_new_room(
+    dummy: Arc<SharedDummy>,
+    old_room_id: Option<String>,
+    room_mapping: Arc<RoomMapping>,
+) {
+    while let Some(packet) = dummy.recv_packet().await {
+        let Some(old_room_id) = &old_room_id else {
+            continue;
+        };
+        let Ok(message) = packet.to_message::<RelayBecomeServer>() else {
+            continue;
+        };
+        let Some(new_room_id) = message.room_id() else {
+            continue;
+        };
+        info!(room_id = new_room_id.as_str(); "房间{}在回放中为{}", old_room_id, new_room_id);
+        room_mapping
+            .rooms
+            .write()
+            .expect("write rooms error")
+            .insert(old_room_id.clone(), new_room_id);
+        room_mapping.notify.notify_waiters();
+    }
+}
+
+//把加入房间的命令中的旧房间号换成回放中的房间号
+async fn rewrite_join_command(
+    record: &CaptureRecord,
+    room_mapping: &RoomMapping,
+) -> Option<Bytes> {
+    if record.packet_type != PacketType::RELAY_118_117_RETURN as u32 {
+        return None;
+    }
+    let message = RelayHallCommand::decode(&mut record.payload.clone()).ok()?;
+    let command = message.command.as_str();
+    if !room_mapping.host_rooms.contains(command) {
+        return None;
+    }
+
+    let Some(new_id) = room_mapping.wait_for(command).await else {
+        warn!("房间{}在回放中没有对应的房间,按原样发送", command);
+        return None;
+    };
+    let mut payload = BytesMut::new();
+    RelayHallCommand { command: new_id }.encode(&mut payload);
+    Some(payload.freeze())
+}
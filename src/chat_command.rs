@@ -0,0 +1,144 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use crate::{
+    connection::{room_player_count, shared_connection::SharedConnection},
+    module::MODULE_MANAGER,
+    packet::{message::ChatReceive, Packet},
+    server::reload::live_config,
+};
+
+//内置的房间命令,模块的命令在这些之后处理
+static BUILTIN_COMMANDS: [(&str, &str); 5] = [
+    ("help", "显示可用的命令"),
+    ("ping", "检查与服务器的连接"),
+    ("room", "显示当前房间的信息"),
+    ("players", "列出房间中的玩家"),
+    ("afk", "切换暂离状态"),
+];
+
+//处理房间中的聊天命令,返回Some时只回复给发送的玩家,不转发给房主
+//服务器和模块都不处理的命令返回None,照常转发给房主,房主可能有自己的命令
+pub async fn handle_chat_command(
+    shared_con: &Arc<SharedConnection>,
+    packet: &Packet,
+) -> Option<String> {
+    let chat = packet.to_message::<ChatReceive>().ok()?;
+    let prefix = live_config().message.command_prefix.clone();
+    let (command, args) = parse_command(&chat.message, &prefix)?;
+
+    let reply = match command {
+        "help" => help(&prefix),
+        "ping" => "pong".to_string(),
+        "room" => room_info(shared_con).await,
+        "players" => player_list(shared_con).await,
+        "afk" => toggle_afk(shared_con),
+        command => MODULE_MANAGER.on_chat_command(shared_con, command, &args)?,
+    };
+    Some(reply)
+}
+
+//不是命令时返回None
+fn parse_command<'a>(message: &'a str, prefix: &str) -> Option<(&'a str, Vec<&'a str>)> {
+    let mut words = message.strip_prefix(prefix)?.split_whitespace();
+    let command = words.next()?;
+    Some((command, words.collect()))
+}
+
+fn help(prefix: &str) -> String {
+    BUILTIN_COMMANDS
+        .iter()
+        .map(|(command, description)| (command.to_string(), description.to_string()))
+        .chain(MODULE_MANAGER.chat_commands())
+        .map(|(command, description)| format!("{}{} {}", prefix, command, description))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn room_info(shared_con: &Arc<SharedConnection>) -> String {
+    let Some(room) = shared_con.get_relay_room().await else {
+        return "你不在房间中".to_string();
+    };
+    let (admin, player_list) = room.get_room_member().await;
+    let room_data = &room.shared_data;
+    format!(
+        "房间:{}\n房主:{}\n玩家:{}/{}\n状态:{}{}",
+        room_data.id,
+        admin
+            .map(|admin| player_name(&admin))
+            .unwrap_or_else(|| "无".to_string()),
+        room_player_count(&player_list),
+        room_data.custom.max_player_size,
+        if room_data.start_game.load(Ordering::Relaxed) {
+            "游戏中"
+        } else {
+            "等待中"
+        },
+        if room_data.custom.mods { " (mods)" } else { "" }
+    )
+}
+
+async fn player_list(shared_con: &Arc<SharedConnection>) -> String {
+    let Some(room) = shared_con.get_relay_room().await else {
+        return "你不在房间中".to_string();
+    };
+    let (admin, player_list) = room.get_room_member().await;
+    admin
+        .map(|admin| format!("[房主] {}", player_status(&admin)))
+        .into_iter()
+        .chain(
+            player_list
+                .iter()
+                .map(|(index, player)| format!("[{}] {}", index, player_status(player))),
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn toggle_afk(shared_con: &Arc<SharedConnection>) -> String {
+    let afk = !shared_con
+        .shared_data
+        .player_info
+        .afk
+        .fetch_xor(true, Ordering::Relaxed);
+    if afk {
+        "你已进入暂离状态".to_string()
+    } else {
+        "你已取消暂离状态".to_string()
+    }
+}
+
+fn player_name(shared_con: &SharedConnection) -> String {
+    shared_con
+        .shared_data
+        .player_info
+        .player_name
+        .read()
+        .expect("read player name error")
+        .clone()
+}
+
+fn player_status(shared_con: &SharedConnection) -> String {
+    if shared_con.shared_data.player_info.afk.load(Ordering::Relaxed) {
+        format!("{} (暂离)", player_name(shared_con))
+    } else {
+        player_name(shared_con)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse_command("/help", "/"), Some(("help", vec![])));
+        assert_eq!(
+            parse_command("!kick  a b ", "!"),
+            Some(("kick", vec!["a", "b"]))
+        );
+        assert_eq!(parse_command("/help", "!"), None);
+        assert_eq!(parse_command("普通聊天", "/"), None);
+        //只有前缀的不是命令
+        assert_eq!(parse_command("/ ", "/"), None);
+    }
+}
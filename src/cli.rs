@@ -0,0 +1,146 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand};
+use log::LevelFilter;
+
+use crate::server::config::ConfigOverride;
+
+//退出码,供部署脚本判断
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_CONFIG_ERROR: i32 = 1;
+//命令行参数错误时clap会以2退出
+pub const EXIT_START_ERROR: i32 = 3;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "RW-RJR 铁锈战争Relay服务器")]
+pub struct Cli {
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "RJR_CONFIG",
+        help = "配置文件路径,未指定时依次查找工作目录和程序所在目录"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "启动服务器(默认)")]
+    Run(RunArgs),
+    #[command(about = "检查配置文件,有误时以非零状态码退出")]
+    CheckConfig,
+    #[command(about = "将默认配置输出到标准输出")]
+    PrintDefaultConfig,
+    #[command(about = "输出版本号")]
+    Version,
+    #[command(about = "通过Dummy客户端把抓包文件回放到Relay服务器")]
+    Replay(ReplayArgs),
+    #[command(about = "输出抓包文件中的每个数据包")]
+    DumpCapture {
+        #[arg(help = "抓包文件路径")]
+        file: PathBuf,
+        #[arg(long, help = "输出完整的数据,默认超过256字节的部分会被省略")]
+        full: bool,
+    },
+}
+
+#[derive(Debug, Default, Args)]
+pub struct RunArgs {
+    #[arg(
+        short,
+        long = "port",
+        value_name = "PORT[-PORT]",
+        value_parser = parse_port_range,
+        help = "监听的端口或端口范围,可重复指定,覆盖server.port_range"
+    )]
+    pub ports: Vec<(u32, u32)>,
+
+    #[arg(short, long, help = "覆盖server.thread_number")]
+    pub thread_number: Option<usize>,
+
+    #[arg(short, long, help = "覆盖log.level,如info、debug")]
+    pub log_level: Option<LevelFilter>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    #[arg(help = "抓包文件路径")]
+    pub file: PathBuf,
+
+    #[arg(long, default_value = "127.0.0.1:5123", help = "回放的目标服务器")]
+    pub target: String,
+
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed, help = "回放速度倍率")]
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct LoadTestArgs {
+    #[arg(long, default_value = "127.0.0.1:5123", help = "压力测试的目标服务器,只能是本机地址")]
+    pub target: String,
+
+    #[arg(long, default_value_t = 10, help = "创建的房间数,每个房间一个房主")]
+    pub rooms: usize,
+
+    #[arg(long, default_value_t = 3, help = "每个房间加入的玩家数")]
+    pub players: usize,
+
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration, help = "测试持续时间")]
+    pub duration: Duration,
+
+    #[arg(long, default_value_t = 10, value_parser = parse_rate, help = "房主每秒发给每个玩家的TICK数")]
+    pub tick_rate: u32,
+
+    #[arg(long, default_value_t = 2, value_parser = parse_rate, help = "每个玩家每秒发给房主的GAMECOMMAND数")]
+    pub command_rate: u32,
+
+    #[arg(long, default_value_t = 200, value_parser = parse_rate, help = "每秒建立的连接数")]
+    pub connect_rate: u32,
+
+    #[arg(long, default_value_t = 64, help = "GAMECOMMAND的数据长度")]
+    pub command_size: usize,
+}
+
+impl From<RunArgs> for ConfigOverride {
+    fn from(run_args: RunArgs) -> Self {
+        Self {
+            port_range: (!run_args.ports.is_empty()).then_some(run_args.ports),
+            thread_number: run_args.thread_number,
+            log_level: run_args.log_level,
+        }
+    }
+}
+
+fn parse_port_range(s: &str) -> Result<(u32, u32), String> {
+    let parse_port = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .map(u32::from)
+            .map_err(|e| format!("`{}`不是有效的端口:{}", port, e))
+    };
+    match s.split_once('-') {
+        Some((start, end)) => Ok((parse_port(start)?, parse_port(end)?)),
+        None => {
+            let port = parse_port(s)?;
+            Ok((port, port))
+        }
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0. => Ok(speed),
+        _ => Err(format!("`{}`不是有效的倍率", s)),
+    }
+}
+
+fn parse_rate(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(rate) if rate > 0 => Ok(rate),
+        _ => Err(format!("`{}`不是有效的速率,需要大于0", s)),
+    }
+}
@@ -0,0 +1,370 @@
+pub mod protocol;
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tokio::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+};
+
+use crate::server::ClusterConfig;
+
+use self::protocol::{read_message, write_message, ClusterMessage};
+
+//连接其他节点失败后的重试间隔
+static RECONNECT_INTERVAL: Duration = Duration::from_secs(3);
+static PING_INTERVAL: Duration = Duration::from_secs(10);
+//超过这个时间没有收到消息就认为节点已经离线
+static PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteRoom {
+    pub id: String,
+    pub node_id: u32,
+    pub public_addr: String,
+}
+
+#[derive(Debug)]
+struct RemoteNode {
+    public_addr: String,
+    rooms: HashSet<String>,
+    //同一节点重连后旧连接断开时不能删除新连接的数据
+    generation: u64,
+}
+
+#[derive(Debug)]
+pub struct Cluster {
+    config: ClusterConfig,
+    local_rooms: RwLock<HashSet<String>>,
+    remote_nodes: RwLock<HashMap<u32, RemoteNode>>,
+    event_tx: broadcast::Sender<ClusterMessage>,
+    generation: AtomicU64,
+}
+
+impl Cluster {
+    //监听节点端口并连接所有peers
+    pub async fn start(config: ClusterConfig) -> anyhow::Result<Arc<Cluster>> {
+        let listener = TcpListener::bind(&config.listen_addr).await?;
+        Ok(Self::start_with_listener(config, listener))
+    }
+
+    //测试中先绑定好端口再互相配置peers
+    pub fn start_with_listener(config: ClusterConfig, listener: TcpListener) -> Arc<Cluster> {
+        if config.secret.is_empty() {
+            warn!("未配置cluster.secret,任何人都可以加入集群");
+        }
+        info!(
+            "集群节点{}正在监听{},玩家地址{}",
+            config.node_id, config.listen_addr, config.public_addr
+        );
+
+        let (event_tx, _) = broadcast::channel(1024);
+        let cluster = Arc::new(Cluster {
+            config,
+            local_rooms: RwLock::new(HashSet::new()),
+            remote_nodes: RwLock::new(HashMap::new()),
+            event_tx,
+            generation: AtomicU64::new(0),
+        });
+
+        let accept_cluster = cluster.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, addr)) => {
+                        let cluster = accept_cluster.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = cluster.handle_peer(stream).await {
+                                warn!(peer:% = addr; "集群节点连接断开:{}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("接受集群节点连接失败:{}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+        });
+
+        for peer in cluster.config.peers.clone() {
+            let cluster = cluster.clone();
+            tokio::spawn(async move {
+                loop {
+                    match TcpStream::connect(&peer).await {
+                        Ok(stream) => {
+                            info!(peer = peer.as_str(); "已连接集群节点");
+                            if let Err(e) = cluster.push_to_peer(stream).await {
+                                warn!(peer = peer.as_str(); "与集群节点的连接断开:{}", e);
+                            }
+                        }
+                        Err(e) => debug!(peer = peer.as_str(); "连接集群节点失败:{}", e),
+                    }
+                    tokio::time::sleep(RECONNECT_INTERVAL).await;
+                }
+            });
+        }
+
+        cluster
+    }
+
+    pub fn node_id(&self) -> u32 {
+        self.config.node_id
+    }
+
+    pub fn reconnect_packet(&self) -> bool {
+        self.config.reconnect_packet
+    }
+
+    pub fn room_added(&self, id: &str) {
+        self.local_rooms
+            .write()
+            .expect("write local rooms error")
+            .insert(id.to_string());
+        let _ = self
+            .event_tx
+            .send(ClusterMessage::RoomAdded { id: id.to_string() });
+    }
+
+    pub fn room_removed(&self, id: &str) {
+        self.local_rooms
+            .write()
+            .expect("write local rooms error")
+            .remove(id);
+        let _ = self
+            .event_tx
+            .send(ClusterMessage::RoomRemoved { id: id.to_string() });
+    }
+
+    //在其他节点上查找房间,id带S前缀
+    pub fn find_room(&self, id: &str) -> Option<RemoteRoom> {
+        self.remote_nodes
+            .read()
+            .expect("read remote nodes error")
+            .iter()
+            .find(|(_, node)| node.rooms.contains(id))
+            .map(|(node_id, node)| RemoteRoom {
+                id: id.to_string(),
+                node_id: *node_id,
+                public_addr: node.public_addr.clone(),
+            })
+    }
+
+    pub fn remote_room_list(&self) -> Vec<RemoteRoom> {
+        self.remote_nodes
+            .read()
+            .expect("read remote nodes error")
+            .iter()
+            .flat_map(|(node_id, node)| {
+                node.rooms.iter().map(|id| RemoteRoom {
+                    id: id.clone(),
+                    node_id: *node_id,
+                    public_addr: node.public_addr.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn snapshot(&self) -> ClusterMessage {
+        ClusterMessage::Snapshot {
+            rooms: self
+                .local_rooms
+                .read()
+                .expect("read local rooms error")
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    //向其他节点推送本节点的房间变化
+    async fn push_to_peer(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        //先订阅再发送快照,避免中间的变化丢失
+        let mut event_rx = self.event_tx.subscribe();
+
+        write_message(
+            &mut stream,
+            &ClusterMessage::Hello {
+                node_id: self.config.node_id,
+                secret: self.config.secret.clone(),
+                public_addr: self.config.public_addr.clone(),
+            },
+        )
+        .await?;
+        write_message(&mut stream, &self.snapshot()).await?;
+
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        loop {
+            let message = tokio::select! {
+                event = event_rx.recv() => match event {
+                    Ok(message) => message,
+                    //消息积压被丢弃时重新发送完整的房间列表
+                    Err(broadcast::error::RecvError::Lagged(_)) => self.snapshot(),
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+                _ = ping.tick() => ClusterMessage::Ping,
+            };
+            write_message(&mut stream, &message).await?;
+        }
+    }
+
+    //接收其他节点推送的房间
+    async fn handle_peer(&self, stream: TcpStream) -> anyhow::Result<()> {
+        let mut reader = BufReader::new(stream);
+
+        let Some(ClusterMessage::Hello {
+            node_id,
+            secret,
+            public_addr,
+        }) = tokio::time::timeout(PEER_TIMEOUT, read_message(&mut reader)).await??
+        else {
+            anyhow::bail!("expected hello");
+        };
+        if !bool::from(secret.as_bytes().ct_eq(self.config.secret.as_bytes())) {
+            anyhow::bail!("node {} secret mismatch", node_id);
+        }
+        if node_id == self.config.node_id {
+            anyhow::bail!("node {} has the same node_id as this node", node_id);
+        }
+
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        self.remote_nodes
+            .write()
+            .expect("write remote nodes error")
+            .insert(
+                node_id,
+                RemoteNode {
+                    public_addr,
+                    rooms: HashSet::new(),
+                    generation,
+                },
+            );
+        info!(node_id; "集群节点已加入");
+
+        let result = self.recv_from_peer(&mut reader, node_id, generation).await;
+
+        let mut remote_nodes = self.remote_nodes.write().expect("write remote nodes error");
+        if remote_nodes
+            .get(&node_id)
+            .is_some_and(|node| node.generation == generation)
+        {
+            remote_nodes.remove(&node_id);
+            info!(node_id; "集群节点已离开");
+        }
+        result
+    }
+
+    async fn recv_from_peer(
+        &self,
+        reader: &mut BufReader<TcpStream>,
+        node_id: u32,
+        generation: u64,
+    ) -> anyhow::Result<()> {
+        loop {
+            let Some(message) = tokio::time::timeout(PEER_TIMEOUT, read_message(reader)).await??
+            else {
+                return Ok(());
+            };
+
+            let mut remote_nodes = self.remote_nodes.write().expect("write remote nodes error");
+            let Some(node) = remote_nodes
+                .get_mut(&node_id)
+                .filter(|node| node.generation == generation)
+            else {
+                return Ok(());
+            };
+            match message {
+                ClusterMessage::Snapshot { rooms } => node.rooms = rooms.into_iter().collect(),
+                ClusterMessage::RoomAdded { id } => {
+                    node.rooms.insert(id);
+                }
+                ClusterMessage::RoomRemoved { id } => {
+                    node.rooms.remove(&id);
+                }
+                ClusterMessage::Hello { .. } | ClusterMessage::Ping => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start_node(
+        node_id: u32,
+        secret: &str,
+        listener: TcpListener,
+        peers: Vec<String>,
+    ) -> Arc<Cluster> {
+        let config = ClusterConfig {
+            enable: true,
+            node_id,
+            listen_addr: listener.local_addr().unwrap().to_string(),
+            public_addr: format!("node{}:5123", node_id),
+            peers,
+            secret: secret.to_string(),
+            reconnect_packet: false,
+        };
+        Cluster::start_with_listener(config, listener)
+    }
+
+    //等待其他节点推送的房间变化
+    async fn wait_until(f: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !f() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("wait cluster timeout");
+    }
+
+    #[test]
+    fn advertise_and_remove_rooms() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let listener_a = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_b = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_c = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr_a = listener_a.local_addr().unwrap().to_string();
+            let addr_b = listener_b.local_addr().unwrap().to_string();
+
+            let a = start_node(1, "secret", listener_a, vec![addr_b.clone()]).await;
+            let b = start_node(2, "secret", listener_b, vec![addr_a]).await;
+            //密钥错误的节点不能加入集群
+            let c = start_node(3, "wrong", listener_c, vec![addr_b]).await;
+
+            a.room_added("S10001");
+            c.room_added("S30001");
+            wait_until(|| b.find_room("S10001").is_some()).await;
+            let room = b.find_room("S10001").unwrap();
+            assert_eq!(room.node_id, 1);
+            assert_eq!(room.public_addr, "node1:5123");
+            assert!(a.find_room("S10001").is_none());
+
+            b.room_added("S20001");
+            wait_until(|| a.find_room("S20001").is_some()).await;
+            assert_eq!(a.remote_room_list().len(), 1);
+
+            a.room_removed("S10001");
+            wait_until(|| b.find_room("S10001").is_none()).await;
+            assert_eq!(b.remote_room_list().len(), 0);
+            assert!(b.find_room("S30001").is_none());
+        });
+    }
+}
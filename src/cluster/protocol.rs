@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+//单条消息的最大长度,防止未认证的连接发送超长数据
+const MAX_MESSAGE_LENGTH: u64 = 1024 * 1024;
+
+//节点之间的消息,每条消息是一行JSON
+//每个节点主动连接所有peers,只通过自己发起的连接推送本节点的房间
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClusterMessage {
+    Hello {
+        node_id: u32,
+        secret: String,
+        public_addr: String,
+    },
+    //本节点当前的全部房间,连接建立后和消息丢失后发送
+    Snapshot {
+        rooms: Vec<String>,
+    },
+    RoomAdded {
+        id: String,
+    },
+    RoomRemoved {
+        id: String,
+    },
+    Ping,
+}
+
+pub async fn read_message<R>(reader: &mut R) -> anyhow::Result<Option<ClusterMessage>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = String::new();
+    if reader.take(MAX_MESSAGE_LENGTH).read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        anyhow::bail!("cluster message too long");
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+pub async fn write_message<W>(writer: &mut W, message: &ClusterMessage) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
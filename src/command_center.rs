@@ -1,88 +1,100 @@
-use std::{io::Read, sync::Arc};
+use std::sync::Arc;
 
+use log::{info, warn};
+use tokio::io::{AsyncBufReadExt, BufReader};
 
 use crate::{
-    connection_manager::ConnectionManager, event::EVENT_CHANNEL_MULTIPLE, uplist::Uplist
+    connection_manager::ConnectionManager, data::COMMAND_HELP, module::MODULE_MANAGER,
+    server::reload::reload_config,
 };
 
 pub async fn command_center(shared_connection_mg: Arc<ConnectionManager>) {
-    let event_receiver = EVENT_CHANNEL_MULTIPLE.1.resubscribe();
-    let _uplsit = Uplist::new(
-        "false",
-        "RW-Relay-RJR",
-        5123,
-        1,
-        100,
-        "RW-RJR",
-        "shishanyue",
-        "192.168.80.1",
-        "开了",
-    );
-    //MODULE_MANAGER
-    //    .write()
-    //    .unwrap()
-    //    .init_module(ModuleType::RwEngine);
+    let mut std_in = BufReader::new(tokio::io::stdin()).lines();
 
-    let mut std_in = std::io::stdin();
-    let mut admin_command = String::new();
-
-    
     loop {
-        std_in.read_to_string(&mut admin_command).unwrap();
-
-
-
-        admin_command.clear();
-    }
-
-    //loop {
-        /*
-        match event_receiver.recv().await {
-            Ok(event) => match event.event_type {
-                EventType::NewPacket(_, mut packet, PacketType::CHAT_RECEIVE) => {
-                    let message = packet.read_string().await.unwrap();
-
-                    let mut arg = message.split_whitespace();
-                    
-                    if arg.next() == Some("RwEnigne"){
-                        let packet = SuperPacket::set_terrain(arg.next().unwrap().parse().unwrap(), arg.next().unwrap().parse().unwrap(), "1").await;
-
-                        shared_connection_mg
-                            .send_packet_to_player_by(By::Addr(admin_command.clone()), packet)
-                            .await;
-                    }
+        let admin_command = match std_in.next_line().await {
+            Ok(Some(admin_command)) => admin_command,
+            //stdin被关闭时(如后台运行)不再读取命令
+            Ok(None) => break,
+            Err(e) => {
+                warn!("读取命令失败:{}", e);
+                break;
+            }
+        };
 
+        match admin_command.trim() {
+            "" => {}
+            "/help" => info!("{}", COMMAND_HELP),
+            "/list player" => {
+                for shared_con in shared_connection_mg.get_connection_list().await {
+                    let connection_info = &shared_con.shared_data.connection_info;
+                    let player_info = &shared_con.shared_data.player_info;
+                    info!(
+                        "{} {} {:?}",
+                        connection_info
+                            .addr
+                            .upgrade()
+                            .map(|addr| addr.to_string())
+                            .unwrap_or_default(),
+                        player_info.player_name.read().expect("read player name error"),
+                        player_info.room_id.read().expect("read room id error")
+                    );
                 }
-                EventType::NewPacket(_, packet, PacketType::CHAT) => {
+            }
+            "/list room" => {
+                for shared_relay in shared_connection_mg.get_relay_list().await {
+                    let (_, player_list) = shared_relay.get_room_member().await;
+                    info!(
+                        "{} 玩家数:{}",
+                        shared_relay.shared_data.id,
+                        player_list.len()
+                    );
                 }
-                _ => {}
-            },
-            Err(e) => panic!("{}", e),
-        };
-        
-         */
-        
-
-        /*
-        std_in.read_line(&mut admin_command).unwrap();
-        let admin_command = admin_command.trim().to_string();
-
-        for ten1 in 1..20 {
-            let packet = SuperPacket::set_terrain(ten1 as f32 * 20. + 20., 500., "1").await;
-
-            shared_connection_mg
-                .send_packet_to_player_by(By::Addr(admin_command.clone()), packet)
-                .await;
+            }
+            "/player size" => info!(
+                "玩家总数:{}",
+                shared_connection_mg.get_connection_list().await.len()
+            ),
+            "/room size" => info!(
+                "房间总数:{}",
+                shared_connection_mg.get_relay_list().await.len()
+            ),
+            "/reload" => {
+                if let Err(e) = reload_config(&shared_connection_mg).await {
+                    warn!("重新加载配置失败,继续使用原配置:\n{}", e);
+                }
+            }
+            command if command.starts_with("/module") => module_command(command),
+            command => warn!("未知命令:{},输入/help查看帮助", command),
         }
+    }
 
-        for ten2 in 1..20 {
-            let packet = SuperPacket::set_terrain(100., ten2 as f32 * 20. + 200., "1").await;
+    //防止stdin关闭后主线程退出
+    std::future::pending::<()>().await;
+}
 
-            shared_connection_mg
-                .send_packet_to_player_by(By::Addr(admin_command.clone()), packet)
-                .await;
+fn module_command(command: &str) {
+    let args = command.split_whitespace().skip(1).collect::<Vec<_>>();
+    match args.as_slice() {
+        ["list"] => {
+            for (name, file) in MODULE_MANAGER.module_list() {
+                match file {
+                    Some(file) => info!("{} {}", name, file.display()),
+                    None => info!("{} 内置", name),
+                }
+            }
+        }
+        ["load", name] => {
+            if let Err(e) = MODULE_MANAGER.load_by_name(name) {
+                warn!("加载模块{}失败:{}", name, e);
+            }
         }
-         */
-    //}
-    //std::thread::sleep(Duration::from_millis(u64::MAX));
+        ["unload", name] => {
+            if let Err(e) = MODULE_MANAGER.unload(name) {
+                warn!("卸载模块{}失败:{}", name, e);
+            }
+        }
+        ["reload"] => info!("已重新加载{}个WASM模块", MODULE_MANAGER.reload_dir()),
+        _ => warn!("未知命令:{},输入/help查看帮助", command),
+    }
 }
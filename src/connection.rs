@@ -1,5 +1,6 @@
 pub mod permission_status;
 pub mod player_net_api;
+pub mod relay_hall;
 pub mod shared_connection;
 
 use std::{
@@ -11,25 +12,36 @@ use std::{
     },
 };
 
+use log::{debug, info};
 use tokio::{
-    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
     runtime::Runtime,
     sync::{broadcast, mpsc, oneshot, Semaphore},
 };
 use uuid::Uuid;
 
 use crate::{
+    cluster::RemoteRoom,
     connection_manager::By,
     core::ServerCommand,
     event::{Event, EventType, EVENT_CHANNEL},
-    packet::{Packet, PacketReadWriteExt, PacketType},
+    module::MODULE_MANAGER,
+    packet::{
+        message::{
+            Chat, ForwardClientAdd, HeartBeat, HeartBeatResponse, Kick, PacketForwardClientFrom,
+            PacketForwardClientTo, PreregisterInfo, ReconnectTo, RelayBecomeServer,
+            RelayHallCommand, RelayHallMessage, RelayVersionInfo, RELAY_VERSION,
+        },
+        Packet, PacketType,
+    },
     relay_manager::{relay::SharedRelayRoom, SharedRelayManager},
+    server::reload::live_config,
     worker_pool::{processor::ProcesseorData},
 };
 
 use self::{
     permission_status::PermissionStatus,
     player_net_api::{CustomRelayData, RelayDirectInspection},
+    relay_hall::{room_line, HallCommand, HALL_HELP, HALL_LIST_LIMIT},
     shared_connection::{SharedConnection, SharedConnectionData},
 };
 
@@ -39,6 +51,11 @@ static NEW_RELAY_PROTOCOL_VERSION: u32 = 172;
 pub struct PlayerInfo {
     pub permission_status: Arc<RwLock<PermissionStatus>>,
     pub player_name: Arc<RwLock<String>>,
+    pub room_id: Arc<RwLock<Option<String>>>,
+    //玩家通过/afk标记的暂离状态
+    pub afk: Arc<AtomicBool>,
+    //服务器模块加入房间的假人,不计入房间人数
+    pub is_dummy: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Default)]
@@ -46,6 +63,8 @@ pub struct ConnectionInfo {
     pub addr: sync::Weak<SocketAddr>,
     pub client_version: Arc<AtomicU32>,
     pub is_beta_version: Arc<AtomicBool>,
+    //断开后SharedConnection可能还被其他地方持有,需要用这个判断
+    pub disconnected: Arc<AtomicBool>,
 }
 
 #[derive(Debug)]
@@ -67,6 +86,7 @@ pub enum ConnectionAPI {
     SetRoomIndex(Option<u32>),
     SendRelayServerInfo,
     RelayDirectInspection(oneshot::Sender<Option<RelayDirectInspection>>),
+    GetRelayRoom(oneshot::Sender<Option<Arc<SharedRelayRoom>>>),
     SendRelayHallMessage(String),
     SendRelayServerTypeReply,
     GetPingData,
@@ -74,6 +94,8 @@ pub enum ConnectionAPI {
     SendPacketToHostRaw(Packet),
     SendPacketToOthers(Packet),
     AddRelayConnect,
+    SendSystemMessage(String),
+    Kick(String),
 }
 
 #[derive(Debug)]
@@ -94,6 +116,8 @@ pub enum ConnectionLibAPI {
     InsertConnection(Arc<SharedConnection>),
     RemoveConnectionBy(By),
     SendPacketToPlayerBy(By, Packet),
+    GetConnectionBy(By, oneshot::Sender<Option<Arc<SharedConnection>>>),
+    GetConnectionList(oneshot::Sender<Vec<Arc<SharedConnection>>>),
 }
 
 impl ConnectionChannel {
@@ -147,28 +171,31 @@ impl Connection {
     }
 
     pub async fn send_relay_server_info(&self) {
-        let mut packet = Packet::new(PacketType::RELAY_VERSION_INFO).await;
-        packet.write_u8(0).await.unwrap();
-        packet.write_u32(151).await.unwrap();
-        packet.write_u32(1).await.unwrap();
-        packet.write_u8(0).await.unwrap();
+        let packet = Packet::from_message(&RelayVersionInfo {
+            version: RELAY_VERSION,
+        });
         self.shared_con.as_ref().unwrap().send_packet(packet).await;
     }
 
-    pub async fn send_packet_to_others(&self, mut packet: Packet) {
-        let index = packet.packet_buffer.read_u32().await.unwrap();
-        let packet_type = packet.packet_buffer.read_u32().await.unwrap();
-
-        let bytes = packet.read_stream_bytes().await;
+    pub async fn send_packet_to_others(&self, packet: Packet) {
+        let message = match packet.to_message::<PacketForwardClientTo>() {
+            Ok(message) => message,
+            Err(e) => {
+                debug!(addr:% = self.addr; "无法解析房主转发的数据包:{}", e);
+                return;
+            }
+        };
+        let packet_type = message.packet_type;
 
         if packet_type == PacketType::DISCONNECT as u32 {
             return;
         }
 
-        let mut send_packet =
-            Packet::new(PacketType::try_from(packet_type).unwrap_or_default()).await;
-
-        send_packet.write_all(&bytes).await.unwrap();
+        //只增加引用计数,不复制数据
+        let send_packet = Packet::new(
+            PacketType::try_from(packet_type).unwrap_or_default(),
+            message.payload,
+        );
 
         if packet_type == PacketType::KICK as u32 {
             // TODO
@@ -180,39 +207,15 @@ impl Connection {
         self.shared_relay_room
             .as_ref()
             .expect("room is None")
-            .send_packet_to_others(index, send_packet)
+            .send_packet_to_others(message.index, send_packet)
             .await;
     }
 
     pub async fn send_packet_to_host(&self, packet: Packet) {
-        let mut send_packet = Packet::new(PacketType::PACKET_FORWARD_CLIENT_FROM).await;
-
-        send_packet
-            .packet_buffer
-            .write_u32(*self.room_index.as_ref().unwrap())
-            .await
-            .unwrap();
-        send_packet
-            .packet_buffer
-            .write_u32(packet.packet_length + 8)
-            .await
-            .unwrap();
-        send_packet
-            .packet_buffer
-            .write_u32(packet.packet_length)
-            .await
-            .unwrap();
-
-        send_packet
-            .packet_buffer
-            .write_u32(packet.packet_type as u32)
-            .await
-            .unwrap();
-        send_packet
-            .packet_buffer
-            .write_all(&packet.packet_buffer.into_inner())
-            .await
-            .unwrap();
+        let send_packet = Packet::from_message(&PacketForwardClientFrom::new(
+            *self.room_index.as_ref().unwrap(),
+            &packet,
+        ));
 
         self.shared_relay_room
             .as_ref()
@@ -222,90 +225,53 @@ impl Connection {
     }
 
     pub async fn get_ping_data(&mut self) {
-        let mut packet = Packet::new(PacketType::HEART_BEAT_RESPONSE).await;
-        packet
-            .packet_buffer
-            .write_u64(
-                self.packet
-                    .take()
-                    .unwrap()
-                    .packet_buffer
-                    .read_u64()
-                    .await
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
-        packet.write_u8(1).await.unwrap();
-        packet.write_u8(60).await.unwrap();
+        let Ok(heart_beat) = self.packet.take().unwrap().to_message::<HeartBeat>() else {
+            return;
+        };
+        let packet = Packet::from_message(&HeartBeatResponse {
+            time: heart_beat.time,
+        });
 
         self.shared_con.as_ref().unwrap().send_packet(packet).await;
     }
 
     pub async fn relay_direct_inspection(&self) -> Option<RelayDirectInspection> {
-        let mut cache_packet = self.cache_packet.clone().unwrap();
-        if cache_packet.packet_length != 0 {
-            cache_packet.read_string().await.unwrap();
-
-            let get_beta_version = |version: u32| (152..=175).contains(&version);
-
-            let packet_version = cache_packet.packet_buffer.read_u32().await.unwrap();
-            let client_version = cache_packet.packet_buffer.read_u32().await.unwrap();
-
-            if packet_version >= 1 {
-                cache_packet
-                    .packet_buffer
-                    .seek(std::io::SeekFrom::Current(4))
-                    .await
-                    .unwrap();
+        let cache_packet = self.cache_packet.as_ref().unwrap();
+        if cache_packet.payload.is_empty() {
+            return None;
+        }
+        let info = match cache_packet.to_message::<PreregisterInfo>() {
+            Ok(info) => info,
+            Err(e) => {
+                debug!(addr:% = self.addr; "无法解析客户端信息:{}", e);
+                return None;
             }
+        };
 
-            let query_string = if packet_version >= 2 {
-                cache_packet.read_if_is_string().await
-            } else {
-                None
-            };
-
-            let player_name = if packet_version >= 3 {
-                cache_packet.read_string().await
-            } else {
-                None
-            };
-
-            cache_packet.packet_buffer.read_u32().await.unwrap();
-
-            Some(RelayDirectInspection {
-                client_version,
-                is_beta_version: get_beta_version(client_version),
-                query_string,
-                player_name,
-            })
-        } else {
-            None
-        }
+        let get_beta_version = |version: u32| (152..=175).contains(&version);
+
+        Some(RelayDirectInspection {
+            client_version: info.client_version,
+            is_beta_version: get_beta_version(info.client_version),
+            query_string: info.query_string,
+            player_name: info.player_name,
+        })
     }
 
     pub async fn send_relay_hall_message(&self, msg: &str) {
-        let mut packet = Packet::new(PacketType::RELAY_117).await;
-        packet.write_u8(1).await.unwrap();
-        packet.write_u32(5).await.unwrap();
-
-        packet.write_string(msg).await.unwrap();
+        let packet = Packet::from_message(&RelayHallMessage {
+            message: msg.to_string(),
+        });
 
         self.shared_con.as_ref().unwrap().send_packet(packet).await;
     }
 
     pub async fn chat_message_packet_internal(&mut self, sender: &str, msg: &str, team: u32) {
-        let mut packet = Packet::new(PacketType::CHAT).await;
-
-        packet.write_string(msg).await.unwrap();
-
-        packet.write_u8(3).await.unwrap();
-
-        packet.write_is_string(sender).await.unwrap();
-
-        packet.write_u32(team).await.unwrap();
-        packet.write_u32(team).await.unwrap();
+        let packet = Packet::from_message(&Chat {
+            message: msg.to_string(),
+            sender: Some(sender.to_string()).filter(|sender| !sender.is_empty()),
+            team,
+        });
 
         self.shared_con.as_ref().unwrap().send_packet(packet).await;
     }
@@ -319,35 +285,20 @@ impl Connection {
             .write()
             .expect("write permission status error") = PermissionStatus::PlayerPermission;
 
-        let mut packet = Packet::new(PacketType::FORWARD_CLIENT_ADD).await;
-
-        if shared_data
+        let packet = if shared_data
             .connection_info
             .client_version
             .load(Ordering::Relaxed)
             >= NEW_RELAY_PROTOCOL_VERSION
         {
-            packet.write_u8(1).await.unwrap();
-            packet
-                .packet_buffer
-                .write_u32(*self.room_index.as_ref().unwrap())
-                .await
-                .unwrap();
-
-            packet
-                .write_string(&Uuid::new_v4().to_string())
-                .await
-                .expect("write packet error");
-
-            packet.write_u8(0).await.unwrap();
-
-            packet
-                .write_is_string(&self.addr.ip().to_string())
-                .await
-                .expect("write packet error");
+            Packet::from_message(&ForwardClientAdd {
+                index: *self.room_index.as_ref().unwrap(),
+                uuid: Uuid::new_v4().to_string(),
+                ip: Some(self.addr.ip().to_string()),
+            })
         } else {
             todo!()
-        }
+        };
 
         self.shared_con
             .as_ref()
@@ -360,56 +311,66 @@ impl Connection {
             .send_packet_to_host(self.cache_packet.clone().expect("cache packet error"))
             .await;
 
-        self.chat_message_packet_internal("RJR Server:", "欢迎", 5)
+        self.chat_message_packet_internal("RJR Server:", &live_config().message.room_welcome, 5)
             .await;
     }
 
     pub async fn send_relay_server_type_reply(&mut self) {
         let shared_data = self.shared_con.as_ref().unwrap().shared_data.as_ref();
 
-        let mut packet = self.packet.take().unwrap();
-        //跳过无用的一个byte和一个int32
-        packet.packet_buffer.set_position(5);
-
-        let player_command = packet.read_string().await.unwrap();
-
-        if player_command.is_empty() {
-            self.send_relay_hall_message("你还什么都没输呢").await
-        } else {
-            if let Some(id) = player_command.strip_prefix('S') {
-                match self.shared_relay_mg.get_relay(id).await {
-                    Some(shared_relay) => {
-                        shared_relay
-                            .add_relay_player(self.shared_con.clone().unwrap())
-                            .await;
-
-                        self.shared_relay_room = Some(shared_relay);
-                    }
-                    None => {
-                        self.send_relay_hall_message(&format!("{}此房间不存在", player_command))
-                            .await
-                    }
-                }
+        let player_command = match self.packet.take().unwrap().to_message::<RelayHallCommand>() {
+            Ok(message) => message.command,
+            Err(e) => {
+                debug!(addr:% = self.addr; "无法解析大厅命令:{}", e);
                 return;
             }
+        };
 
-            let uplist = false;
-            let mut mods = false;
-            let mut new_room = false;
-
-            if player_command.starts_with("new") || player_command.starts_with("news") {
-                new_room = true;
-            } else if player_command.starts_with("mod") || player_command.starts_with("mods") {
-                new_room = true;
-                mods = true;
-            } else {
-                self.send_relay_hall_message("不懂").await
+        let command = match HallCommand::parse(&player_command) {
+            Ok(command) => command,
+            Err(reply) => {
+                self.send_relay_hall_message(&reply).await;
+                return;
             }
+        };
 
-            if new_room {
-                let custom = CustomRelayData::new(
-                    mods,
-                    uplist,
+        //旧版本的relay协议还不支持,加入或创建房间时会出错
+        if matches!(command, HallCommand::Join(_) | HallCommand::New(_))
+            && shared_data
+                .connection_info
+                .client_version
+                .load(Ordering::Relaxed)
+                < NEW_RELAY_PROTOCOL_VERSION
+        {
+            self.send_relay_hall_message("客户端版本过低,请升级游戏后再加入或创建房间")
+                .await;
+            return;
+        }
+
+        match command {
+            HallCommand::Empty => self.send_relay_hall_message("你还什么都没输呢").await,
+            HallCommand::Help => self.send_relay_hall_message(HALL_HELP).await,
+            HallCommand::List => {
+                let list = self.hall_room_list().await;
+                self.send_relay_hall_message(&list).await
+            }
+            HallCommand::Stats => {
+                let stats = self.hall_stats().await;
+                self.send_relay_hall_message(&stats).await
+            }
+            HallCommand::Motd => {
+                let motd = live_config().message.motd.clone();
+                if motd.is_empty() {
+                    self.send_relay_hall_message("暂无公告").await
+                } else {
+                    self.send_relay_hall_message(&motd).await
+                }
+            }
+            HallCommand::Join(id) => self.join_relay_room(id).await,
+            HallCommand::New(options) => {
+                let mut custom = CustomRelayData::new(
+                    options.mods,
+                    false,
                     shared_data
                         .connection_info
                         .is_beta_version
@@ -419,6 +380,10 @@ impl Connection {
                         .client_version
                         .load(Ordering::Relaxed),
                 );
+                if let Some(max_player) = options.max_player {
+                    custom.max_player_size = max_player;
+                }
+                custom.rw_engine = options.engine;
                 let new_shared_room = self
                     .shared_relay_mg
                     .new_relay_id(self.shared_con.clone().unwrap(), None, custom)
@@ -428,6 +393,143 @@ impl Connection {
 
                 self.send_relay_server_id().await;
             }
+            HallCommand::Unknown(command) => {
+                self.send_relay_hall_message(&format!("未知命令:{}\n{}", command, HALL_HELP))
+                    .await
+            }
+        }
+    }
+
+    //id不带S前缀
+    async fn join_relay_room(&mut self, id: &str) {
+        let shared_data = self.shared_con.as_ref().unwrap().shared_data.as_ref();
+        match self.shared_relay_mg.get_relay(id).await {
+            Some(shared_relay) => {
+                let (admin, player_list) = shared_relay.get_room_member().await;
+                if !is_host_alive(&admin) {
+                    self.send_relay_hall_message(&format!("S{}此房间不存在", id))
+                        .await;
+                    return;
+                }
+                if room_player_count(&player_list) as i32
+                    >= shared_relay.shared_data.custom.max_player_size
+                {
+                    self.send_relay_hall_message(&format!("房间S{}已满", id))
+                        .await;
+                    return;
+                }
+                shared_relay
+                    .add_relay_player(self.shared_con.clone().unwrap())
+                    .await;
+
+                *shared_data
+                    .player_info
+                    .room_id
+                    .write()
+                    .expect("write room id error") = Some(shared_relay.shared_data.id.clone());
+
+                info!(
+                    addr:% = self.addr, room_id = shared_relay.shared_data.id.as_str();
+                    "玩家加入房间"
+                );
+
+                self.shared_relay_room = Some(shared_relay);
+            }
+            None => match self.shared_relay_mg.find_remote_relay(id) {
+                Some(remote_room) => self.redirect_to_remote_relay(&remote_room).await,
+                None => {
+                    self.send_relay_hall_message(&format!("S{}此房间不存在", id))
+                        .await
+                }
+            },
+        }
+    }
+
+    //等待中(未开始游戏)的房间,房主已经离开的房间不显示
+    async fn hall_room_list(&self) -> String {
+        let mut waiting = Vec::new();
+        for room in self.shared_relay_mg.get_relay_list().await {
+            if room.shared_data.start_game.load(Ordering::Relaxed) {
+                continue;
+            }
+            let (admin, player_list) = room.get_room_member().await;
+            if let Some(admin) = admin.filter(|admin| !admin.is_disconnected()) {
+                waiting.push((room, admin, room_player_count(&player_list)));
+            }
+        }
+        if waiting.is_empty() {
+            return "当前没有等待中的房间,输入new创建房间".to_string();
+        }
+        waiting.sort_by(|(a, ..), (b, ..)| a.shared_data.id.cmp(&b.shared_data.id));
+
+        let mut lines = vec![format!("等待中的房间({}):", waiting.len())];
+        for (room, admin, player_count) in waiting.iter().take(HALL_LIST_LIMIT) {
+            let host = admin
+                .shared_data
+                .player_info
+                .player_name
+                .read()
+                .expect("read player name error")
+                .clone();
+            let custom = &room.shared_data.custom;
+            lines.push(room_line(
+                &room.shared_data.id,
+                &host,
+                *player_count,
+                custom.max_player_size,
+                custom.mods,
+            ));
+        }
+        if waiting.len() > HALL_LIST_LIMIT {
+            lines.push(format!("...还有{}个房间", waiting.len() - HALL_LIST_LIMIT));
+        }
+        lines.push("输入join <房间号>加入房间".to_string());
+        lines.join("\n")
+    }
+
+    async fn hall_stats(&self) -> String {
+        let room_list = self.shared_relay_mg.get_relay_list().await;
+        let playing = room_list
+            .iter()
+            .filter(|room| room.shared_data.start_game.load(Ordering::Relaxed))
+            .count();
+        let (con_list_tx, con_list_rx) = oneshot::channel();
+        self.con_lib_api_tx
+            .send(ConnectionLibAPI::GetConnectionList(con_list_tx))
+            .await
+            .expect("send con_lib_api error");
+        let connections = con_list_rx.await.expect("recv connection list error").len();
+        format!(
+            "在线玩家:{}\n房间总数:{}\n游戏中:{}\n等待中:{}",
+            connections,
+            room_list.len(),
+            playing,
+            room_list.len() - playing
+        )
+    }
+
+    //房间在集群的其他节点上,告诉玩家正确的地址
+    pub async fn redirect_to_remote_relay(&self, remote_room: &RemoteRoom) {
+        info!(
+            addr:% = self.addr, room_id = remote_room.id.as_str(), node_id = remote_room.node_id;
+            "玩家被引导到其他节点"
+        );
+        self.send_relay_hall_message(&format!(
+            "房间{}在另一台服务器上,请连接{}后输入{}",
+            remote_room.id, remote_room.public_addr, remote_room.id
+        ))
+        .await;
+
+        if self
+            .shared_relay_mg
+            .cluster
+            .as_ref()
+            .is_some_and(|cluster| cluster.reconnect_packet())
+        {
+            let packet = Packet::from_message(&ReconnectTo {
+                addr: remote_room.public_addr.clone(),
+            });
+            self.shared_con.as_ref().unwrap().send_packet(packet).await;
         }
     }
 
@@ -443,49 +545,36 @@ impl Connection {
             .write()
             .expect("write permission status error") = PermissionStatus::HostPermission;
 
-        let mut packet = Packet::new(PacketType::RELAY_BECOME_SERVER).await;
-
-        let public = false;
-
-        if shared_relay_room.shared_data.custom.version >= NEW_RELAY_PROTOCOL_VERSION {
-            packet.write_u8(2).await.unwrap();
-
-            packet.write_u8(1).await.unwrap();
-            packet.write_u8(1).await.unwrap();
-            packet.write_u8(1).await.unwrap();
+        *shared_data
+            .player_info
+            .room_id
+            .write()
+            .expect("write room id error") = Some(shared_relay_room.shared_data.id.clone());
 
-            packet
-                .write_string("RJR Team")
-                .await
-                .expect("write packet error");
+        info!(
+            addr:% = self.addr, room_id = shared_relay_room.shared_data.id.as_str();
+            "房间已创建"
+        );
 
-            packet
-                .packet_buffer
-                .write_u8(shared_relay_room.shared_data.custom.mods as u8)
-                .await
-                .unwrap();
-
-            packet.write_u8(public as u8).await.unwrap();
-            packet.write_u8(1).await.unwrap();
+        let public = false;
 
-            packet
-                .write_string(&format!(
+        let packet = if shared_relay_room.shared_data.custom.version >= NEW_RELAY_PROTOCOL_VERSION {
+            Packet::from_message(&RelayBecomeServer {
+                team: "RJR Team".to_string(),
+                mods: shared_relay_room.shared_data.custom.mods,
+                public,
+                description: format!(
                     "{{RW-RJR Relay}}.Room ID : {}",
                     shared_relay_room.shared_data.id
-                ))
-                .await
-                .expect("write packet error");
-
-            packet.write_u8(public as u8).await.unwrap();
-
-            packet
-                .write_is_string(&Uuid::new_v4().to_string())
-                .await
-                .expect("write packet error");
+                ),
+                uuid: Some(Uuid::new_v4().to_string()),
+            })
         } else {
             todo!()
-        }
+        };
 
+        //在房主收到房间号之前通知模块,模块可以在玩家加入前做好准备
+        MODULE_MANAGER.on_room_created(shared_relay_room);
         self.shared_con.as_ref().unwrap().send_packet(packet).await;
 
         EVENT_CHANNEL
@@ -497,9 +586,58 @@ impl Connection {
             .await.expect("send event error");
     }
 
+    pub async fn send_system_message(&mut self, msg: &str) {
+        let Some(shared_con) = self.shared_con.as_ref() else {
+            return;
+        };
+        let permission_status = *shared_con
+            .shared_data
+            .player_info
+            .permission_status
+            .read()
+            .expect("read permission status error");
+
+        match permission_status {
+            PermissionStatus::InitialConnection => {}
+            PermissionStatus::Certified => self.send_relay_hall_message(msg).await,
+            PermissionStatus::PlayerPermission | PermissionStatus::HostPermission => {
+                self.chat_message_packet_internal("RJR Server:", msg, 5)
+                    .await
+            }
+        }
+    }
+
+    pub async fn kick(&mut self, reason: &str) {
+        let Some(shared_con) = self.shared_con.as_ref() else {
+            return;
+        };
+        let packet = Packet::from_message(&Kick {
+            reason: reason.to_string(),
+        });
+
+        shared_con.send_packet(packet).await;
+
+        info!(addr:% = self.addr; "连接被踢出:{}", reason);
+
+        self.disconnect().await;
+    }
+
     pub async fn disconnect(&mut self) {
         if (self.is_disconnected.acquire().await).is_ok() {
             self.is_disconnected.close();
+            self.shared_con
+                .as_ref()
+                .unwrap()
+                .shared_data
+                .connection_info
+                .disconnected
+                .store(true, Ordering::Relaxed);
+
+            debug!(
+                addr:% = self.addr,
+                room_id = self.shared_relay_room.as_ref().map(|room| room.shared_data.id.as_str());
+                "连接已断开"
+            );
 
             let shared_channel = self.shared_con.as_ref().unwrap().shared_channel.as_ref();
 
@@ -515,9 +653,47 @@ impl Connection {
                 .await
                 .expect("remove con error");
 
+            //房主离开后房间已经无法继续,从房间表和集群中移除并踢出其他玩家
+            //在新任务中关闭,避免房间actor正在等待本连接时互相等待
+            let is_host = matches!(
+                *self
+                    .shared_con
+                    .as_ref()
+                    .unwrap()
+                    .shared_data
+                    .player_info
+                    .permission_status
+                    .read()
+                    .expect("read permission status error"),
+                PermissionStatus::HostPermission
+            );
+            if let Some(shared_relay_room) = self.shared_relay_room.as_ref().filter(|_| is_host) {
+                let shared_relay_mg = self.shared_relay_mg.clone();
+                let id = shared_relay_room.shared_data.id.clone();
+                tokio::spawn(async move {
+                    shared_relay_mg
+                        .close_relay(id.trim_start_matches('S'), "房主已离开")
+                        .await;
+                });
+            }
+
             self.shared_con
                 .take()
                 .expect("remove shared_con error when disconnect");
         }
     }
 }
+
+//房主断开后房间会被关闭,关闭前不再显示也不能加入
+fn is_host_alive(admin: &Option<Arc<SharedConnection>>) -> bool {
+    admin.as_ref().is_some_and(|admin| !admin.is_disconnected())
+}
+
+//房间人数包括房主,不包括服务器模块的假人
+pub(crate) fn room_player_count(player_list: &[(u32, Arc<SharedConnection>)]) -> usize {
+    player_list
+        .iter()
+        .filter(|(_, con)| !con.shared_data.player_info.is_dummy.load(Ordering::Relaxed))
+        .count()
+        + 1
+}
@@ -1,5 +1,7 @@
+use serde::Serialize;
+
 #[allow(non_camel_case_types)]
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, Serialize)]
 pub enum PermissionStatus {
     #[default]
     InitialConnection = 1,
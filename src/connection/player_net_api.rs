@@ -1,3 +1,5 @@
+use crate::server::reload::live_config;
+
 #[derive(Debug)]
 pub struct RelayDirectInspection {
     pub client_version: u32,
@@ -14,18 +16,22 @@ pub struct CustomRelayData {
     pub mods: bool,
     pub beta_game_version: bool,
     pub version: u32,
+    //创建时带engine选项,RwEngine会加入
+    pub rw_engine: bool,
 }
 
 impl CustomRelayData {
     pub fn new(mods: bool, uplist: bool, beta_game_version: bool, version: u32) -> Self {
+        let game_config = &live_config().game;
         Self {
             max_player_size: -1,
-            max_unit_size: 300,
-            income: 1.0,
+            max_unit_size: game_config.max_unit_size,
+            income: game_config.income,
             mods,
             uplist,
             version,
             beta_game_version,
+            rw_engine: false,
         }
     }
 }
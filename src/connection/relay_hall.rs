@@ -0,0 +1,167 @@
+//Relay大厅中玩家输入的命令,回复显示在RELAY_117的对话框中
+
+//list最多显示的房间数
+pub static HALL_LIST_LIMIT: usize = 10;
+static MAX_PLAYER_RANGE: std::ops::RangeInclusive<i32> = 1..=100;
+
+pub static HALL_HELP: &str = "可用的命令:
+S<房间号> 或 join <房间号>  加入房间
+new [mods] [engine] [p<人数>]  创建房间,engine让RwEngine加入
+mods  创建mods房间
+list  列出等待中的房间
+stats  服务器状态
+motd  服务器公告
+help  显示本帮助";
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NewRoomOptions {
+    pub mods: bool,
+    pub engine: bool,
+    pub max_player: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HallCommand<'a> {
+    Empty,
+    Help,
+    List,
+    Stats,
+    Motd,
+    //房间号不带S前缀
+    Join(&'a str),
+    New(NewRoomOptions),
+    Unknown(&'a str),
+}
+
+impl<'a> HallCommand<'a> {
+    //命令可以带/前缀,参数错误时返回回复给玩家的消息
+    pub fn parse(command: &'a str) -> Result<Self, String> {
+        let command = command.trim();
+        let command = command.strip_prefix('/').unwrap_or(command);
+        let mut words = command.split_whitespace();
+        let Some(first) = words.next() else {
+            return Ok(Self::Empty);
+        };
+
+        Ok(match first.to_lowercase().as_str() {
+            "help" | "?" => Self::Help,
+            "list" => Self::List,
+            "stats" => Self::Stats,
+            "motd" => Self::Motd,
+            "join" => match words.next() {
+                Some(id) => Self::Join(strip_room_prefix(id)),
+                None => return Err("用法:join <房间号>".to_string()),
+            },
+            "new" | "news" => Self::New(parse_new_options(words, false)?),
+            "mod" | "mods" => Self::New(parse_new_options(words, true)?),
+            _ => match first.strip_prefix('S') {
+                Some(id) if !id.is_empty() => Self::Join(id),
+                _ => Self::Unknown(first),
+            },
+        })
+    }
+}
+
+fn strip_room_prefix(id: &str) -> &str {
+    id.strip_prefix(['S', 's']).unwrap_or(id)
+}
+
+fn parse_new_options<'a>(
+    words: impl Iterator<Item = &'a str>,
+    mods: bool,
+) -> Result<NewRoomOptions, String> {
+    let mut options = NewRoomOptions {
+        mods,
+        ..Default::default()
+    };
+    for word in words {
+        if word.eq_ignore_ascii_case("mods") || word.eq_ignore_ascii_case("mod") {
+            options.mods = true;
+            continue;
+        }
+        if word.eq_ignore_ascii_case("engine") {
+            options.engine = true;
+            continue;
+        }
+        match word
+            .strip_prefix(['p', 'P'])
+            .and_then(|size| size.parse::<i32>().ok())
+        {
+            Some(size) if MAX_PLAYER_RANGE.contains(&size) => options.max_player = Some(size),
+            Some(_) => {
+                return Err(format!(
+                    "人数必须在{}到{}之间",
+                    MAX_PLAYER_RANGE.start(),
+                    MAX_PLAYER_RANGE.end()
+                ))
+            }
+            None => return Err(format!("未知的选项:{}\n{}", word, HALL_HELP)),
+        }
+    }
+    Ok(options)
+}
+
+//list中的一行,players包括房主
+pub fn room_line(id: &str, host: &str, players: usize, max_player: i32, mods: bool) -> String {
+    format!(
+        "{} {} {}/{}{}",
+        id,
+        host,
+        players,
+        max_player,
+        if mods { " [mods]" } else { "" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hall_commands() {
+        assert_eq!(HallCommand::parse(""), Ok(HallCommand::Empty));
+        assert_eq!(HallCommand::parse(" /help "), Ok(HallCommand::Help));
+        assert_eq!(HallCommand::parse("LIST"), Ok(HallCommand::List));
+        assert_eq!(HallCommand::parse("stats"), Ok(HallCommand::Stats));
+        assert_eq!(HallCommand::parse("/motd"), Ok(HallCommand::Motd));
+        assert_eq!(HallCommand::parse("S1234"), Ok(HallCommand::Join("1234")));
+        assert_eq!(HallCommand::parse("join S1234"), Ok(HallCommand::Join("1234")));
+        assert_eq!(HallCommand::parse("join 1234"), Ok(HallCommand::Join("1234")));
+        assert!(HallCommand::parse("join").is_err());
+        assert_eq!(HallCommand::parse("S"), Ok(HallCommand::Unknown("S")));
+        assert_eq!(HallCommand::parse("what"), Ok(HallCommand::Unknown("what")));
+    }
+
+    #[test]
+    fn parse_new_room_options() {
+        assert_eq!(
+            HallCommand::parse("new"),
+            Ok(HallCommand::New(NewRoomOptions::default()))
+        );
+        assert_eq!(
+            HallCommand::parse("mods p8"),
+            Ok(HallCommand::New(NewRoomOptions {
+                mods: true,
+                max_player: Some(8),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            HallCommand::parse("new P4 mods engine"),
+            Ok(HallCommand::New(NewRoomOptions {
+                mods: true,
+                engine: true,
+                max_player: Some(4),
+            }))
+        );
+        assert!(HallCommand::parse("new p0").is_err());
+        assert!(HallCommand::parse("new p101").is_err());
+        assert!(HallCommand::parse("new fast").is_err());
+    }
+
+    #[test]
+    fn room_lines() {
+        assert_eq!(room_line("S1234", "host", 2, 10, false), "S1234 host 2/10");
+        assert_eq!(room_line("S1", "host", 1, 4, true), "S1 host 1/4 [mods]");
+    }
+}
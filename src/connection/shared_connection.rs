@@ -1,6 +1,6 @@
 use std::{
     net::SocketAddr,
-    sync::{Arc, Weak},
+    sync::{atomic::Ordering, Arc, Weak},
 };
 
 use tokio::{
@@ -9,7 +9,7 @@ use tokio::{
     task::JoinHandle,
 };
 
-use crate::packet::Packet;
+use crate::{packet::Packet, relay_manager::relay::SharedRelayRoom};
 
 use super::{
     player_net_api::RelayDirectInspection, Connection, ConnectionAPI, ConnectionChannel,
@@ -70,6 +70,9 @@ impl SharedConnection {
                                 .send(con.relay_direct_inspection().await)
                                 .expect("send inspection_data error")
                         }
+                        ConnectionAPI::GetRelayRoom(room_tx) => {
+                            let _ = room_tx.send(con.shared_relay_room.clone());
+                        }
 
                         ConnectionAPI::SendRelayServerTypeReply => {
                             con.send_relay_server_type_reply().await
@@ -83,6 +86,10 @@ impl SharedConnection {
                         ConnectionAPI::SendPacketToHost(packet) => {
                             con.send_packet_to_host(packet).await
                         }
+                        ConnectionAPI::SendSystemMessage(msg) => {
+                            con.send_system_message(&msg).await
+                        }
+                        ConnectionAPI::Kick(reason) => con.kick(&reason).await,
                         ConnectionAPI::SendPacketToHostRaw(packet) => {
                             con.shared_relay_room
                                 .as_ref()
@@ -108,6 +115,13 @@ impl SharedConnection {
         shared_con
     }
 
+    pub fn is_disconnected(&self) -> bool {
+        self.shared_data
+            .connection_info
+            .disconnected
+            .load(Ordering::Relaxed)
+    }
+
     pub async fn type_relay(&self, shared_self: Arc<SharedConnection>, packet: Packet) {
         self.shared_channel
             .processor_sorter_tx
@@ -148,6 +162,16 @@ impl SharedConnection {
             .unwrap()
     }
 
+    //连接已断开时为空字符串
+    pub fn addr_string(&self) -> String {
+        self.shared_data
+            .connection_info
+            .addr
+            .upgrade()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default()
+    }
+
     pub async fn send_packet(&self, packet: Packet) {
         self.shared_channel
             .packet_tx
@@ -188,6 +212,24 @@ impl SharedConnection {
             .unwrap()
     }
 
+    //连接已结束时忽略,房间关闭和Web API都可能和断开同时发生
+    pub async fn send_system_message(&self, msg: &str) {
+        let _ = self
+            .shared_channel
+            .con_api_tx
+            .send(ConnectionAPI::SendSystemMessage(msg.to_string()))
+            .await;
+    }
+
+    //连接已结束时忽略
+    pub async fn kick(&self, reason: &str) {
+        let _ = self
+            .shared_channel
+            .con_api_tx
+            .send(ConnectionAPI::Kick(reason.to_string()))
+            .await;
+    }
+
     pub async fn relay_direct_inspection(&self) -> Option<RelayDirectInspection> {
         let (inspection_data_tx, inspection_data_rx) = oneshot::channel();
         self.shared_channel
@@ -201,6 +243,18 @@ impl SharedConnection {
             .expect("recv inspection data error")
     }
 
+    //玩家或房主所在的房间
+    pub async fn get_relay_room(&self) -> Option<Arc<SharedRelayRoom>> {
+        let (room_tx, room_rx) = oneshot::channel();
+        self.shared_channel
+            .con_api_tx
+            .send(ConnectionAPI::GetRelayRoom(room_tx))
+            .await
+            .unwrap();
+
+        room_rx.await.expect("recv relay room error")
+    }
+
     pub async fn send_relay_server_type_reply(&self) {
         self.shared_channel
             .con_api_tx
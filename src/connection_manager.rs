@@ -1,13 +1,25 @@
 mod connection_lib;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+};
 
 use log::info;
-use tokio::{join, net::TcpStream, runtime::Runtime, sync::mpsc, task::JoinHandle};
+use tokio::{
+    join,
+    net::TcpStream,
+    runtime::Runtime,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
 
-use crate::connection::ConnectionLibAPI;
+use crate::cluster::RemoteRoom;
+use crate::module::MODULE_MANAGER;
+use crate::connection::{shared_connection::SharedConnection, ConnectionLibAPI};
 use crate::packet::Packet;
-use crate::relay_manager::SharedRelayManager;
+use crate::relay_manager::{relay::SharedRelayRoom, SharedRelayManager};
 use crate::worker_pool::{receiver::receiver, sender::sender};
 use crate::{
     connection::Connection,
@@ -27,6 +39,7 @@ pub struct ConnectionManager {
     runtime: Option<Arc<Runtime>>,
     connection_runtime: Option<Runtime>,
     shared_relay_mg: Arc<SharedRelayManager>,
+    banlist: Arc<RwLock<HashSet<IpAddr>>>,
     pub con_lib_api_tx: Option<mpsc::Sender<ConnectionLibAPI>>,
 }
 
@@ -141,6 +154,7 @@ impl ConnectionManager {
                         shared_relay_mg.clone(),
                         con_lib_api_tx.clone(),
                     );
+                    MODULE_MANAGER.on_connection(&new_shared_con);
                     let (read_half, write_half) = socket.into_split();
 
                     
@@ -213,6 +227,12 @@ impl ConnectionManager {
                             ConnectionLibAPI::SendPacketToPlayerBy(by, packet) => {
                                 connection_lib.send_packet_to_player_by(by, packet).await
                             }
+                            ConnectionLibAPI::GetConnectionBy(by, shared_con_tx) => {
+                                let _ = shared_con_tx.send(connection_lib.get_connection_by(by));
+                            }
+                            ConnectionLibAPI::GetConnectionList(con_list_tx) => {
+                                let _ = con_list_tx.send(connection_lib.get_connection_list());
+                            }
                         }
                     }
                 }),
@@ -229,10 +249,93 @@ impl ConnectionManager {
             .expect("send con_lib_api error");
     }
 
+    pub async fn get_connection_by(&self, by: By) -> Option<Arc<SharedConnection>> {
+        let (shared_con_tx, shared_con_rx) = oneshot::channel();
+        self.con_lib_api_tx
+            .as_ref()
+            .unwrap()
+            .send(ConnectionLibAPI::GetConnectionBy(by, shared_con_tx))
+            .await
+            .expect("send con_lib_api error");
+        shared_con_rx.await.expect("recv connection error")
+    }
+
+    pub async fn get_connection_list(&self) -> Vec<Arc<SharedConnection>> {
+        let (con_list_tx, con_list_rx) = oneshot::channel();
+        self.con_lib_api_tx
+            .as_ref()
+            .unwrap()
+            .send(ConnectionLibAPI::GetConnectionList(con_list_tx))
+            .await
+            .expect("send con_lib_api error");
+        con_list_rx.await.expect("recv connection list error")
+    }
+
+    pub async fn get_relay_list(&self) -> Vec<Arc<SharedRelayRoom>> {
+        self.shared_relay_mg.get_relay_list().await
+    }
+
+    //集群其他节点上的房间,未开启集群时为None
+    pub fn get_remote_relay_list(&self) -> Option<Vec<RemoteRoom>> {
+        self.shared_relay_mg
+            .cluster
+            .as_ref()
+            .map(|cluster| cluster.remote_room_list())
+    }
+
+    pub async fn close_relay(&self, id: &str, reason: &str) -> bool {
+        self.shared_relay_mg.close_relay(id, reason).await
+    }
+
+    pub async fn broadcast(&self, msg: &str) {
+        for shared_con in self.get_connection_list().await {
+            shared_con.send_system_message(msg).await;
+        }
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.banlist
+            .read()
+            .expect("read banlist error")
+            .contains(ip)
+    }
+
+    pub fn get_banlist(&self) -> Vec<IpAddr> {
+        self.banlist
+            .read()
+            .expect("read banlist error")
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    //封禁IP并踢出该IP下的所有连接
+    pub async fn ban(&self, ip: IpAddr, reason: &str) {
+        self.banlist.write().expect("write banlist error").insert(ip);
+        info!(ip:% = ip; "已封禁IP:{}", reason);
+
+        for shared_con in self.get_connection_list().await {
+            let banned = shared_con
+                .shared_data
+                .connection_info
+                .addr
+                .upgrade()
+                .is_some_and(|addr| addr.ip() == ip);
+            if banned {
+                shared_con.kick(reason).await;
+            }
+        }
+    }
+
+    pub fn unban(&self, ip: &IpAddr) -> bool {
+        self.banlist.write().expect("write banlist error").remove(ip)
+    }
+
     async fn ne_new(
         con_mg_thread_number: usize,
         con_thread_number: usize,
         shared_relay_mg: Arc<SharedRelayManager>,
+        banlist: Vec<IpAddr>,
     ) -> Self {
         ConnectionManager {
             runtime: Some(Arc::new(
@@ -248,15 +351,17 @@ impl ConnectionManager {
             new_con_tx: None,
             handle_vec: Vec::new(),
             shared_relay_mg,
+            banlist: Arc::new(RwLock::new(banlist.into_iter().collect())),
             con_lib_api_tx: None,
         }
     }
 
     pub async fn new(
         server_config: ServerConfig,
+        banlist: Vec<IpAddr>,
         shared_relay_mg: Arc<SharedRelayManager>,
     ) -> Self {
-        let mut connection_mg = ConnectionManager::ne_new(3, 10, shared_relay_mg).await;
+        let mut connection_mg = ConnectionManager::ne_new(3, 10, shared_relay_mg, banlist).await;
 
         connection_mg
             .init_worker_pool(
@@ -46,6 +46,28 @@ impl ConnectionLib {
         }
     }
 
+    pub fn get_connection_by(&self, by: By) -> Option<Arc<SharedConnection>> {
+        match by {
+            By::Addr(addr) => self.addr_map.get(&addr).cloned(),
+            By::Name(name) => self
+                .addr_map
+                .values()
+                .find(|con| {
+                    *con.shared_data
+                        .player_info
+                        .player_name
+                        .read()
+                        .expect("read player name error")
+                        == name
+                })
+                .cloned(),
+        }
+    }
+
+    pub fn get_connection_list(&self) -> Vec<Arc<SharedConnection>> {
+        self.addr_map.values().cloned().collect()
+    }
+
     pub fn remove_by(&mut self, by: By) {
         match by {
             By::Addr(addr) => {
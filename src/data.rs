@@ -11,8 +11,12 @@ pub static COMMAND_HELP: &str = "
 /list player    列出所有玩家
 /list room      列出所有房间
 /player size    玩家总数
-/room size    房间总数
-/list all_worker
+/room size      房间总数
+/reload         重新加载配置文件
+/module list    列出已加载的模块
+/module load <名称>    加载内置模块或模块目录中的<名称>.wasm
+/module unload <名称>  卸载模块
+/module reload  重新加载模块目录中的所有WASM模块
 ";
 
 pub const _RELAY_INFO: &str = "[Relay CN]{} 欢迎使用RJR,这台服务是非官方的Relay房间
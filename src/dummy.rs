@@ -1,3 +1,4 @@
+pub mod host;
 pub mod shared_dummy;
 
 use std::sync::{Arc};
@@ -15,7 +16,10 @@ use crate::{
     packet::{Packet},
 };
 
-use self::shared_dummy::SharedDummy;
+use self::{
+    host::{DummyHost, DummyHostConf},
+    shared_dummy::SharedDummy,
+};
 
 lazy_static! {
     static ref DUMMY_RUNTIME:Runtime = Builder::new_multi_thread()
@@ -33,41 +37,79 @@ pub enum DummyAPI {
     SendPackets(Vec<Packet>),
 }
 
-#[derive(Debug,Clone, Copy)]
+#[derive(Debug,Clone, Copy, PartialEq, Eq)]
 pub enum DummyVersion {
     Version1_15,
-    Version1_16,
+    //1.16没有公开的固定版本号,使用房主上报的客户端版本号,保证能加入房主的房间
+    Version1_16(u32),
 }
 
+//1.15正式版的客户端版本号,uplist上报的1.15版本号也是它,更早的152到175是测试版
+static CLIENT_VERSION_1_15_RELEASE: u32 = 176;
+//客户端信息数据包的格式版本,两个版本相同,都带有玩家名
+static PREREGISTER_PACKET_VERSION: u32 = 4;
+
+impl DummyVersion {
+    //根据房主的客户端版本选择假人版本,1.15正式版之后的都按1.16处理
+    pub fn from_client_version(client_version: u32) -> Self {
+        if client_version > CLIENT_VERSION_1_15_RELEASE {
+            DummyVersion::Version1_16(client_version)
+        } else {
+            DummyVersion::Version1_15
+        }
+    }
+
+    pub fn packet_version(&self) -> u32 {
+        PREREGISTER_PACKET_VERSION
+    }
+
+    pub fn client_version(&self) -> u32 {
+        match self {
+            DummyVersion::Version1_15 => 172,
+            DummyVersion::Version1_16(client_version) => *client_version,
+        }
+    }
+}
+
+//假人在中继服务器上的状态,由接收循环根据服务器发来的数据包更新
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum DummyState {
+    //已建立TCP连接,还没有发送客户端信息
+    #[default]
+    Connected,
+    //服务器已回复中继版本
+    Registered,
+    //收到大厅消息,可以输入命令
+    InHall,
+    //房主的编号为0,玩家的编号只有房主知道
+    InRoom {
+        room_id: Option<String>,
+        room_index: Option<u32>,
+    },
+    Disconnected,
+}
 
 #[derive(Debug,Clone)]
 pub struct DummyConf {
     pub dummy_name: String,
     pub domain: String,
     pub version: DummyVersion,
-    pub packet_version: u32,
-    pub client_version: u32,
+    //是否自动回复心跳,回放时心跳回复来自抓包文件
+    pub auto_heartbeat: bool,
 }
 pub struct Dummy {
     pub conf: DummyConf,
-    pub is_connect: bool,
     pub read_half: OwnedReadHalf,
     pub write_half: OwnedWriteHalf,
 }
 
 impl DummyConf {
     pub fn new(dummy_name: String, domain: String, version: DummyVersion) -> Self {
-        match version {
-            DummyVersion::Version1_15 => {
-                Self{
-                    dummy_name,
-                    domain,
-                    version,
-                    packet_version: 4,
-                    client_version: 172,
-                }
-            },
-            DummyVersion::Version1_16 => todo!(),
+        Self {
+            dummy_name,
+            domain,
+            version,
+            auto_heartbeat: true,
         }
     }
 }
@@ -77,27 +119,43 @@ impl Dummy {
         dummy_conf: DummyConf,
         addr: &str,
     ) -> Result<Arc<SharedDummy>, DummyError> {
-        match dummy_conf.version {
-            DummyVersion::Version1_15 => {
-                let stream = TcpStream::connect(&addr).await?;
-
-                let (read_half, write_half) = stream.into_split();
-
-                let dummy = Self {
-                    conf: dummy_conf,
-                    is_connect: false,
-                    read_half,
-                    write_half,
-                };
-                Ok(SharedDummy::new(dummy))
-            }
-            DummyVersion::Version1_16 => todo!(),
-        }
+        //两个版本的连接方式相同,区别只在客户端信息里
+        let stream = TcpStream::connect(&addr).await?;
+
+        let (read_half, write_half) = stream.into_split();
+
+        let dummy = Self {
+            conf: dummy_conf,
+            read_half,
+            write_half,
+        };
+        Ok(SharedDummy::new(dummy))
     }
 
-    pub async fn connect_to_relay(&mut self, addr: &str) {}
+    //连接后直接创建房间并作为房主
+    pub async fn new_host(
+        dummy_conf: DummyConf,
+        addr: &str,
+        host_conf: DummyHostConf,
+    ) -> Result<Arc<DummyHost>, DummyError> {
+        let dummy = Self::new_shared(dummy_conf, addr).await?;
+        DummyHost::start(dummy, host_conf).await
+    }
+}
 
-    pub async fn join_to_room(&self, id: &str) {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_from_host() {
+        assert_eq!(DummyVersion::from_client_version(151), DummyVersion::Version1_15);
+        assert_eq!(DummyVersion::from_client_version(176), DummyVersion::Version1_15);
+        let version = DummyVersion::from_client_version(190);
+        assert_eq!(version, DummyVersion::Version1_16(190));
+        assert_eq!(version.client_version(), 190);
+        assert_eq!(DummyVersion::Version1_15.client_version(), 172);
+    }
 }
 
 /*
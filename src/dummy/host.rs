@@ -0,0 +1,417 @@
+//让假人创建房间并扮演一个最简单的游戏房主
+//回复玩家注册、维护玩家列表并转发聊天,可以用来开常驻的大厅房间,或者在没有游戏客户端时测试加入流程
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+use tokio::{sync::broadcast, task::JoinHandle};
+
+use crate::{
+    error::DummyError,
+    packet::{
+        common_packet::CommonPacket,
+        message::{
+            Chat, ChatReceive, HeartBeat, Kick, PacketForwardClientFrom, PacketForwardClientTo,
+            PreregisterInfo, RoomSettings, ServerInfo, TeamList, TeamPlayer,
+        },
+        Packet, PacketType,
+    },
+    server::LobbyConfig,
+};
+
+use super::{shared_dummy::SharedDummy, Dummy, DummyConf, DummyState, DummyVersion, DUMMY_RUNTIME};
+
+//等待服务器回复大厅消息和创建房间的时间
+static START_TIMEOUT: Duration = Duration::from_secs(10);
+//房主给已注册玩家发送心跳的间隔,游戏客户端长时间收不到心跳会断开
+static HEART_BEAT_INTERVAL: Duration = Duration::from_secs(5);
+//大厅房间开房失败或断开后重开的间隔
+static LOBBY_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct DummyHostConf {
+    //在大厅输入的开房命令,可以带参数
+    pub room_command: String,
+    pub map_name: String,
+    pub max_player: u32,
+    //玩家注册后房主发送的欢迎消息,{player}替换为玩家名
+    pub welcome: Option<String>,
+    pub settings: RoomSettings,
+}
+
+impl Default for DummyHostConf {
+    fn default() -> Self {
+        Self {
+            room_command: "new".to_string(),
+            map_name: "[z;p10]Crossing Large (10p)".to_string(),
+            max_player: 10,
+            welcome: Some("欢迎{player}".to_string()),
+            settings: RoomSettings::default(),
+        }
+    }
+}
+
+impl From<&LobbyConfig> for DummyHostConf {
+    fn from(lobby: &LobbyConfig) -> Self {
+        Self {
+            room_command: lobby.room_command.clone(),
+            map_name: lobby.map_name.clone(),
+            max_player: lobby.max_player,
+            welcome: Some(lobby.welcome.clone()).filter(|welcome| !welcome.is_empty()),
+            settings: RoomSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostedPlayer {
+    //中继分配的编号,转发数据包时使用
+    pub index: u32,
+    pub name: String,
+    //注册后分配的位置
+    pub site: Option<u32>,
+}
+
+pub struct DummyHost {
+    pub dummy: Arc<SharedDummy>,
+    //带S前缀
+    pub room_id: String,
+    players: Arc<RwLock<BTreeMap<u32, HostedPlayer>>>,
+    handle: JoinHandle<()>,
+}
+
+impl DummyHost {
+    //dummy需要是刚建立连接、还没有发送客户端信息的假人
+    pub async fn start(
+        dummy: Arc<SharedDummy>,
+        conf: DummyHostConf,
+    ) -> Result<Arc<Self>, DummyError> {
+        //先订阅,避免漏掉创建房间后马上到来的数据包
+        let packet_rx = dummy.subscribe();
+
+        dummy.connect_to_relay().await;
+        dummy
+            .wait_state_timeout("relay hall", START_TIMEOUT, |state| {
+                *state == DummyState::InHall
+            })
+            .await?;
+
+        dummy
+            .send_packet(CommonPacket::get_relay_hall_command(&conf.room_command))
+            .await;
+        let room_id = match dummy
+            .wait_state_timeout("room", START_TIMEOUT, |state| {
+                matches!(state, DummyState::InRoom { .. })
+            })
+            .await?
+        {
+            DummyState::InRoom {
+                room_id: Some(room_id),
+                ..
+            } => room_id,
+            _ => return Err(DummyError::Timeout("room id")),
+        };
+        info!(room_id = room_id.as_str(); "{}开始作为房主", dummy.conf.dummy_name);
+
+        let players = Arc::new(RwLock::new(BTreeMap::new()));
+        let handle =
+            DUMMY_RUNTIME.spawn(host_loop(dummy.clone(), conf, players.clone(), packet_rx));
+        Ok(Arc::new(Self {
+            dummy,
+            room_id,
+            players,
+            handle,
+        }))
+    }
+
+    //按中继编号排列
+    pub fn players(&self) -> Vec<HostedPlayer> {
+        self.players
+            .read()
+            .expect("read hosted players error")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    //以房主的身份向所有已注册的玩家发送聊天,目前只有测试使用
+    #[cfg(test)]
+    pub async fn send_chat(&self, message: &str) {
+        let chat = Packet::from_message(&Chat {
+            message: message.to_string(),
+            sender: Some(self.dummy.conf.dummy_name.clone()),
+            team: 0,
+        });
+        let packets = registered(&self.players)
+            .into_iter()
+            .map(|player| forward_to(player.index, &chat))
+            .collect();
+        self.dummy.send_packets(packets).await;
+    }
+}
+
+impl Drop for DummyHost {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+//为每个配置的大厅房间启动一个假人房主,addr为本机的中继地址
+pub fn start_lobbies(lobbies: &[LobbyConfig], addr: SocketAddr) {
+    for lobby in lobbies {
+        DUMMY_RUNTIME.spawn(keep_lobby(lobby.clone(), addr));
+    }
+}
+
+//房间被关闭或连接断开后重新开房
+async fn keep_lobby(lobby: LobbyConfig, addr: SocketAddr) {
+    loop {
+        let dummy_conf = DummyConf::new(
+            lobby.name.clone(),
+            "com.corrodinggames.rts".to_string(),
+            DummyVersion::Version1_15,
+        );
+        match Dummy::new_host(dummy_conf, &addr.to_string(), (&lobby).into()).await {
+            Ok(host) => {
+                info!(room_id = host.room_id.as_str(); "大厅房间{}已开启", lobby.name);
+                host.dummy
+                    .wait_state(|state| *state == DummyState::Disconnected)
+                    .await;
+                warn!(
+                    room_id = host.room_id.as_str();
+                    "大厅房间{}已断开,断开时有{}个玩家",
+                    lobby.name,
+                    host.players().len()
+                );
+            }
+            Err(e) => warn!("大厅房间{}开启失败:{}", lobby.name, e),
+        }
+        tokio::time::sleep(LOBBY_RETRY_INTERVAL).await;
+    }
+}
+
+async fn host_loop(
+    dummy: Arc<SharedDummy>,
+    conf: DummyHostConf,
+    players: Arc<RwLock<BTreeMap<u32, HostedPlayer>>>,
+    mut packet_rx: broadcast::Receiver<Packet>,
+) {
+    let mut heart_beat = tokio::time::interval(HEART_BEAT_INTERVAL);
+    loop {
+        let packet = tokio::select! {
+            recv = packet_rx.recv() => match recv {
+                Ok(packet) => packet,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("{}丢弃了{}个未处理的数据包", dummy.conf.dummy_name, skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = heart_beat.tick() => {
+                send_heart_beat(&dummy, &players).await;
+                continue;
+            }
+        };
+
+        //玩家发来的数据包都经过中继转发,其余的是中继自己的消息
+        if packet.packet_type != PacketType::PACKET_FORWARD_CLIENT_FROM {
+            continue;
+        }
+        match packet.to_message::<PacketForwardClientFrom>() {
+            Ok(from) => handle_player_packet(&dummy, &conf, &players, from).await,
+            Err(e) => debug!("{}无法解析玩家数据包:{}", dummy.conf.dummy_name, e),
+        }
+    }
+    info!("{}的连接已断开,不再处理房间", dummy.conf.dummy_name);
+}
+
+async fn handle_player_packet(
+    dummy: &SharedDummy,
+    conf: &DummyHostConf,
+    players: &RwLock<BTreeMap<u32, HostedPlayer>>,
+    from: PacketForwardClientFrom,
+) {
+    let index = from.index;
+    let packet_type = PacketType::try_from(from.packet_type).unwrap_or_default();
+    let packet = Packet::new(packet_type, from.payload);
+    match packet_type {
+        //加入房间时中继会转发玩家的客户端信息,从中取得玩家名
+        PacketType::PREREGISTER_INFO_RECEIVE => {
+            let name = packet
+                .to_message::<PreregisterInfo>()
+                .ok()
+                .and_then(|info| info.player_name)
+                .unwrap_or_else(|| format!("Player{}", index));
+            players.write().expect("write hosted players error").insert(
+                index,
+                HostedPlayer {
+                    index,
+                    name,
+                    site: None,
+                },
+            );
+        }
+        PacketType::REGISTER_PLAYER => register_player(dummy, conf, players, index).await,
+        PacketType::CHAT_RECEIVE => {
+            let Ok(chat) = packet.to_message::<ChatReceive>() else {
+                return;
+            };
+            let sender = players
+                .read()
+                .expect("read hosted players error")
+                .get(&index)
+                .filter(|player| player.site.is_some())
+                .cloned();
+            if let Some(sender) = sender {
+                let chat = Packet::from_message(&Chat {
+                    message: chat.message,
+                    sender: Some(sender.name),
+                    team: sender.site.unwrap_or_default(),
+                });
+                let packets = registered(players)
+                    .into_iter()
+                    .map(|player| forward_to(player.index, &chat))
+                    .collect();
+                dummy.send_packets(packets).await;
+            }
+        }
+        PacketType::DISCONNECT => {
+            let removed = players
+                .write()
+                .expect("write hosted players error")
+                .remove(&index);
+            if removed.is_some_and(|player| player.site.is_some()) {
+                send_team_list(dummy, conf, players).await;
+            }
+        }
+        _ => {}
+    }
+}
+
+async fn register_player(
+    dummy: &SharedDummy,
+    conf: &DummyHostConf,
+    players: &RwLock<BTreeMap<u32, HostedPlayer>>,
+    index: u32,
+) {
+    let name = {
+        let mut players = players.write().expect("write hosted players error");
+        //没有收到客户端信息时也允许注册
+        let free_site = (0..conf.max_player)
+            .find(|site| players.values().all(|player| player.site != Some(*site)));
+        let player = players.entry(index).or_insert_with(|| HostedPlayer {
+            index,
+            name: format!("Player{}", index),
+            site: None,
+        });
+        if player.site.is_none() {
+            player.site = free_site;
+        }
+        player.site.map(|_| player.name.clone())
+    };
+
+    let Some(name) = name else {
+        dummy
+            .send_packet(forward_to(
+                index,
+                &Packet::from_message(&Kick {
+                    reason: "房间已满".to_string(),
+                }),
+            ))
+            .await;
+        players
+            .write()
+            .expect("write hosted players error")
+            .remove(&index);
+        return;
+    };
+    debug!("{}注册到房间{}", name, dummy.conf.dummy_name);
+
+    let server_info = Packet::from_message(&ServerInfo {
+        version: dummy.conf.version.client_version(),
+        map_name: conf.map_name.clone(),
+        settings: conf.settings.clone(),
+        is_admin: false,
+    });
+    dummy.send_packet(forward_to(index, &server_info)).await;
+    //新玩家和其他玩家都需要新的玩家列表
+    send_team_list(dummy, conf, players).await;
+
+    if let Some(welcome) = &conf.welcome {
+        let chat = Packet::from_message(&Chat {
+            message: welcome.replace("{player}", &name),
+            sender: Some(dummy.conf.dummy_name.clone()),
+            team: 0,
+        });
+        dummy.send_packet(forward_to(index, &chat)).await;
+    }
+}
+
+//每个玩家的列表相同,只有自己的位置不同
+async fn send_team_list(
+    dummy: &SharedDummy,
+    conf: &DummyHostConf,
+    players: &RwLock<BTreeMap<u32, HostedPlayer>>,
+) {
+    let registered = registered(players);
+    let mut slots = vec![None; conf.max_player as usize];
+    for player in registered.iter() {
+        let site = player.site.unwrap_or_default();
+        if let Some(slot) = slots.get_mut(site as usize) {
+            *slot = Some(TeamPlayer {
+                site,
+                team: site % 2,
+                name: player.name.clone(),
+                ping: 0,
+            });
+        }
+    }
+
+    let mut team_list = TeamList {
+        site: 0,
+        start_game: false,
+        players: slots,
+        settings: conf.settings.clone(),
+    };
+    let mut packets = Vec::with_capacity(registered.len());
+    for player in registered.iter() {
+        team_list.site = player.site.unwrap_or_default();
+        packets.push(forward_to(player.index, &Packet::from_message(&team_list)));
+    }
+    dummy.send_packets(packets).await;
+}
+
+async fn send_heart_beat(dummy: &SharedDummy, players: &RwLock<BTreeMap<u32, HostedPlayer>>) {
+    let registered = registered(players);
+    if registered.is_empty() {
+        return;
+    }
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or_default();
+    let heart_beat = Packet::from_message(&HeartBeat { time });
+    let packets = registered
+        .into_iter()
+        .map(|player| forward_to(player.index, &heart_beat))
+        .collect();
+    dummy.send_packets(packets).await;
+}
+
+fn registered(players: &RwLock<BTreeMap<u32, HostedPlayer>>) -> Vec<HostedPlayer> {
+    players
+        .read()
+        .expect("read hosted players error")
+        .values()
+        .filter(|player| player.site.is_some())
+        .cloned()
+        .collect()
+}
+
+fn forward_to(index: u32, packet: &Packet) -> Packet {
+    Packet::from_message(&PacketForwardClientTo::new(index, packet))
+}
@@ -1,62 +1,291 @@
-use std::sync::Arc;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
-use tokio::{io::AsyncWriteExt, task::JoinHandle};
+use futures_util::{SinkExt, StreamExt};
+use log::debug;
+use tokio::{
+    net::tcp::OwnedReadHalf,
+    sync::{broadcast, watch, Mutex},
+    task::JoinHandle,
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
 
-use crate::packet::Packet;
+use crate::{
+    error::DummyError,
+    packet::{
+        codec::PacketCodec,
+        common_packet::CommonPacket,
+        message::{HeartBeat, HeartBeatResponse, RelayBecomeServer},
+        Packet, PacketType,
+    },
+};
 
-use super::{Dummy, DummyAPI, DummyConf, DUMMY_RUNTIME};
+use super::{Dummy, DummyAPI, DummyConf, DummyState, DUMMY_RUNTIME};
+
+//订阅者处理不过来时最多积压的数据包,超过后丢弃最旧的
+//每个假人都会预先分配,压力测试时假人很多,不宜过大
+static SUBSCRIBE_CAPACITY: usize = 256;
 
 pub struct SharedDummy {
     basic_dummy_api_tx: async_channel::Sender<DummyAPI>,
+    //只用来创建新的订阅,本身不读取
+    subscribe_rx: broadcast::Receiver<Packet>,
+    //recv_packet使用的订阅,从连接建立时开始
+    packet_rx: Mutex<broadcast::Receiver<Packet>>,
+    state_rx: watch::Receiver<DummyState>,
+    //请求加入的房间,进入房间后记录到状态里
+    joining_room: Arc<RwLock<Option<String>>>,
     handle: JoinHandle<()>,
+    recv_handle: JoinHandle<()>,
+    //服务器看到的假人地址
+    local_addr: Option<SocketAddr>,
     pub conf: DummyConf,
 }
 
 impl SharedDummy {
     pub fn new(dummy: Dummy) -> Arc<Self> {
         let (basic_dummy_api_tx, basic_dummy_api_rx) = async_channel::bounded(10);
-        let conf = dummy.conf.clone();
+        let (packet_tx, packet_rx) = broadcast::channel(SUBSCRIBE_CAPACITY);
+        let (state_tx, state_rx) = watch::channel(DummyState::Connected);
+        let joining_room = Arc::new(RwLock::new(None));
+        let Dummy {
+            conf,
+            read_half,
+            write_half,
+        } = dummy;
+        let local_addr = read_half.local_addr().ok();
+
         let handle = DUMMY_RUNTIME.spawn(async move {
-            let mut dummy = dummy;
             let basic_dummy_api_rx = basic_dummy_api_rx;
-            loop {
-                if let Ok(api_type) = basic_dummy_api_rx.recv().await {
-                    match api_type {
-                        DummyAPI::SendPacket(mut packet) => {
-                            packet.prepare().await;
-                            println!("{:?}", packet);
-                            dummy
-                                .write_half
-                                .write_all(&packet.packet_buffer.into_inner())
-                                .await
-                                .expect("dummy send packet error");
-                        }
-                        DummyAPI::SendPackets(packets) => {
-                            for mut packet in packets {
-                                packet.prepare().await;
-                                println!("{:?}", packet);
-                                dummy
-                                    .write_half
-                                    .write_all(&packet.packet_buffer.into_inner())
-                                    .await
-                                    .expect("dummy send packet error");
-                            }
-                        }
+            let mut sink = FramedWrite::new(write_half, PacketCodec);
+            while let Ok(api_type) = basic_dummy_api_rx.recv().await {
+                let packets = match api_type {
+                    DummyAPI::SendPacket(packet) => vec![packet],
+                    DummyAPI::SendPackets(packets) => packets,
+                };
+                for packet in packets {
+                    debug!("dummy发送{}", packet.decoded());
+                    if let Err(e) = sink.feed(packet).await {
+                        debug!("dummy发送失败:{}", e);
+                        return;
                     }
                 }
+                if let Err(e) = sink.flush().await {
+                    debug!("dummy发送失败:{}", e);
+                    return;
+                }
             }
         });
+
+        let recv_handle = DUMMY_RUNTIME.spawn(receive_loop(
+            read_half,
+            conf.clone(),
+            basic_dummy_api_tx.clone(),
+            packet_tx,
+            state_tx,
+            joining_room.clone(),
+        ));
+
         Arc::new(SharedDummy {
             basic_dummy_api_tx,
+            packet_rx: Mutex::new(packet_rx.resubscribe()),
+            subscribe_rx: packet_rx,
+            state_rx,
+            joining_room,
             handle,
-            conf
+            recv_handle,
+            local_addr,
+            conf,
         })
     }
 
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    //连接已断开时丢弃数据包
     pub async fn send_packet(&self, packet: Packet) {
-        self.basic_dummy_api_tx
+        let _ = self
+            .basic_dummy_api_tx
             .send(DummyAPI::SendPacket(packet))
-            .await
-            .unwrap()
+            .await;
+    }
+
+    pub async fn send_packets(&self, packets: Vec<Packet>) {
+        let _ = self
+            .basic_dummy_api_tx
+            .send(DummyAPI::SendPackets(packets))
+            .await;
+    }
+
+    //服务器发来的数据包,连接断开后返回None
+    pub async fn recv_packet(&self) -> Option<Packet> {
+        let mut packet_rx = self.packet_rx.lock().await;
+        recv_subscribed(&mut packet_rx, &self.conf).await
+    }
+
+    //订阅之后服务器发来的所有数据包,每个订阅者都会收到一份
+    //连接断开后recv返回Closed
+    pub fn subscribe(&self) -> broadcast::Receiver<Packet> {
+        self.subscribe_rx.resubscribe()
+    }
+
+    pub fn state(&self) -> DummyState {
+        self.state_rx.borrow().clone()
+    }
+
+    //等待状态满足条件,连接断开后状态为Disconnected
+    pub async fn wait_state(&self, f: impl FnMut(&DummyState) -> bool) -> DummyState {
+        let mut state_rx = self.state_rx.clone();
+        let state = match state_rx.wait_for(f).await {
+            Ok(state) => state.clone(),
+            Err(_) => DummyState::Disconnected,
+        };
+        state
+    }
+
+    //等待状态满足条件,超时或连接断开时返回错误,waiting用于错误信息
+    pub async fn wait_state_timeout(
+        &self,
+        waiting: &'static str,
+        timeout: Duration,
+        mut f: impl FnMut(&DummyState) -> bool,
+    ) -> Result<DummyState, DummyError> {
+        let state = tokio::time::timeout(
+            timeout,
+            self.wait_state(|state| f(state) || *state == DummyState::Disconnected),
+        )
+        .await
+        .map_err(|_| DummyError::Timeout(waiting))?;
+        match state {
+            DummyState::Disconnected => Err(DummyError::Disconnected),
+            state => Ok(state),
+        }
+    }
+
+    //发送客户端信息,之后服务器会回复中继版本和大厅消息
+    pub async fn connect_to_relay(&self) {
+        self.send_packet(CommonPacket::get_preregister_info(
+            &self.conf.dummy_name,
+            &self.conf.domain,
+            self.conf.version.packet_version(),
+            self.conf.version.client_version(),
+            "",
+        ))
+        .await;
+    }
+
+    //id可以带S前缀,也可以不带
+    pub async fn join_to_room(&self, id: &str) {
+        let id = format!("S{}", id.trim_start_matches('S'));
+        *self.joining_room.write().expect("write joining room error") = Some(id.clone());
+        self.send_packet(CommonPacket::get_relay_hall_command(&id))
+            .await;
+    }
+}
+
+//接收服务器发来的数据包,更新状态并分发给订阅者
+async fn receive_loop(
+    read_half: OwnedReadHalf,
+    conf: DummyConf,
+    api_tx: async_channel::Sender<DummyAPI>,
+    packet_tx: broadcast::Sender<Packet>,
+    state_tx: watch::Sender<DummyState>,
+    joining_room: Arc<RwLock<Option<String>>>,
+) {
+    let mut stream = FramedRead::new(read_half, PacketCodec);
+    while let Some(recv) = stream.next().await {
+        let packet = match recv {
+            Ok(packet) => packet,
+            Err(e) => {
+                debug!("{}接收失败:{}", conf.dummy_name, e);
+                break;
+            }
+        };
+        debug!("{}收到{}", conf.dummy_name, packet.decoded());
+
+        if packet.packet_type == PacketType::HEART_BEAT && conf.auto_heartbeat {
+            if let Ok(heart_beat) = packet.to_message::<HeartBeat>() {
+                let response = Packet::from_message(&HeartBeatResponse {
+                    time: heart_beat.time,
+                });
+                let _ = api_tx.send(DummyAPI::SendPacket(response)).await;
+            }
+        }
+
+        state_tx.send_if_modified(|state| match next_state(state, &packet, &joining_room) {
+            Some(next) if next != *state => {
+                debug!("{}状态变为{:?}", conf.dummy_name, next);
+                *state = next;
+                true
+            }
+            _ => false,
+        });
+
+        //没有订阅者时直接丢弃
+        let _ = packet_tx.send(packet);
+    }
+    state_tx.send_replace(DummyState::Disconnected);
+}
+
+fn next_state(
+    state: &DummyState,
+    packet: &Packet,
+    joining_room: &RwLock<Option<String>>,
+) -> Option<DummyState> {
+    match (state, packet.packet_type) {
+        (DummyState::Connected, PacketType::RELAY_VERSION_INFO) => Some(DummyState::Registered),
+        (DummyState::Registered, PacketType::RELAY_117) => Some(DummyState::InHall),
+        //创建房间成功,房主的编号固定为0
+        (DummyState::InHall, PacketType::RELAY_BECOME_SERVER) => Some(DummyState::InRoom {
+            room_id: packet
+                .to_message::<RelayBecomeServer>()
+                .ok()
+                .and_then(|message| message.room_id()),
+            room_index: Some(0),
+        }),
+        //请求加入房间后收到大厅消息以外的数据包,说明已经进入房间
+        (DummyState::InHall, packet_type)
+            if !matches!(
+                packet_type,
+                PacketType::RELAY_117 | PacketType::KICK | PacketType::PACKET_RECONNECT_TO
+            ) =>
+        {
+            let room_id = joining_room
+                .write()
+                .expect("write joining room error")
+                .take()?;
+            Some(DummyState::InRoom {
+                room_id: Some(room_id),
+                room_index: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+//订阅者落后太多时跳过丢失的数据包
+async fn recv_subscribed(
+    packet_rx: &mut broadcast::Receiver<Packet>,
+    conf: &DummyConf,
+) -> Option<Packet> {
+    loop {
+        match packet_rx.recv().await {
+            Ok(packet) => return Some(packet),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                debug!("{}丢弃了{}个未读取的数据包", conf.dummy_name, skipped)
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+impl Drop for SharedDummy {
+    fn drop(&mut self) {
+        self.handle.abort();
+        self.recv_handle.abort();
     }
 }
@@ -0,0 +1,1297 @@
+//端到端测试:在临时端口上启动完整的服务器,用Dummy扮演房主和玩家走一遍中继流程
+//所有测试共用一个服务器,每个测试使用不同的玩家名,互不影响
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::BytesMut;
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    cli::LoadTestArgs,
+    connection::{relay_hall::HALL_HELP, shared_connection::SharedConnection},
+    connection_manager::{By, ConnectionManager},
+    dummy::{
+        host::DummyHostConf, shared_dummy::SharedDummy, Dummy, DummyConf, DummyState, DummyVersion,
+    },
+    error::ModuleError,
+    load_test::load_test,
+    module::{rw_engine::RwEngine, Module, PacketAction, MODULE_MANAGER},
+    packet::{common_packet::CommonPacket, message::*, Packet, PacketType},
+    relay_manager::relay::SharedRelayRoom,
+    serve,
+    server::{
+        config::ConfigOverride,
+        listener::bind_listener,
+        reload::{init_live_config, reload_config, save_banlist},
+        AllConfig, ListenConfig, ModuleConfig, RwEngineConfig,
+        ServerConfig,
+    },
+    web,
+};
+
+//等待单个数据包的时间,超时说明服务器没有按预期回复
+static RECV_TIMEOUT: Duration = Duration::from_secs(5);
+//单个测试的总时间
+static TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct TestServer {
+    addr: SocketAddr,
+    connection_mg: Arc<ConnectionManager>,
+}
+
+lazy_static! {
+    //运行时不能在异步上下文中drop,服务器和运行时一直保留到测试进程退出
+    static ref TEST_RUNTIME: Runtime = Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .expect("create test runtime error");
+    static ref TEST_SERVER: TestServer = TEST_RUNTIME.block_on(start_test_server());
+    //修改配置文件路径和封禁列表的测试不能同时运行
+    static ref CONFIG_LOCK: Mutex<()> = Mutex::new(());
+}
+
+async fn start_test_server() -> TestServer {
+    let listen_config = ListenConfig {
+        addr: "127.0.0.1".parse().unwrap(),
+        ..Default::default()
+    };
+    let listener = bind_listener(SocketAddr::new(listen_config.addr, 0), &listen_config)
+        .expect("bind test listener error");
+    let addr = listener.local_addr().unwrap();
+
+    let config = AllConfig {
+        server: ServerConfig {
+            thread_number: 2,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let connection_mg = serve(&config, vec![(listener, listen_config)])
+        .await
+        .expect("start test server error");
+    MODULE_MANAGER.start(&ModuleConfig::default(), connection_mg.clone());
+    TestServer {
+        addr,
+        connection_mg,
+    }
+}
+
+fn run_test<F, Fut>(test: F)
+where
+    F: FnOnce(&'static TestServer) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    //必须在block_on之外初始化,否则会在运行时中再次block_on
+    let server = &*TEST_SERVER;
+    TEST_RUNTIME
+        .block_on(async { tokio::time::timeout(TEST_TIMEOUT, test(server)).await })
+        .expect("test timeout");
+}
+
+async fn recv(dummy: &SharedDummy) -> Option<Packet> {
+    tokio::time::timeout(RECV_TIMEOUT, dummy.recv_packet())
+        .await
+        .unwrap_or_else(|_| panic!("{}等待数据包超时", dummy.conf.dummy_name))
+}
+
+//跳过其他数据包,直到收到指定类型的数据包
+async fn recv_type(dummy: &SharedDummy, packet_type: PacketType) -> Packet {
+    loop {
+        match recv(dummy).await {
+            Some(packet) if packet.packet_type == packet_type => return packet,
+            Some(_) => continue,
+            None => panic!(
+                "{}在收到{:?}之前连接已断开",
+                dummy.conf.dummy_name, packet_type
+            ),
+        }
+    }
+}
+
+async fn recv_message<M: PacketMessage>(dummy: &SharedDummy) -> M {
+    recv_type(dummy, M::PACKET_TYPE)
+        .await
+        .to_message()
+        .expect("decode message error")
+}
+
+//服务器关闭连接后recv_packet返回None
+async fn recv_closed(dummy: &SharedDummy) {
+    while recv(dummy).await.is_some() {}
+}
+
+async fn send_command(dummy: &SharedDummy, command: &str) {
+    dummy
+        .send_packet(CommonPacket::get_relay_hall_command(command))
+        .await;
+}
+
+async fn connect(server: &TestServer, name: &str) -> Arc<SharedDummy> {
+    connect_as(server, name, DummyVersion::Version1_15).await
+}
+
+//连接并完成预注册,返回时已经收到大厅欢迎消息
+async fn connect_as(server: &TestServer, name: &str, version: DummyVersion) -> Arc<SharedDummy> {
+    let dummy = Dummy::new_shared(
+        DummyConf::new(
+            name.to_string(),
+            "com.corrodinggames.rts".to_string(),
+            version,
+        ),
+        &server.addr.to_string(),
+    )
+    .await
+    .expect("connect test server error");
+    assert_eq!(dummy.state(), DummyState::Connected);
+    dummy.connect_to_relay().await;
+
+    let version = recv_message::<RelayVersionInfo>(&dummy).await;
+    assert_eq!(version.version, RELAY_VERSION);
+    recv_message::<RelayHallMessage>(&dummy).await;
+    assert_eq!(dummy.state(), DummyState::InHall);
+    dummy
+}
+
+async fn host_room(server: &TestServer, name: &str) -> (Arc<SharedDummy>, String) {
+    host_room_as(server, name, DummyVersion::Version1_15).await
+}
+
+//创建房间,返回不带S前缀的房间号
+async fn host_room_as(
+    server: &TestServer,
+    name: &str,
+    version: DummyVersion,
+) -> (Arc<SharedDummy>, String) {
+    let host = connect_as(server, name, version).await;
+    send_command(&host, "new").await;
+    let room_id = recv_message::<RelayBecomeServer>(&host)
+        .await
+        .room_id()
+        .expect("room id not found");
+    assert_eq!(
+        host.state(),
+        DummyState::InRoom {
+            room_id: Some(room_id.clone()),
+            room_index: Some(0),
+        }
+    );
+    (host, room_id.trim_start_matches('S').to_string())
+}
+
+async fn join_room(
+    server: &TestServer,
+    host: &SharedDummy,
+    id: &str,
+    name: &str,
+) -> (Arc<SharedDummy>, u32) {
+    join_room_as(server, host, id, name, DummyVersion::Version1_15).await
+}
+
+//加入房间,返回玩家在房间中的编号
+async fn join_room_as(
+    server: &TestServer,
+    host: &SharedDummy,
+    id: &str,
+    name: &str,
+    version: DummyVersion,
+) -> (Arc<SharedDummy>, u32) {
+    let player = connect_as(server, name, version).await;
+    player.join_to_room(id).await;
+
+    let add = recv_message::<ForwardClientAdd>(host).await;
+    assert_eq!(add.ip.as_deref(), Some("127.0.0.1"));
+    //玩家的预注册信息会转发给房主
+    let preregister = recv_message::<PacketForwardClientFrom>(host).await;
+    assert_eq!(preregister.index, add.index);
+    assert_eq!(
+        preregister.packet_type,
+        PacketType::PREREGISTER_INFO_RECEIVE as u32
+    );
+    let info = Packet::new(PacketType::PREREGISTER_INFO_RECEIVE, preregister.payload)
+        .to_message::<PreregisterInfo>()
+        .unwrap();
+    assert_eq!(info.player_name.as_deref(), Some(name));
+    assert_eq!(info.client_version, version.client_version());
+
+    let welcome = recv_message::<Chat>(&player).await;
+    assert_eq!(welcome.sender.as_deref(), Some("RJR Server:"));
+    assert_eq!(
+        player.state(),
+        DummyState::InRoom {
+            room_id: Some(format!("S{}", id)),
+            room_index: None,
+        }
+    );
+    (player, add.index)
+}
+
+async fn room_ids(server: &TestServer) -> Vec<String> {
+    server
+        .connection_mg
+        .get_relay_list()
+        .await
+        .iter()
+        .map(|room| room.shared_data.id.clone())
+        .collect()
+}
+
+#[test]
+fn create_and_join_room() {
+    run_test(|server| async move {
+        let (host, id) = host_room(server, "e2e_host_join").await;
+        assert!(room_ids(server).await.contains(&format!("S{}", id)));
+
+        let (_first, first_index) = join_room(server, &host, &id, "e2e_join_1").await;
+        let (_second, second_index) = join_room(server, &host, &id, "e2e_join_2").await;
+        assert_ne!(first_index, second_index);
+
+        let room = server
+            .connection_mg
+            .get_relay_list()
+            .await
+            .into_iter()
+            .find(|room| room.shared_data.id == format!("S{}", id))
+            .unwrap();
+        let (admin, players) = room.get_room_member().await;
+        assert!(admin.is_some());
+        assert_eq!(
+            players.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![first_index, second_index]
+        );
+    });
+}
+
+//只用来区分版本,不是真实的1.16版本号
+static TEST_CLIENT_VERSION_1_16: u32 = 180;
+
+#[test]
+fn join_newer_host() {
+    run_test(|server| async move {
+        let version = DummyVersion::Version1_16(TEST_CLIENT_VERSION_1_16);
+        let (host, id) = host_room_as(server, "e2e_host_1_16", version).await;
+        let room = server
+            .connection_mg
+            .get_relay_list()
+            .await
+            .into_iter()
+            .find(|room| room.shared_data.id == format!("S{}", id))
+            .unwrap();
+        assert_eq!(
+            DummyVersion::from_client_version(room.shared_data.custom.version),
+            version
+        );
+
+        let (_new_player, _) = join_room_as(server, &host, &id, "e2e_1_16", version).await;
+        let (_old_player, _) =
+            join_room_as(server, &host, &id, "e2e_1_15", DummyVersion::Version1_15).await;
+    });
+}
+
+//发送大厅命令并等待回复
+async fn hall_command(dummy: &SharedDummy, command: &str) -> String {
+    send_command(dummy, command).await;
+    recv_message::<RelayHallMessage>(dummy).await.message
+}
+
+#[test]
+fn relay_hall_commands() {
+    run_test(|server| async move {
+        let player = connect(server, "e2e_hall").await;
+        assert_eq!(hall_command(&player, "help").await, HALL_HELP);
+        assert_eq!(hall_command(&player, "/help").await, HALL_HELP);
+        assert!(hall_command(&player, "what").await.starts_with("未知命令:what"));
+        assert!(hall_command(&player, "new p0").await.contains("人数"));
+        assert_eq!(hall_command(&player, "motd").await, "暂无公告");
+
+        let (_host, id) = host_room(server, "e2e_hall_host").await;
+        let list = hall_command(&player, "list").await;
+        //其他测试的房间可能更多
+        assert!(
+            list.contains(&format!("S{} e2e_hall_host 1/", id)) || list.contains("还有"),
+            "{}",
+            list
+        );
+        let stats = hall_command(&player, "stats").await;
+        assert!(stats.contains("房间总数:"), "{}", stats);
+        assert_eq!(player.state(), DummyState::InHall);
+    });
+}
+
+#[test]
+fn new_room_with_options() {
+    run_test(|server| async move {
+        let host = connect(server, "e2e_hall_small").await;
+        send_command(&host, "new mods p2").await;
+        let id = recv_message::<RelayBecomeServer>(&host)
+            .await
+            .room_id()
+            .expect("room id not found");
+        let room = server
+            .connection_mg
+            .get_relay_list()
+            .await
+            .into_iter()
+            .find(|room| room.shared_data.id == id)
+            .unwrap();
+        assert!(room.shared_data.custom.mods);
+        assert_eq!(room.shared_data.custom.max_player_size, 2);
+
+        //房主和一个玩家后房间已满
+        let player = connect(server, "e2e_hall_join").await;
+        send_command(&player, &format!("join {}", id)).await;
+        let add = recv_message::<ForwardClientAdd>(&host).await;
+        assert_eq!(add.ip.as_deref(), Some("127.0.0.1"));
+        let late = connect(server, "e2e_hall_full").await;
+        let reply = hall_command(&late, &id).await;
+        assert!(reply.contains("已满"), "{}", reply);
+    });
+}
+
+#[test]
+fn join_missing_room() {
+    run_test(|server| async move {
+        let player = connect(server, "e2e_missing").await;
+        player.join_to_room("0").await;
+        let reply = recv_message::<RelayHallMessage>(&player).await;
+        assert!(reply.message.contains("此房间不存在"), "{}", reply.message);
+        assert_eq!(player.state(), DummyState::InHall);
+    });
+}
+
+#[test]
+fn forward_packets_both_ways() {
+    run_test(|server| async move {
+        let (host, id) = host_room(server, "e2e_host_forward").await;
+        let (player, index) = join_room(server, &host, &id, "e2e_forward").await;
+
+        //玩家发出的数据包带上编号转发给房主
+        player
+            .send_packet(Packet::new(PacketType::REGISTER_PLAYER, &b"register"[..]))
+            .await;
+        let from = recv_message::<PacketForwardClientFrom>(&host).await;
+        assert_eq!(from.index, index);
+        assert_eq!(from.packet_type, PacketType::REGISTER_PLAYER as u32);
+        assert_eq!(&from.payload[..], b"register");
+
+        //房主按编号转发给玩家,玩家收到的是原始数据包
+        host.send_packet(Packet::from_message(&PacketForwardClientTo {
+            index,
+            packet_type: PacketType::TICK as u32,
+            payload: (&b"tick"[..]).into(),
+        }))
+        .await;
+        let tick = recv_type(&player, PacketType::TICK).await;
+        assert_eq!(&tick.payload[..], b"tick");
+
+        //转发给不存在的玩家时直接丢弃,房间继续工作
+        host.send_packet(Packet::from_message(&PacketForwardClientTo {
+            index: index + 100,
+            packet_type: PacketType::TICK as u32,
+            payload: (&b"lost"[..]).into(),
+        }))
+        .await;
+        host.send_packet(Packet::from_message(&HeartBeat { time: 42 }))
+            .await;
+        let response = recv_message::<HeartBeatResponse>(&host).await;
+        assert_eq!(response.time, 42);
+    });
+}
+
+#[test]
+fn kick_player() {
+    run_test(|server| async move {
+        let (host, id) = host_room(server, "e2e_host_kick").await;
+        let (player, _) = join_room(server, &host, &id, "e2e_kick").await;
+
+        server
+            .connection_mg
+            .get_connection_by(By::Name("e2e_kick".to_string()))
+            .await
+            .expect("player not found")
+            .kick("测试踢出")
+            .await;
+        let kick = recv_message::<Kick>(&player).await;
+        assert_eq!(kick.reason, "测试踢出");
+        recv_closed(&player).await;
+        assert_eq!(player.state(), DummyState::Disconnected);
+
+        //房主不受影响
+        host.send_packet(Packet::from_message(&HeartBeat { time: 7 }))
+            .await;
+        assert_eq!(recv_message::<HeartBeatResponse>(&host).await.time, 7);
+    });
+}
+
+#[test]
+fn player_disconnect() {
+    run_test(|server| async move {
+        let (host, id) = host_room(server, "e2e_host_disconnect").await;
+        let (player, index) = join_room(server, &host, &id, "e2e_disconnect").await;
+
+        player
+            .send_packet(Packet::new(PacketType::DISCONNECT, &[0u8][..]))
+            .await;
+        recv_closed(&player).await;
+
+        let from = recv_message::<PacketForwardClientFrom>(&host).await;
+        assert_eq!(from.index, index);
+        assert_eq!(from.packet_type, PacketType::DISCONNECT as u32);
+        assert!(server
+            .connection_mg
+            .get_connection_by(By::Name("e2e_disconnect".to_string()))
+            .await
+            .is_none());
+    });
+}
+
+#[test]
+fn host_disconnect_closes_room() {
+    run_test(|server| async move {
+        let (host, id) = host_room(server, "e2e_host_leave").await;
+        let (player, _) = join_room(server, &host, &id, "e2e_host_leave_player").await;
+
+        host.send_packet(Packet::new(PacketType::DISCONNECT, &[0u8][..]))
+            .await;
+        recv_closed(&host).await;
+
+        assert_eq!(recv_message::<Kick>(&player).await.reason, "房主已离开");
+        recv_closed(&player).await;
+        assert!(!room_ids(server).await.contains(&format!("S{}", id)));
+    });
+}
+
+#[test]
+fn close_room() {
+    run_test(|server| async move {
+        let (host, id) = host_room(server, "e2e_host_close").await;
+        let (player, _) = join_room(server, &host, &id, "e2e_close").await;
+
+        assert!(server.connection_mg.close_relay(&id, "测试关闭").await);
+        assert_eq!(recv_message::<Kick>(&player).await.reason, "测试关闭");
+        assert_eq!(recv_message::<Kick>(&host).await.reason, "测试关闭");
+        recv_closed(&player).await;
+        recv_closed(&host).await;
+
+        assert!(!room_ids(server).await.contains(&format!("S{}", id)));
+        assert!(!server.connection_mg.close_relay(&id, "测试关闭").await);
+
+        //房间关闭后不能再加入
+        let late = connect(server, "e2e_close_late").await;
+        send_command(&late, &format!("S{}", id)).await;
+        let reply = recv_message::<RelayHallMessage>(&late).await;
+        assert!(reply.message.contains("此房间不存在"), "{}", reply.message);
+    });
+}
+
+#[test]
+fn dummy_answers_heart_beat() {
+    run_test(|server| async move {
+        let (host, id) = host_room(server, "e2e_host_heart_beat").await;
+        let (_player, index) = join_room(server, &host, &id, "e2e_heart_beat").await;
+
+        //房主发给玩家的心跳由假人自动回复,经服务器转发回房主
+        let mut heart_beat = BytesMut::new();
+        HeartBeat { time: 1234 }.encode(&mut heart_beat);
+        host.send_packet(Packet::from_message(&PacketForwardClientTo {
+            index,
+            packet_type: PacketType::HEART_BEAT as u32,
+            payload: heart_beat.freeze(),
+        }))
+        .await;
+        let from = recv_message::<PacketForwardClientFrom>(&host).await;
+        assert_eq!(from.index, index);
+        let response = Packet::new(PacketType::HEART_BEAT_RESPONSE, from.payload)
+            .to_message::<HeartBeatResponse>()
+            .unwrap();
+        assert_eq!(response.time, 1234);
+    });
+}
+
+#[test]
+fn every_subscriber_sees_packets() {
+    run_test(|server| async move {
+        let host = connect(server, "e2e_subscribe").await;
+        let mut first = host.subscribe();
+        let mut second = host.subscribe();
+        send_command(&host, "new").await;
+
+        for subscriber in [&mut first, &mut second] {
+            let packet = tokio::time::timeout(RECV_TIMEOUT, subscriber.recv())
+                .await
+                .expect("subscriber timeout")
+                .unwrap();
+            assert_eq!(packet.packet_type, PacketType::RELAY_BECOME_SERVER);
+        }
+
+        //订阅者在连接断开后收到Closed
+        drop(host);
+        for subscriber in [&mut first, &mut second] {
+            while tokio::time::timeout(RECV_TIMEOUT, subscriber.recv())
+                .await
+                .expect("subscriber timeout")
+                .is_ok()
+            {}
+        }
+    });
+}
+
+async fn register(player: &SharedDummy) {
+    player
+        .send_packet(Packet::new(PacketType::REGISTER_PLAYER, &b"register"[..]))
+        .await;
+}
+
+//服务器用多个任务处理同一个连接的数据包,房主连续发送的数据包可能乱序到达
+async fn recv_registered(player: &SharedDummy) -> (ServerInfo, TeamList, Chat) {
+    let (mut info, mut team_list, mut welcome) = (None, None, None);
+    while info.is_none() || team_list.is_none() || welcome.is_none() {
+        let packet = recv(player).await.expect("player disconnected");
+        match packet.packet_type {
+            PacketType::SERVER_INFO => info = packet.to_message().ok(),
+            PacketType::TEAM_LIST => team_list = packet.to_message().ok(),
+            PacketType::CHAT => welcome = packet.to_message().ok(),
+            _ => {}
+        }
+    }
+    (info.unwrap(), team_list.unwrap(), welcome.unwrap())
+}
+
+fn team_names(team_list: &TeamList) -> Vec<Option<&str>> {
+    team_list
+        .players
+        .iter()
+        .map(|player| player.as_ref().map(|player| player.name.as_str()))
+        .collect()
+}
+
+#[test]
+fn dummy_host_serves_lobby() {
+    run_test(|server| async move {
+        let lobby = Dummy::new_host(
+            DummyConf::new(
+                "e2e_lobby".to_string(),
+                "com.corrodinggames.rts".to_string(),
+                DummyVersion::Version1_15,
+            ),
+            &server.addr.to_string(),
+            DummyHostConf {
+                max_player: 2,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let id = lobby.room_id.trim_start_matches('S').to_string();
+        assert!(room_ids(server).await.contains(&lobby.room_id));
+
+        let (first, _) = join_room(server, &lobby.dummy, &id, "e2e_lobby_1").await;
+        register(&first).await;
+        let (info, team_list, welcome) = recv_registered(&first).await;
+        assert_eq!(info.map_name, DummyHostConf::default().map_name);
+        assert_eq!(team_list.site, 0);
+        assert_eq!(team_names(&team_list), vec![Some("e2e_lobby_1"), None]);
+        assert_eq!(welcome.message, "欢迎e2e_lobby_1");
+
+        //新玩家注册后所有人都会收到新的玩家列表
+        let (second, _) = join_room(server, &lobby.dummy, &id, "e2e_lobby_2").await;
+        register(&second).await;
+        let (_, team_list, welcome) = recv_registered(&second).await;
+        assert_eq!(team_list.site, 1);
+        assert_eq!(welcome.message, "欢迎e2e_lobby_2");
+        let team_list = recv_message::<TeamList>(&first).await;
+        assert_eq!(
+            team_names(&team_list),
+            vec![Some("e2e_lobby_1"), Some("e2e_lobby_2")]
+        );
+
+        //聊天转发给包括发送者在内的所有玩家
+        second
+            .send_packet(Packet::from_message(&ChatReceive {
+                message: "你好".to_string(),
+            }))
+            .await;
+        for player in [&first, &second] {
+            let chat = recv_message::<Chat>(player).await;
+            assert_eq!(chat.message, "你好");
+            assert_eq!(chat.sender.as_deref(), Some("e2e_lobby_2"));
+        }
+        lobby.send_chat("公告").await;
+        let chat = recv_message::<Chat>(&first).await;
+        assert_eq!(chat.sender.as_deref(), Some("e2e_lobby"));
+
+        //房间已满
+        let (third, _) = join_room(server, &lobby.dummy, &id, "e2e_lobby_3").await;
+        register(&third).await;
+        let kick = recv_message::<Kick>(&third).await;
+        assert_eq!(kick.reason, "房间已满");
+
+        second
+            .send_packet(Packet::new(PacketType::DISCONNECT, &[0u8][..]))
+            .await;
+        let team_list = recv_message::<TeamList>(&first).await;
+        assert_eq!(team_names(&team_list), vec![Some("e2e_lobby_1"), None]);
+        assert_eq!(
+            lobby
+                .players()
+                .into_iter()
+                .map(|player| player.name)
+                .collect::<Vec<_>>(),
+            vec!["e2e_lobby_1".to_string()]
+        );
+    });
+}
+
+fn player_name(shared_con: &SharedConnection) -> String {
+    shared_con
+        .shared_data
+        .player_info
+        .player_name
+        .read()
+        .unwrap()
+        .clone()
+}
+
+//所有测试共用模块管理器,只处理名字以e2e_module开头的玩家
+#[derive(Default)]
+struct TestModule {
+    rooms: Arc<Mutex<Vec<String>>>,
+}
+
+impl Module for TestModule {
+    fn on_room_created(&self, room: &Arc<SharedRelayRoom>) {
+        self.rooms.lock().unwrap().push(room.shared_data.id.clone());
+    }
+
+    fn on_packet(&self, shared_con: &Arc<SharedConnection>, packet: &mut Packet) -> PacketAction {
+        if packet.packet_type != PacketType::REGISTER_PLAYER {
+            return PacketAction::Continue;
+        }
+        match player_name(shared_con).as_str() {
+            "e2e_module_drop" => PacketAction::Drop,
+            "e2e_module_edit" => {
+                *packet = Packet::new(PacketType::REGISTER_PLAYER, &b"edited"[..]);
+                PacketAction::Continue
+            }
+            _ => PacketAction::Continue,
+        }
+    }
+
+    fn on_chat_command(
+        &self,
+        shared_con: &Arc<SharedConnection>,
+        command: &str,
+        args: &[&str],
+    ) -> Option<String> {
+        (command == "echo" && player_name(shared_con).starts_with("e2e_module"))
+            .then(|| args.join(" "))
+    }
+}
+
+struct PanicModule;
+
+impl Module for PanicModule {
+    fn on_packet(&self, shared_con: &Arc<SharedConnection>, packet: &mut Packet) -> PacketAction {
+        if packet.packet_type == PacketType::REGISTER_PLAYER
+            && player_name(shared_con) == "e2e_module_panic"
+        {
+            panic!("test module panic");
+        }
+        PacketAction::Continue
+    }
+}
+
+//不处理数据包的模块,on_packet被调用时会panic并被卸载
+struct NoPacketModule;
+
+impl Module for NoPacketModule {
+    fn handles_packets(&self) -> bool {
+        false
+    }
+
+    fn on_packet(&self, _shared_con: &Arc<SharedConnection>, _packet: &mut Packet) -> PacketAction {
+        panic!("on_packet should not be called");
+    }
+}
+
+//on_start中先加载另一个同名模块,模拟两个加载同时进行
+struct RacingModule {
+    stopped: Arc<AtomicBool>,
+    rival: Mutex<Option<Box<RacingModule>>>,
+}
+
+impl Module for RacingModule {
+    fn on_start(&self, _connection_mg: &Arc<ConnectionManager>) {
+        if let Some(rival) = self.rival.lock().unwrap().take() {
+            MODULE_MANAGER.load("e2e_race", rival).unwrap();
+        }
+    }
+
+    fn on_stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+async fn recv_forwarded(host: &SharedDummy) -> PacketForwardClientFrom {
+    recv_message::<PacketForwardClientFrom>(host).await
+}
+
+#[test]
+fn module_hooks() {
+    run_test(|server| async move {
+        let module = TestModule::default();
+        let rooms = module.rooms.clone();
+        MODULE_MANAGER.load("e2e_hooks", Box::new(module)).unwrap();
+        MODULE_MANAGER
+            .load("e2e_no_packets", Box::new(NoPacketModule))
+            .unwrap();
+
+        let (host, id) = host_room(server, "e2e_module_host").await;
+        assert!(rooms.lock().unwrap().contains(&format!("S{}", id)));
+
+        //被丢弃的数据包不会转发给房主
+        let (dropped, _) = join_room(server, &host, &id, "e2e_module_drop").await;
+        register(&dropped).await;
+        dropped
+            .send_packet(Packet::new(PacketType::ACCEPT_START_GAME, &[0u8][..]))
+            .await;
+        let from = recv_forwarded(&host).await;
+        assert_eq!(from.packet_type, PacketType::ACCEPT_START_GAME as u32);
+
+        let (edited, _) = join_room(server, &host, &id, "e2e_module_edit").await;
+        register(&edited).await;
+        let from = recv_forwarded(&host).await;
+        assert_eq!(from.packet_type, PacketType::REGISTER_PLAYER as u32);
+        assert_eq!(&from.payload[..], b"edited");
+
+        //模块处理的命令只回复给玩家
+        edited
+            .send_packet(Packet::from_message(&ChatReceive {
+                message: "/echo 你好 世界".to_string(),
+            }))
+            .await;
+        let reply = recv_message::<Chat>(&edited).await;
+        assert_eq!(reply.message, "你好 世界");
+        edited
+            .send_packet(Packet::from_message(&ChatReceive {
+                message: "普通聊天".to_string(),
+            }))
+            .await;
+        let from = recv_forwarded(&host).await;
+        assert_eq!(from.packet_type, PacketType::CHAT_RECEIVE as u32);
+
+        MODULE_MANAGER.unload("e2e_hooks").unwrap();
+        assert!(!MODULE_MANAGER.is_loaded("e2e_hooks"));
+        MODULE_MANAGER.unload("e2e_no_packets").unwrap();
+    });
+}
+
+#[test]
+fn concurrent_load_stops_loser() {
+    run_test(|_| async move {
+        let (winner_stopped, loser_stopped) = (Arc::default(), Arc::default());
+        let winner = RacingModule {
+            stopped: Arc::clone(&winner_stopped),
+            rival: Mutex::new(None),
+        };
+        let loser = RacingModule {
+            stopped: Arc::clone(&loser_stopped),
+            rival: Mutex::new(Some(Box::new(winner))),
+        };
+
+        //后完成的实例已经调用过on_start,返回错误前要调用on_stop
+        assert!(matches!(
+            MODULE_MANAGER.load("e2e_race", Box::new(loser)),
+            Err(ModuleError::AlreadyLoaded(_))
+        ));
+        assert!(loser_stopped.load(Ordering::Relaxed));
+        assert!(!winner_stopped.load(Ordering::Relaxed));
+        assert!(MODULE_MANAGER.is_loaded("e2e_race"));
+
+        MODULE_MANAGER.unload("e2e_race").unwrap();
+        assert!(winner_stopped.load(Ordering::Relaxed));
+    });
+}
+
+#[test]
+fn panicking_module_is_unloaded() {
+    run_test(|server| async move {
+        MODULE_MANAGER
+            .load("e2e_panic", Box::new(PanicModule))
+            .unwrap();
+
+        let (host, id) = host_room(server, "e2e_module_panic_host").await;
+        let (player, _) = join_room(server, &host, &id, "e2e_module_panic").await;
+        register(&player).await;
+        //panic的模块被卸载,数据包照常转发
+        let from = recv_forwarded(&host).await;
+        assert_eq!(from.packet_type, PacketType::REGISTER_PLAYER as u32);
+        assert!(!MODULE_MANAGER.is_loaded("e2e_panic"));
+    });
+}
+
+//发送聊天并等待服务器的回复,命令要逐条发送,处理顺序不固定
+async fn chat_command(player: &SharedDummy, message: &str) -> String {
+    player
+        .send_packet(Packet::from_message(&ChatReceive {
+            message: message.to_string(),
+        }))
+        .await;
+    let reply = recv_message::<Chat>(player).await;
+    assert_eq!(reply.sender.as_deref(), Some("RJR Server:"));
+    reply.message
+}
+
+#[test]
+fn builtin_chat_commands() {
+    run_test(|server| async move {
+        let (host, id) = host_room(server, "e2e_chat_host").await;
+        let (player, _) = join_room(server, &host, &id, "e2e_chat_player").await;
+
+        let help = chat_command(&player, "/help").await;
+        for command in ["/help", "/ping", "/room", "/players", "/afk"] {
+            assert!(help.contains(command), "{}", help);
+        }
+        assert_eq!(chat_command(&player, "/ping").await, "pong");
+        let room = chat_command(&player, "/room").await;
+        assert!(room.contains(&format!("S{}", id)), "{}", room);
+        assert!(room.contains("e2e_chat_host"), "{}", room);
+        assert!(room.contains("玩家:2/"), "{}", room);
+
+        assert_eq!(chat_command(&player, "/afk").await, "你已进入暂离状态");
+        let players = chat_command(&player, "/players").await;
+        assert!(players.contains("[房主] e2e_chat_host"), "{}", players);
+        assert!(players.contains("e2e_chat_player (暂离)"), "{}", players);
+        assert_eq!(chat_command(&player, "/afk").await, "你已取消暂离状态");
+
+        //处理过的命令不转发给房主,未知命令和普通聊天照常转发
+        for message in ["/foo bar", "普通聊天"] {
+            player
+                .send_packet(Packet::from_message(&ChatReceive {
+                    message: message.to_string(),
+                }))
+                .await;
+            let from = recv_forwarded(&host).await;
+            assert_eq!(from.packet_type, PacketType::CHAT_RECEIVE as u32);
+            let chat = Packet::new(PacketType::CHAT_RECEIVE, from.payload)
+                .to_message::<ChatReceive>()
+                .unwrap();
+            assert_eq!(chat.message, message);
+        }
+    });
+}
+
+#[test]
+fn wasm_module_chat_command() {
+    run_test(|server| async move {
+        //回复发送命令的玩家名
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "rjr" "player_name" (func $player_name (param i32 i32) (result i32)))
+                (import "rjr" "reply" (func $reply (param i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "on_chat_command") (param $ptr i32) (param $len i32) (result i32)
+                    (local $name_len i32)
+                    (if (i32.ne (i32.load8_u (local.get $ptr)) (i32.const 119))
+                        (then (return (i32.const 0))))
+                    (local.set $name_len (call $player_name (i32.const 2048) (i32.const 64)))
+                    (call $reply (i32.const 2048) (local.get $name_len))
+                    (i32.const 1)))"#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("rjr_e2e_wasm_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("e2e_wasm.wasm");
+        std::fs::write(&file, wasm).unwrap();
+        MODULE_MANAGER.load_file(&file).unwrap();
+        assert!(MODULE_MANAGER.is_loaded("e2e_wasm"));
+
+        let (host, id) = host_room(server, "e2e_wasm_host").await;
+        let (player, _) = join_room(server, &host, &id, "e2e_wasm_player").await;
+        player
+            .send_packet(Packet::from_message(&ChatReceive {
+                message: "/whoami".to_string(),
+            }))
+            .await;
+        let reply = recv_message::<Chat>(&player).await;
+        assert_eq!(reply.message, "e2e_wasm_player");
+
+        MODULE_MANAGER.unload("e2e_wasm").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    });
+}
+
+//等待假人加入房间,返回其客户端信息中的玩家名和在房间中的编号
+async fn recv_joined_name(host: &SharedDummy) -> (String, u32) {
+    let add = recv_message::<ForwardClientAdd>(host).await;
+    let preregister = recv_message::<PacketForwardClientFrom>(host).await;
+    let name = Packet::new(PacketType::PREREGISTER_INFO_RECEIVE, preregister.payload)
+        .to_message::<PreregisterInfo>()
+        .unwrap()
+        .player_name
+        .unwrap_or_default();
+    (name, add.index)
+}
+
+#[test]
+fn rw_engine_joins_opt_in_rooms() {
+    run_test(|server| async move {
+        let engine = RwEngine::with_config(RwEngineConfig {
+            addr: server.addr.to_string(),
+            dummy_name: "e2e_rw_engine".to_string(),
+            ..Default::default()
+        });
+        let joined = engine.joined_rooms();
+        MODULE_MANAGER.load("e2e_rw_engine", Box::new(engine)).unwrap();
+        let is_joined = |id: &str| {
+            joined
+                .lock()
+                .unwrap()
+                .get(id)
+                .is_some_and(|dummy| dummy.is_some())
+        };
+
+        //创建时带engine选项
+        let host = connect(server, "e2e_rwe_option").await;
+        send_command(&host, "new engine").await;
+        let id = recv_message::<RelayBecomeServer>(&host)
+            .await
+            .room_id()
+            .expect("room id not found");
+        assert_eq!(recv_joined_name(&host).await.0, "e2e_rw_engine");
+        while !is_joined(&id) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        //大厅中的人数不计入假人
+        let player = connect(server, "e2e_rwe_hall").await;
+        let list = hall_command(&player, "list").await;
+        assert!(
+            list.contains(&format!("{} e2e_rwe_option 1/", id)) || list.contains("还有"),
+            "{}",
+            list
+        );
+
+        //房主离开后假人也离开
+        drop(host);
+        while joined.lock().unwrap().contains_key(&id) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        //没有选项的房间由房主输入命令后加入
+        let (host, id) = host_room(server, "e2e_rwe_command").await;
+        let (player, index) = join_room(server, &host, &id, "e2e_rwe_player").await;
+        assert!(!joined.lock().unwrap().contains_key(&format!("S{}", id)));
+        let chat = Packet::from_message(&Chat {
+            message: "/engine".to_string(),
+            sender: Some("e2e_rwe_command".to_string()),
+            team: 0,
+        });
+        host.send_packet(Packet::from_message(&PacketForwardClientTo::new(index, &chat)))
+            .await;
+        assert_eq!(recv_joined_name(&host).await.0, "e2e_rw_engine");
+        while !is_joined(&format!("S{}", id)) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        //和大厅一样,房主计入人数,假人不计入
+        assert_eq!(recv_message::<Chat>(&player).await.message, "/engine");
+        let room = chat_command(&player, "/room").await;
+        assert!(room.contains("玩家:2/"), "{}", room);
+
+        MODULE_MANAGER.unload("e2e_rw_engine").unwrap();
+    });
+}
+
+//房主的聊天只转发给index对应的玩家
+async fn host_chat(host: &SharedDummy, index: u32, message: &str) {
+    let chat = Packet::from_message(&Chat {
+        message: message.to_string(),
+        sender: Some(host.conf.dummy_name.clone()),
+        team: 0,
+    });
+    host.send_packet(Packet::from_message(&PacketForwardClientTo::new(index, &chat)))
+        .await;
+}
+
+#[test]
+fn rw_engine_paints_image() {
+    run_test(|server| async move {
+        let dir = std::env::temp_dir().join(format!("rjr_e2e_paint_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbImage::from_pixel(3, 3, image::Rgb([255, 0, 0]))
+            .save(dir.join("small.png"))
+            .unwrap();
+        image::RgbImage::new(40, 40).save(dir.join("big.png")).unwrap();
+
+        let engine = RwEngine::with_config(RwEngineConfig {
+            addr: server.addr.to_string(),
+            dummy_name: "e2e_rw_painter".to_string(),
+            image_dir: dir.to_string_lossy().into_owned(),
+            paint_rate: 1000,
+            ..Default::default()
+        });
+        let joined = engine.joined_rooms();
+        MODULE_MANAGER.load("e2e_rw_painter", Box::new(engine)).unwrap();
+
+        let host = connect(server, "e2e_paint_host").await;
+        send_command(&host, "new engine").await;
+        let id = recv_message::<RelayBecomeServer>(&host)
+            .await
+            .room_id()
+            .expect("room id not found");
+        let (_, index) = recv_joined_name(&host).await;
+        while !joined
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|dummy| dummy.is_some())
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        //只能绘制图片目录下的文件
+        host_chat(&host, index, "/paint ../small.png").await;
+        assert!(recv_message::<Chat>(&host)
+            .await
+            .message
+            .contains("不是有效的图片文件名"));
+
+        //和房主同名的玩家也不能使用命令,命令不转发给房主
+        let (player, player_index) =
+            join_room(server, &host, id.trim_start_matches('S'), "e2e_paint_host").await;
+        assert_eq!(
+            chat_command(&player, " /paint small.png").await,
+            "只有房主可以使用此命令"
+        );
+        player
+            .send_packet(Packet::from_message(&ChatReceive {
+                message: "普通聊天".to_string(),
+            }))
+            .await;
+        let chat = loop {
+            let from = recv_forwarded(&host).await;
+            if from.index == player_index && from.packet_type == PacketType::CHAT_RECEIVE as u32 {
+                break Packet::new(PacketType::CHAT_RECEIVE, from.payload)
+                    .to_message::<ChatReceive>()
+                    .unwrap();
+            }
+        };
+        assert_eq!(chat.message, "普通聊天");
+
+        //缩放到2x2,每格一个地形包
+        host_chat(&host, index, "/paint small.png 2 1 1").await;
+        let terrain = b"SetTerrainTypehsv0009595";
+        let (mut painted, mut done) = (0, false);
+        while painted < 4 || !done {
+            let packet = recv(&host).await.expect("host disconnected");
+            match packet.packet_type {
+                PacketType::PACKET_FORWARD_CLIENT_FROM => {
+                    let forward = packet.to_message::<PacketForwardClientFrom>().unwrap();
+                    assert_eq!(forward.packet_type, PacketType::GAMECOMMAND_RECEIVE as u32);
+                    assert!(forward
+                        .payload
+                        .windows(terrain.len())
+                        .any(|window| window == terrain));
+                    painted += 1;
+                }
+                PacketType::CHAT => {
+                    done |= packet.to_message::<Chat>().unwrap().message == "small.png绘制完成"
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(painted, 4);
+
+        //绘制中可以停止
+        host_chat(&host, index, "/paint big.png").await;
+        assert_eq!(
+            recv_message::<Chat>(&host).await.message,
+            "开始绘制big.png,共1600格"
+        );
+        host_chat(&host, index, "/paint stop").await;
+        loop {
+            let message = recv_message::<Chat>(&host).await.message;
+            assert_ne!(message, "big.png绘制完成");
+            if message == "已停止绘制" {
+                break;
+            }
+        }
+
+        MODULE_MANAGER.unload("e2e_rw_painter").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    });
+}
+
+#[test]
+fn load_test_smoke() {
+    run_test(|server| async move {
+        let report = load_test(LoadTestArgs {
+            target: server.addr.to_string(),
+            rooms: 3,
+            players: 2,
+            duration: Duration::from_secs(2),
+            tick_rate: 20,
+            command_rate: 10,
+            connect_rate: 100,
+            command_size: 64,
+        })
+        .await
+        .unwrap();
+        assert_eq!(report.errors(), 0, "{}", report);
+        assert_eq!(report.rooms, 3);
+        assert_eq!(report.joined, 6);
+        assert!(report.to_player.count > 0, "{}", report);
+        assert!(report.to_host.count > 0, "{}", report);
+    });
+}
+
+#[test]
+fn web_api_endpoints() {
+    let _guard = CONFIG_LOCK.lock().unwrap();
+    run_test(|server| async move {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/api", listener.local_addr().unwrap());
+        let app = web::router(server.connection_mg.clone(), "e2e_token".to_string());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let get_json = |path: &str| {
+            let request = client.get(format!("{}{}", base, path)).bearer_auth("e2e_token");
+            async move {
+                let response = request.send().await.unwrap();
+                assert_eq!(response.status(), 200);
+                serde_json::from_str::<serde_json::Value>(&response.text().await.unwrap()).unwrap()
+            }
+        };
+        let post_json = |path: &str, body: serde_json::Value| {
+            client
+                .post(format!("{}{}", base, path))
+                .bearer_auth("e2e_token")
+                .header("content-type", "application/json")
+                .body(body.to_string())
+                .send()
+        };
+
+        //没有token或token错误
+        let response = client.get(format!("{}/status", base)).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        let response = client
+            .get(format!("{}/status", base))
+            .bearer_auth("e2e_tokem")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let player = connect(server, "e2e_web_player").await;
+        let connections = get_json("/connections").await;
+        let addr = connections
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|connection| connection["player_name"] == "e2e_web_player")
+            .expect("player not listed")["addr"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = post_json("/connections/kick", serde_json::json!({ "addr": addr }))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        recv_closed(&player).await;
+        //连接可能还没有从列表中移除,已结束的连接不能让请求失败
+        let response = post_json("/connections/kick", serde_json::json!({ "addr": addr }))
+            .await
+            .unwrap();
+        assert!(matches!(response.status().as_u16(), 204 | 404));
+
+        //使用测试连接之外的IP,不影响其他测试
+        let response = post_json("/bans", serde_json::json!({ "ip": "10.20.30.40" }))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        assert!(get_json("/bans")
+            .await
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("10.20.30.40")));
+        let unban = || {
+            client
+                .delete(format!("{}/bans/10.20.30.40", base))
+                .bearer_auth("e2e_token")
+                .send()
+        };
+        assert_eq!(unban().await.unwrap().status(), 204);
+        assert_eq!(unban().await.unwrap().status(), 404);
+        assert!(!server
+            .connection_mg
+            .is_banned(&"10.20.30.40".parse().unwrap()));
+    });
+}
+
+#[test]
+fn reload_config_and_save_bans() {
+    let _guard = CONFIG_LOCK.lock().unwrap();
+    run_test(|server| async move {
+        let dir = std::env::temp_dir().join(format!("rjr_e2e_reload_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let write_config = |config: &AllConfig| {
+            let content = format!("# 保留的注释\n{}", toml::to_string(config).unwrap());
+            std::fs::write(&path, content).unwrap();
+        };
+        let banned = |ip: &str| server.connection_mg.is_banned(&ip.parse().unwrap());
+
+        let mut config = AllConfig::default();
+        write_config(&config);
+        init_live_config(&path, config.clone(), ConfigOverride::default());
+        let report = reload_config(&server.connection_mg).await.unwrap();
+        assert!(report.applied.is_empty() && report.restart_required.is_empty());
+
+        //banlist立即生效,server中的修改需要重启
+        config.banlist = vec!["10.9.8.7".parse().unwrap()];
+        config.server.thread_number = 3;
+        write_config(&config);
+        let report = reload_config(&server.connection_mg).await.unwrap();
+        assert_eq!(report.applied, vec!["banlist"]);
+        assert_eq!(report.restart_required, vec!["server.thread_number"]);
+        assert!(banned("10.9.8.7"));
+
+        //运行时添加的封禁写回配置文件,重载后仍然有效
+        server
+            .connection_mg
+            .ban("10.9.8.6".parse().unwrap(), "e2e")
+            .await;
+        save_banlist(&server.connection_mg).await.unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# 保留的注释"));
+        let saved = toml::from_str::<AllConfig>(&content).unwrap();
+        assert_eq!(
+            saved.banlist,
+            vec!["10.9.8.6".parse::<IpAddr>().unwrap(), "10.9.8.7".parse().unwrap()]
+        );
+        assert_eq!(saved.server.thread_number, 3);
+        let report = reload_config(&server.connection_mg).await.unwrap();
+        assert!(report.applied.is_empty(), "{}", report);
+        assert!(banned("10.9.8.6"));
+
+        //从文件中删除后解除封禁
+        config.banlist = Vec::new();
+        write_config(&config);
+        let report = reload_config(&server.connection_mg).await.unwrap();
+        assert_eq!(report.applied, vec!["banlist"]);
+        assert!(!banned("10.9.8.6") && !banned("10.9.8.7"));
+
+        init_live_config(Path::new(""), AllConfig::default(), ConfigOverride::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+    });
+}
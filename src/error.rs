@@ -3,7 +3,10 @@ use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::mpsc;
 
-use crate::{connection::shared_connection::SharedConnection, packet::Packet};
+use crate::{
+    connection::shared_connection::SharedConnection,
+    packet::{Packet, PacketType},
+};
 
 #[derive(Error, Debug)]
 pub enum ConnectionError {
@@ -14,10 +17,69 @@ pub enum ConnectionError {
 #[derive(Error, Debug)]
 pub enum ReceiverError {
     #[error("`{0}`")]
-    InvalidInput(String)
+    InvalidInput(String),
+    #[error("read packet error: {0}")]
+    Io(#[from] std::io::Error),
 }
 #[derive(Error, Debug)]
 pub enum DummyError {
     #[error("join to relay error mag: {0}")]
     JoinRelayError(#[from] std::io::Error),
+    #[error("dummy timeout while waiting for {0}")]
+    Timeout(&'static str),
+    #[error("dummy disconnected by relay")]
+    Disconnected,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ModuleError {
+    #[error("unknown module `{0}`")]
+    Unknown(String),
+    #[error("module `{0}` is already loaded")]
+    AlreadyLoaded(String),
+    #[error("module `{0}` is not loaded")]
+    NotLoaded(String),
+    #[error("module system is not started")]
+    NotStarted,
+    #[error("module `{0}` panicked while starting")]
+    StartPanic(String),
+    #[error("invalid module `{0}`: {1}")]
+    Invalid(String, String),
+}
+
+#[derive(Error, Debug)]
+pub enum WebError {
+    #[error("invalid or missing token")]
+    Unauthorized,
+    #[error("`{0}` not found")]
+    NotFound(String),
+    #[error("`{0}`")]
+    InvalidInput(String),
+}
+
+#[derive(Error, Debug)]
+pub enum ProxyProtocolError {
+    #[error("read proxy header error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("missing proxy header")]
+    Missing,
+    #[error("invalid proxy header: {0}")]
+    Invalid(String),
+    #[error("read proxy header timeout")]
+    Timeout,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PacketDecodeError {
+    #[error("unexpected end of packet")]
+    UnexpectedEof,
+    #[error("expected {expected:?} packet, got {actual:?}")]
+    WrongType {
+        expected: PacketType,
+        actual: PacketType,
+    },
+    #[error("unsupported protocol version {0}")]
+    UnsupportedProtocol(u8),
+    #[error("invalid compressed data")]
+    InvalidCompressedData,
 }
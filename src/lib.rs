@@ -0,0 +1,366 @@
+#[macro_use]
+extern crate lazy_static;
+
+mod connection;
+mod connection_manager;
+mod core;
+mod data;
+mod error;
+mod packet;
+mod relay_manager;
+mod server;
+mod uplist;
+mod worker_pool;
+mod command_center;
+mod dummy;
+mod module;
+mod event;
+pub mod logger;
+mod web;
+pub mod cli;
+mod cluster;
+mod capture;
+pub mod load_test;
+mod chat_command;
+#[cfg(test)]
+mod e2e;
+
+
+
+lazy_static! {
+    static ref NOW: std::time::Instant = Instant::now();
+}
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    data::START_INFO,
+    event::init_event_system,
+    server::{
+        config::*,
+        reload::{init_live_config, watch_config},
+    },
+    uplist::apply_uplist_config,
+};
+use capture::{
+    apply_capture_config,
+    format::{read_capture, Direction},
+};
+use packet::decoder::decode_packet;
+use dummy::host::start_lobbies;
+use module::MODULE_MANAGER;
+
+use cluster::Cluster;
+use connection_manager::ConnectionManager;
+use cli::{ReplayArgs, EXIT_CONFIG_ERROR, EXIT_OK, EXIT_START_ERROR};
+use log::{debug, error, info, warn};
+use crate::command_center::command_center;
+
+use relay_manager::SharedRelayManager;
+use error::ProxyProtocolError;
+use server::{
+    listener::bind_listener,
+    proxy_protocol::{read_proxy_header, PROXY_HEADER_TIMEOUT},
+    AllConfig, ListenConfig,
+};
+
+use tokio::net::{TcpListener, TcpStream};
+
+pub async fn run(config_path: Option<PathBuf>, config_override: ConfigOverride) -> i32 {
+    logger::init_shell().unwrap();
+    // 加载配置文件并初始化终端
+    // 完成初始化后开始启动服务器
+    //
+
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    let config_path = resolve_config_path(config_path);
+
+    info!(
+        "当前启动目录:{}\n          配置文件路径:{}",
+        current_dir.display(),
+        config_path.display()
+    );
+
+    let res = match load_config(&config_path).await.and_then(|mut res| {
+        config_override.apply(&mut res);
+        res.validate(&config_path, &config_override)?;
+        Ok(res)
+    }) {
+        Ok(res) => res,
+        Err(e) => {
+            error!("{}", e);
+            return EXIT_CONFIG_ERROR;
+        }
+    };
+
+    for warning in res.warnings() {
+        warn!("{}", warning);
+    }
+
+    if let Err(e) = logger::apply_log_config(&res.log) {
+        error!("应用日志配置失败:{}", e);
+        return EXIT_CONFIG_ERROR;
+    }
+    let res = init_live_config(&config_path, res, config_override);
+
+    println!("{}", START_INFO);
+    info!("加载中.....");
+    info!("将从如下配置启动\n{}", res);
+
+    init_event_system().expect("event system init error");
+
+    let shared_connection_mg = match start_server(&res).await {
+        Ok(shared_connection_mg) => shared_connection_mg,
+        Err(e) => {
+            error!("启动服务器失败:{}", e);
+            return EXIT_START_ERROR;
+        }
+    };
+
+    if res.server.enable_web {
+        if let Err(e) = web::start_web(res.web.clone(), shared_connection_mg.clone()).await {
+            error!("启动Web API失败:{}", e);
+            //运行时不能在异步上下文中drop,直接退出
+            std::process::exit(EXIT_START_ERROR);
+        }
+    }
+
+    MODULE_MANAGER.start(&res.module, shared_connection_mg.clone());
+
+    if !res.lobby.is_empty() {
+        start_lobbies(&res.lobby, res.server.local_addr());
+    }
+
+    apply_uplist_config(&res.uplist);
+    if let Err(e) = apply_capture_config(&res.capture) {
+        error!("开启抓包失败:{}", e);
+    }
+
+    if res.server.config_watch_secs > 0 {
+        watch_config(
+            shared_connection_mg.clone(),
+            Duration::from_secs(res.server.config_watch_secs),
+        );
+    }
+
+    command_center(shared_connection_mg).await;
+    EXIT_OK
+}
+
+pub async fn check_config(config_path: Option<PathBuf>) -> i32 {
+    let config_path = resolve_config_path(config_path);
+    match read_config(&config_path)
+        .await
+        .and_then(|res| {
+            res.validate(&config_path, &ConfigOverride::default())
+                .map(|_| res)
+        })
+    {
+        Ok(res) => {
+            for warning in res.warnings() {
+                println!("警告: {}", warning);
+            }
+            println!("配置文件{}检查通过\n{}", config_path.display(), res);
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_CONFIG_ERROR
+        }
+    }
+}
+
+pub async fn replay_capture(replay_args: ReplayArgs) -> i32 {
+    logger::init_shell().unwrap();
+    match capture::replay::replay(&replay_args.file, &replay_args.target, replay_args.speed).await {
+        Ok(_) => EXIT_OK,
+        Err(e) => {
+            error!("回放失败:{}", e);
+            EXIT_START_ERROR
+        }
+    }
+}
+
+pub fn dump_capture(file: &Path, full: bool) -> i32 {
+    let records = match std::fs::File::open(file)
+        .and_then(|file| read_capture(&mut std::io::BufReader::new(file)))
+    {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("读取抓包文件{}失败:{}", file.display(), e);
+            return EXIT_CONFIG_ERROR;
+        }
+    };
+    for record in records {
+        let decoded = decode_packet(record.packet_type, &record.payload);
+        println!(
+            "{} {} {} {} {}",
+            record.timestamp_ms,
+            match record.direction {
+                Direction::In => "->",
+                Direction::Out => "<-",
+            },
+            record.addr,
+            record.room_id.as_deref().unwrap_or("-"),
+            if full {
+                format!("{:#}", decoded)
+            } else {
+                decoded.to_string()
+            }
+        );
+    }
+    EXIT_OK
+}
+
+pub fn print_default_config() -> i32 {
+    match default_config_content() {
+        Ok(default) => {
+            print!("{}", default);
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("默认配置转换TOML失败:{}", e);
+            EXIT_CONFIG_ERROR
+        }
+    }
+}
+
+async fn start_server(config: &AllConfig) -> anyhow::Result<Arc<ConnectionManager>> {
+    let server_config = config.server.clone();
+    //先绑定端口,失败时还没有创建任何运行时,可以直接返回
+    //单个地址绑定失败只记录错误,全部失败时才放弃启动
+    let mut listeners = Vec::new();
+    let mut bind_failed = 0;
+    for listen_config in server_config.listen_list() {
+        for port_range in listen_config.port_range.iter() {
+            let mut bind_success = 0;
+            for port in port_range.0..=port_range.1 {
+                let listen_addr = SocketAddr::new(listen_config.addr, port as u16);
+                match bind_listener(listen_addr, &listen_config) {
+                    Ok(listener) => {
+                        bind_success += 1;
+                        listeners.push((listener, listen_config.clone()));
+                    }
+                    Err(e) => {
+                        bind_failed += 1;
+                        error!(addr:% = listen_addr; "监听{}失败:{}", listen_addr, e);
+                    }
+                }
+            }
+            if bind_success > 0 {
+                info!("{} {:?}范围内的Accepter注册成功", listen_config.addr, port_range);
+            }
+        }
+    }
+    if listeners.is_empty() {
+        anyhow::bail!("没有可用的监听地址");
+    }
+    if bind_failed > 0 {
+        warn!("{}个地址监听失败,已跳过", bind_failed);
+    }
+
+    serve(config, listeners).await
+}
+
+//在已经绑定好的端口上启动服务器,测试时传入临时端口
+async fn serve(
+    config: &AllConfig,
+    listeners: Vec<(TcpListener, ListenConfig)>,
+) -> anyhow::Result<Arc<ConnectionManager>> {
+    let cluster = if config.cluster.enable {
+        Some(Cluster::start(config.cluster.clone()).await?)
+    } else {
+        None
+    };
+
+    let shared_relay_mg = SharedRelayManager::new(10, cluster).await;
+    let shared_connection_mg = Arc::new(
+        ConnectionManager::new(
+            config.server.clone(),
+            config.banlist.clone(),
+            shared_relay_mg.clone(),
+        )
+        .await,
+    );
+
+    for (listener, listen_config) in listeners {
+        tokio::spawn(init_accepter(
+            listener,
+            listen_config,
+            shared_connection_mg.clone(),
+        ));
+    }
+    //准备IP地址信息
+
+    Ok(shared_connection_mg)
+}
+
+async fn init_accepter(
+    listener: TcpListener,
+    listen_config: ListenConfig,
+    connection_mg: Arc<ConnectionManager>,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(new_connection) => new_connection,
+            Err(e) => {
+                //如文件描述符耗尽,稍后重试而不是停止监听
+                warn!("接受连接失败:{}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        if listen_config.nodelay {
+            let _ = stream.set_nodelay(true);
+        }
+
+        if listen_config.proxy_protocol {
+            //等待头部时不能阻塞后续连接的accept
+            let connection_mg = connection_mg.clone();
+            tokio::spawn(async move {
+                accept_proxy_connection(stream, addr, connection_mg).await;
+            });
+        } else {
+            accept_connection(stream, addr, &connection_mg).await;
+        }
+    }
+}
+
+async fn accept_proxy_connection(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    connection_mg: Arc<ConnectionManager>,
+) {
+    let header = tokio::time::timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(&mut stream))
+        .await
+        .unwrap_or(Err(ProxyProtocolError::Timeout));
+    match header {
+        Ok(Some(real_addr)) => {
+            debug!(addr:% = real_addr, proxy:% = addr; "PROXY protocol头部解析成功");
+            accept_connection(stream, real_addr, &connection_mg).await;
+        }
+        Ok(None) => accept_connection(stream, addr, &connection_mg).await,
+        Err(e) => warn!(proxy:% = addr; "PROXY protocol头部解析失败,已断开:{}", e),
+    }
+}
+
+async fn accept_connection(
+    stream: TcpStream,
+    mut addr: SocketAddr,
+    connection_mg: &ConnectionManager,
+) {
+    //双栈监听时IPv4连接的地址形如::ffff:1.2.3.4,统一转换为IPv4
+    addr.set_ip(addr.ip().to_canonical());
+
+    if connection_mg.is_banned(&addr.ip()) {
+        info!(addr:% = addr; "已拒绝被封禁的连接");
+        return;
+    }
+    debug!(addr:% = addr; "新连接");
+    connection_mg.new_connection((stream, addr)).await;
+}
@@ -0,0 +1,588 @@
+//压力测试:用Dummy模拟大量房主和玩家,房主通过PACKET_FORWARD_CLIENT_TO发送TICK,玩家发送GAMECOMMAND
+//数据包里带有发送时间,收到后计算经过服务器转发的延迟
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use log::{debug, info, warn};
+use tokio::{
+    sync::{broadcast, Mutex as AsyncMutex},
+    task::JoinSet,
+    time::{Instant, Interval, MissedTickBehavior},
+};
+
+use crate::{
+    cli::LoadTestArgs,
+    dummy::{shared_dummy::SharedDummy, Dummy, DummyConf, DummyState, DummyVersion},
+    packet::{
+        common_packet::CommonPacket,
+        message::{ForwardClientAdd, PacketForwardClientFrom, PacketForwardClientTo},
+        Packet, PacketType,
+    },
+};
+
+//建立连接、创建和加入房间的最长等待时间
+static SETUP_TIMEOUT: Duration = Duration::from_secs(10);
+//运行期间输出统计的间隔
+static REPORT_INTERVAL: Duration = Duration::from_secs(5);
+//延迟直方图每个2的幂区间再分成的格数,误差不超过1/16
+const LATENCY_SUB_BUCKETS: usize = 16;
+const LATENCY_BUCKETS: usize = LATENCY_SUB_BUCKETS * 61;
+
+#[derive(Debug, Default)]
+struct LoadStats {
+    connected: AtomicU64,
+    connect_failed: AtomicU64,
+    rooms: AtomicU64,
+    room_failed: AtomicU64,
+    joined: AtomicU64,
+    join_failed: AtomicU64,
+    //测试结束前被服务器断开
+    disconnected: AtomicU64,
+    //已加入房间但房主没有收到FORWARD_CLIENT_ADD,不会收到TICK
+    lost_players: AtomicU64,
+    //接收太慢被订阅丢弃的数据包
+    lagged: AtomicU64,
+    sent: AtomicU64,
+    received: AtomicU64,
+    received_bytes: AtomicU64,
+    to_player: LatencyHistogram,
+    to_host: LatencyHistogram,
+}
+
+impl LoadStats {
+    fn record_latency(&self, histogram: &LatencyHistogram, payload: &Bytes, start: Instant) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.received_bytes
+            .fetch_add(payload.len() as u64, Ordering::Relaxed);
+        if let Some(sent_at) = read_timestamp(payload) {
+            histogram.record(elapsed_micros(start).saturating_sub(sent_at));
+        }
+    }
+}
+
+//延迟直方图,单位微秒,占用的内存不随样本数增长
+//小于16的值单独计数,之后每个2的幂区间分成16格
+#[derive(Debug)]
+struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    max: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: (0..LATENCY_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn record(&self, micros: u64) {
+        self.buckets[Self::bucket(micros)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn bucket(micros: u64) -> usize {
+        if micros < LATENCY_SUB_BUCKETS as u64 {
+            return micros as usize;
+        }
+        let exp = 63 - micros.leading_zeros() as usize;
+        let sub = (micros >> (exp - 4)) as usize & (LATENCY_SUB_BUCKETS - 1);
+        (exp - 3) * LATENCY_SUB_BUCKETS + sub
+    }
+
+    //格中最大的值
+    fn bucket_upper(bucket: usize) -> u64 {
+        if bucket < LATENCY_SUB_BUCKETS {
+            return bucket as u64;
+        }
+        let exp = bucket / LATENCY_SUB_BUCKETS + 3;
+        let sub = (bucket % LATENCY_SUB_BUCKETS) as u64;
+        let width = 1 << (exp - 4);
+        (LATENCY_SUB_BUCKETS as u64 + sub) * width + (width - 1)
+    }
+
+    fn latency(&self) -> Latency {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let count = counts.iter().sum::<u64>();
+        let max = self.max.load(Ordering::Relaxed);
+        let percentile = |p: f64| {
+            let rank = (count.saturating_sub(1) as f64 * p).round() as u64;
+            let mut seen = 0;
+            for (bucket, bucket_count) in counts.iter().enumerate() {
+                seen += bucket_count;
+                if seen > rank {
+                    return Duration::from_micros(Self::bucket_upper(bucket).min(max));
+                }
+            }
+            Duration::ZERO
+        };
+        Latency {
+            count: count as usize,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max: Duration::from_micros(max),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Latency {
+    pub count: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}个 p50 {:?} p90 {:?} p99 {:?} max {:?}",
+            self.count, self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub elapsed: Duration,
+    pub connected: u64,
+    pub connect_failed: u64,
+    pub rooms: u64,
+    pub room_failed: u64,
+    pub joined: u64,
+    pub join_failed: u64,
+    pub disconnected: u64,
+    pub lost_players: u64,
+    pub lagged: u64,
+    pub sent: u64,
+    pub received: u64,
+    pub received_bytes: u64,
+    pub to_player: Latency,
+    pub to_host: Latency,
+}
+
+impl LoadReport {
+    pub fn errors(&self) -> u64 {
+        self.connect_failed
+            + self.room_failed
+            + self.join_failed
+            + self.disconnected
+            + self.lost_players
+            + self.lagged
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        writeln!(f, "耗时{:?}", self.elapsed)?;
+        writeln!(
+            f,
+            "连接 成功{} 失败{} 被断开{}",
+            self.connected, self.connect_failed, self.disconnected
+        )?;
+        writeln!(
+            f,
+            "房间 创建{} 失败{},玩家 加入{} 失败{}",
+            self.rooms, self.room_failed, self.joined, self.join_failed
+        )?;
+        writeln!(
+            f,
+            "未收到编号的玩家{},接收太慢丢弃的数据包{}",
+            self.lost_players, self.lagged
+        )?;
+        writeln!(
+            f,
+            "数据包 发送{} 接收{},接收速率{:.0}个/s {:.1}KiB/s",
+            self.sent,
+            self.received,
+            self.received as f64 / secs,
+            self.received_bytes as f64 / 1024. / secs
+        )?;
+        writeln!(f, "房主->玩家延迟 {}", self.to_player)?;
+        write!(f, "玩家->房主延迟 {}", self.to_host)
+    }
+}
+
+struct LoadTest {
+    args: LoadTestArgs,
+    stats: LoadStats,
+    start: Instant,
+    deadline: Instant,
+    //按connect_rate限制建立连接的速度
+    connect_interval: AsyncMutex<Interval>,
+}
+
+//按参数创建房间和玩家并持续发送数据,结束后返回统计结果
+pub async fn load_test(args: LoadTestArgs) -> anyhow::Result<LoadReport> {
+    ensure_localhost(&args.target).await?;
+
+    let start = Instant::now();
+    let mut connect_interval = tokio::time::interval(Duration::from_secs(1) / args.connect_rate);
+    connect_interval.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    let test = Arc::new(LoadTest {
+        deadline: start + args.duration,
+        args,
+        stats: LoadStats::default(),
+        start,
+        connect_interval: AsyncMutex::new(connect_interval),
+    });
+    info!(
+        "开始压力测试:{}个房间,每个房间{}个玩家,持续{:?}",
+        test.args.rooms, test.args.players, test.args.duration
+    );
+
+    let mut rooms = JoinSet::new();
+    for room in 0..test.args.rooms {
+        rooms.spawn(run_room(test.clone(), room));
+    }
+
+    let mut report = tokio::time::interval_at(start + REPORT_INTERVAL, REPORT_INTERVAL);
+    let mut last_received = 0;
+    loop {
+        tokio::select! {
+            joined = rooms.join_next() => {
+                if joined.is_none() {
+                    break;
+                }
+            }
+            _ = report.tick() => {
+                let received = test.stats.received.load(Ordering::Relaxed);
+                info!(
+                    "连接{} 房间{} 玩家{} 已发送{} 已接收{} 接收速率{:.0}个/s",
+                    test.stats.connected.load(Ordering::Relaxed),
+                    test.stats.rooms.load(Ordering::Relaxed),
+                    test.stats.joined.load(Ordering::Relaxed),
+                    test.stats.sent.load(Ordering::Relaxed),
+                    received,
+                    (received - last_received) as f64 / REPORT_INTERVAL.as_secs_f64()
+                );
+                last_received = received;
+            }
+        }
+    }
+
+    let stats = &test.stats;
+    Ok(LoadReport {
+        elapsed: start.elapsed(),
+        connected: stats.connected.load(Ordering::Relaxed),
+        connect_failed: stats.connect_failed.load(Ordering::Relaxed),
+        rooms: stats.rooms.load(Ordering::Relaxed),
+        room_failed: stats.room_failed.load(Ordering::Relaxed),
+        joined: stats.joined.load(Ordering::Relaxed),
+        join_failed: stats.join_failed.load(Ordering::Relaxed),
+        disconnected: stats.disconnected.load(Ordering::Relaxed),
+        lost_players: stats.lost_players.load(Ordering::Relaxed),
+        lagged: stats.lagged.load(Ordering::Relaxed),
+        sent: stats.sent.load(Ordering::Relaxed),
+        received: stats.received.load(Ordering::Relaxed),
+        received_bytes: stats.received_bytes.load(Ordering::Relaxed),
+        to_player: stats.to_player.latency(),
+        to_host: stats.to_host.latency(),
+    })
+}
+
+//压力测试产生的流量很大,只允许对本机进行
+async fn ensure_localhost(target: &str) -> anyhow::Result<()> {
+    let addrs = tokio::net::lookup_host(target).await?.collect::<Vec<_>>();
+    if addrs.is_empty() || addrs.iter().any(|addr| !addr.ip().is_loopback()) {
+        anyhow::bail!("只能对本机进行压力测试,{}不是本机地址", target);
+    }
+    Ok(())
+}
+
+async fn run_room(test: Arc<LoadTest>, room: usize) {
+    let Some(host) = connect(&test, format!("load-host-{}", room)).await else {
+        return;
+    };
+    host.send_packet(CommonPacket::get_relay_hall_command("new"))
+        .await;
+    let room_id = match wait_in_room(&host).await {
+        Some(DummyState::InRoom {
+            room_id: Some(room_id),
+            ..
+        }) => room_id,
+        _ => {
+            warn!("房间{}创建失败", room);
+            test.stats.room_failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    test.stats.rooms.fetch_add(1, Ordering::Relaxed);
+    debug!(room_id = room_id.as_str(); "压力测试房间已创建");
+    //在玩家加入前订阅,FORWARD_CLIENT_ADD被丢弃时能够发现
+    let mut host_rx = host.subscribe();
+
+    let mut players = JoinSet::new();
+    for player in 0..test.args.players {
+        let test = test.clone();
+        let room_id = room_id.clone();
+        players.spawn(async move {
+            let player = connect(&test, format!("load-{}-{}", room, player)).await?;
+            player.join_to_room(&room_id).await;
+            match wait_in_room(&player).await {
+                Some(DummyState::InRoom { .. }) => {
+                    test.stats.joined.fetch_add(1, Ordering::Relaxed);
+                    Some(player)
+                }
+                _ => {
+                    test.stats.join_failed.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            }
+        });
+    }
+
+    //房主按玩家编号转发,编号从FORWARD_CLIENT_ADD中获得
+    let mut indexes = Vec::new();
+    let mut joined = Vec::new();
+    while let Some(player) = players.join_next().await {
+        if let Ok(Some(player)) = player {
+            joined.push(player);
+        }
+    }
+    while indexes.len() < joined.len() {
+        match tokio::time::timeout(SETUP_TIMEOUT, recv_counted(&test, &mut host_rx)).await {
+            Ok(Some(packet)) => {
+                if let Ok(add) = packet.to_message::<ForwardClientAdd>() {
+                    indexes.push(add.index);
+                }
+            }
+            _ => break,
+        }
+    }
+    if indexes.len() < joined.len() {
+        let lost = joined.len() - indexes.len();
+        warn!(room_id = room_id.as_str(); "房主没有收到{}个玩家的编号", lost);
+        test.stats
+            .lost_players
+            .fetch_add(lost as u64, Ordering::Relaxed);
+    }
+
+    let mut tasks = JoinSet::new();
+    tasks.spawn(send_ticks(test.clone(), host.clone(), indexes));
+    tasks.spawn(receive(test.clone(), host.clone(), host_rx, true));
+    for player in joined {
+        let player_rx = player.subscribe();
+        tasks.spawn(send_commands(test.clone(), player.clone()));
+        tasks.spawn(receive(test.clone(), player, player_rx, false));
+    }
+    while tasks.join_next().await.is_some() {}
+}
+
+async fn connect(test: &LoadTest, name: String) -> Option<Arc<SharedDummy>> {
+    test.connect_interval.lock().await.tick().await;
+    let conf = DummyConf::new(
+        name,
+        "com.corrodinggames.rts".to_string(),
+        DummyVersion::Version1_15,
+    );
+    let dummy = match Dummy::new_shared(conf, &test.args.target).await {
+        Ok(dummy) => dummy,
+        Err(e) => {
+            debug!("压力测试连接失败:{}", e);
+            test.stats.connect_failed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+    };
+    dummy.connect_to_relay().await;
+    let in_hall = tokio::time::timeout(
+        SETUP_TIMEOUT,
+        dummy.wait_state(|state| matches!(state, DummyState::InHall | DummyState::Disconnected)),
+    )
+    .await;
+    if in_hall != Ok(DummyState::InHall) {
+        test.stats.connect_failed.fetch_add(1, Ordering::Relaxed);
+        return None;
+    }
+    test.stats.connected.fetch_add(1, Ordering::Relaxed);
+    Some(dummy)
+}
+
+async fn wait_in_room(dummy: &SharedDummy) -> Option<DummyState> {
+    tokio::time::timeout(
+        SETUP_TIMEOUT,
+        dummy.wait_state(|state| {
+            matches!(state, DummyState::InRoom { .. } | DummyState::Disconnected)
+        }),
+    )
+    .await
+    .ok()
+}
+
+//房主按tick_rate给每个玩家发送TICK
+async fn send_ticks(test: Arc<LoadTest>, host: Arc<SharedDummy>, indexes: Vec<u32>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / test.args.tick_rate);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut tick = 0u32;
+    while interval.tick().await < test.deadline {
+        if host.state() == DummyState::Disconnected {
+            return;
+        }
+        //TICK的内容是帧号和指令数,前面加上发送时间
+        let mut payload = BytesMut::new();
+        payload.put_u64(elapsed_micros(test.start));
+        payload.put_u32(tick);
+        payload.put_u32(0);
+        let payload = payload.freeze();
+
+        let packets = indexes
+            .iter()
+            .map(|index| {
+                Packet::from_message(&PacketForwardClientTo {
+                    index: *index,
+                    packet_type: PacketType::TICK as u32,
+                    payload: payload.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        test.stats
+            .sent
+            .fetch_add(packets.len() as u64, Ordering::Relaxed);
+        host.send_packets(packets).await;
+        tick = tick.wrapping_add(1);
+    }
+}
+
+//玩家按command_rate发送GAMECOMMAND,服务器转发给房主
+async fn send_commands(test: Arc<LoadTest>, player: Arc<SharedDummy>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / test.args.command_rate);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    while interval.tick().await < test.deadline {
+        if player.state() == DummyState::Disconnected {
+            return;
+        }
+        let mut payload = BytesMut::with_capacity(8 + test.args.command_size);
+        payload.put_u64(elapsed_micros(test.start));
+        payload.put_bytes(0, test.args.command_size);
+        test.stats.sent.fetch_add(1, Ordering::Relaxed);
+        player
+            .send_packet(Packet::new(PacketType::GAMECOMMAND_RECEIVE, payload))
+            .await;
+    }
+}
+
+//接收太慢时broadcast会丢弃数据包,丢弃的数量计入lagged,连接断开后返回None
+async fn recv_counted(
+    test: &LoadTest,
+    packet_rx: &mut broadcast::Receiver<Packet>,
+) -> Option<Packet> {
+    loop {
+        match packet_rx.recv().await {
+            Ok(packet) => return Some(packet),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                test.stats.lagged.fetch_add(skipped, Ordering::Relaxed);
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+//接收转发过来的数据包并记录延迟,到截止时间后结束
+async fn receive(
+    test: Arc<LoadTest>,
+    dummy: Arc<SharedDummy>,
+    mut packet_rx: broadcast::Receiver<Packet>,
+    is_host: bool,
+) {
+    loop {
+        let packet =
+            match tokio::time::timeout_at(test.deadline, recv_counted(&test, &mut packet_rx)).await
+            {
+                Ok(Some(packet)) => packet,
+                Ok(None) => {
+                    debug!("{}被服务器断开", dummy.conf.dummy_name);
+                    test.stats.disconnected.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                Err(_) => return,
+            };
+        match packet.packet_type {
+            PacketType::PACKET_FORWARD_CLIENT_FROM if is_host => {
+                if let Ok(from) = packet.to_message::<PacketForwardClientFrom>() {
+                    if from.packet_type == PacketType::GAMECOMMAND_RECEIVE as u32 {
+                        test.stats
+                            .record_latency(&test.stats.to_host, &from.payload, test.start);
+                    }
+                }
+            }
+            PacketType::TICK if !is_host => {
+                test.stats
+                    .record_latency(&test.stats.to_player, &packet.payload, test.start)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn elapsed_micros(start: Instant) -> u64 {
+    start.elapsed().as_micros() as u64
+}
+
+fn read_timestamp(payload: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(payload.get(..8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_percentiles() {
+        let histogram = LatencyHistogram::default();
+        for micros in (1..=100).rev() {
+            histogram.record(micros);
+        }
+        let latency = histogram.latency();
+        assert_eq!(latency.count, 100);
+        //51在[50,51]格中,90在[88,91]格中,99在[96,99]格中
+        assert_eq!(latency.p50, Duration::from_micros(51));
+        assert_eq!(latency.p90, Duration::from_micros(91));
+        assert_eq!(latency.p99, Duration::from_micros(99));
+        assert_eq!(latency.max, Duration::from_micros(100));
+        assert_eq!(LatencyHistogram::default().latency().count, 0);
+    }
+
+    #[test]
+    fn latency_buckets() {
+        for micros in (0..100_000).chain([u64::MAX / 3, u64::MAX]) {
+            let bucket = LatencyHistogram::bucket(micros);
+            let upper = LatencyHistogram::bucket_upper(bucket);
+            assert!(bucket < LATENCY_BUCKETS);
+            assert!(micros <= upper, "{} > {}", micros, upper);
+            assert!(upper - micros <= micros / 8, "{} {}", micros, upper);
+            if bucket > 0 {
+                assert!(LatencyHistogram::bucket_upper(bucket - 1) < micros);
+            }
+        }
+    }
+
+    #[test]
+    fn only_localhost() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            assert!(ensure_localhost("127.0.0.1:5123").await.is_ok());
+            assert!(ensure_localhost("[::1]:5123").await.is_ok());
+            assert!(ensure_localhost("10.0.0.1:5123").await.is_err());
+        });
+    }
+}
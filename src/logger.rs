@@ -0,0 +1,177 @@
+mod rotation;
+
+use std::{path::Path, sync::RwLock};
+
+use fern::colors::{Color, ColoredLevelConfig};
+use log::{
+    kv::{Key, Value, VisitSource},
+    Log, Metadata, Record,
+};
+
+use crate::server::{LogConfig, LogFormat};
+
+use self::rotation::RotatingFile;
+
+lazy_static! {
+    static ref LOGGER: ReloadableLogger = ReloadableLogger {
+        inner: RwLock::new(None),
+    };
+}
+
+//全局只能注册一次logger,所以注册的是这个壳子
+//读取配置文件之后再把真正的Dispatch换进来
+struct ReloadableLogger {
+    inner: RwLock<Option<Box<dyn Log>>>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner
+            .read()
+            .map(|inner| inner.as_ref().is_some_and(|log| log.enabled(metadata)))
+            .unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        if let Ok(inner) = self.inner.read() {
+            if let Some(log) = inner.as_ref() {
+                log.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(inner) = self.inner.read() {
+            if let Some(log) = inner.as_ref() {
+                log.flush();
+            }
+        }
+    }
+}
+
+//读取配置前使用的终端输出
+pub fn init_shell() -> anyhow::Result<()> {
+    log::set_logger(&*LOGGER)?;
+    swap_dispatch(
+        fern::Dispatch::new()
+            .format(text_format(true))
+            .level(log::LevelFilter::Info)
+            .chain(std::io::stdout()),
+    );
+    Ok(())
+}
+
+pub fn apply_log_config(log_config: &LogConfig) -> anyhow::Result<()> {
+    let mut dispatch = fern::Dispatch::new().level(log_config.level);
+    for (module, level) in log_config.module_level.iter() {
+        dispatch = dispatch.level_for(module.clone(), *level);
+    }
+
+    let mut stdout = fern::Dispatch::new().chain(std::io::stdout());
+    stdout = match log_config.format {
+        LogFormat::Text => stdout.format(text_format(true)),
+        LogFormat::Json => stdout.format(json_format),
+    };
+    dispatch = dispatch.chain(stdout);
+
+    if !log_config.file.is_empty() {
+        let file = RotatingFile::new(
+            Path::new(&log_config.file),
+            log_config.rotation,
+            log_config.max_file_size_mb.saturating_mul(1024 * 1024),
+            log_config.max_files,
+        )?;
+        let mut file_output =
+            fern::Dispatch::new().chain(fern::Output::writer(Box::new(file), "\n"));
+        file_output = match log_config.format {
+            LogFormat::Text => file_output.format(text_format(false)),
+            LogFormat::Json => file_output.format(json_format),
+        };
+        dispatch = dispatch.chain(file_output);
+    }
+
+    swap_dispatch(dispatch);
+    Ok(())
+}
+
+fn swap_dispatch(dispatch: fern::Dispatch) {
+    let (level, log) = dispatch.into_log();
+    *LOGGER.inner.write().expect("write logger error") = Some(log);
+    log::set_max_level(level);
+}
+
+fn text_format(
+    colored: bool,
+) -> impl Fn(fern::FormatCallback, &std::fmt::Arguments, &Record) + Sync + Send + 'static {
+    let mut colors = ColoredLevelConfig::new().info(Color::Green);
+    colors.warn = Color::Magenta;
+
+    move |out, message, record| {
+        let mut fields = TextFields(String::new());
+        let _ = record.key_values().visit(&mut fields);
+
+        if colored {
+            out.finish(format_args!(
+                "[{} {}] {}{}",
+                humantime::format_rfc3339_seconds(std::time::SystemTime::now()),
+                colors.color(record.level()),
+                message,
+                fields.0
+            ))
+        } else {
+            out.finish(format_args!(
+                "[{} {}] {}{}",
+                humantime::format_rfc3339_seconds(std::time::SystemTime::now()),
+                record.level(),
+                message,
+                fields.0
+            ))
+        }
+    }
+}
+
+fn json_format(out: fern::FormatCallback, message: &std::fmt::Arguments, record: &Record) {
+    let mut fields = JsonFields(serde_json::Map::new());
+    fields.0.insert(
+        "time".to_string(),
+        humantime::format_rfc3339_millis(std::time::SystemTime::now())
+            .to_string()
+            .into(),
+    );
+    fields
+        .0
+        .insert("level".to_string(), record.level().as_str().into());
+    fields
+        .0
+        .insert("target".to_string(), record.target().into());
+    fields
+        .0
+        .insert("message".to_string(), message.to_string().into());
+    let _ = record.key_values().visit(&mut fields);
+
+    out.finish(format_args!(
+        "{}",
+        serde_json::Value::Object(fields.0)
+    ))
+}
+
+struct TextFields(String);
+
+impl<'kvs> VisitSource<'kvs> for TextFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.push_str(&format!(" {}={}", key, value));
+        Ok(())
+    }
+}
+
+struct JsonFields(serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.insert(
+            key.to_string(),
+            serde_json::to_value(&value).unwrap_or_else(|_| value.to_string().into()),
+        );
+        Ok(())
+    }
+}
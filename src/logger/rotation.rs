@@ -0,0 +1,318 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{Local, NaiveDate, NaiveDateTime};
+
+use crate::server::LogRotation;
+
+//rotate生成的后缀
+static DAILY_SUFFIX: &str = "%Y-%m-%d";
+static TIME_SUFFIX: &str = "%Y-%m-%d-%H%M%S";
+
+//按天或按大小切分的日志文件
+//当前日志始终写入`path`,切分后旧文件被重命名为`path.<时间>`
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: LogRotation,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+    date: NaiveDate,
+    line_start: bool,
+}
+
+impl RotatingFile {
+    pub fn new(
+        path: &Path,
+        rotation: LogRotation,
+        max_size: u64,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let date = metadata
+            .modified()
+            .map(|modified| chrono::DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            max_size,
+            max_files,
+            file,
+            size: metadata.len(),
+            date,
+            line_start: true,
+        })
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        //一行日志可能分多次写入,只在行首切分
+        if !self.line_start {
+            return false;
+        }
+        match self.rotation {
+            LogRotation::Never => false,
+            LogRotation::Daily => Local::now().date_naive() != self.date,
+            LogRotation::Size => self.size > 0 && self.size + incoming as u64 > self.max_size,
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let suffix = match self.rotation {
+            LogRotation::Daily => self.date.format(DAILY_SUFFIX).to_string(),
+            _ => Local::now().format(TIME_SUFFIX).to_string(),
+        };
+        let rotated = match self.last_index(&suffix)? {
+            None => self.rotated_path(&suffix),
+            //同一时间多次切分时加序号,序号补零使按名字排序仍然是按时间排序
+            //删除过期文件后不能复用更小的名字
+            Some(index) => self.rotated_path(&format!("{}.{:03}", suffix, index + 1)),
+        };
+        fs::rename(&self.path, rotated)?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.date = Local::now().date_naive();
+
+        self.remove_expired()
+    }
+
+    //已有的同一后缀的文件中最大的序号,不带序号的为0
+    fn last_index(&self, suffix: &str) -> io::Result<Option<usize>> {
+        let Some(base) = self
+            .rotated_path(suffix)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(str::to_string)
+        else {
+            return Ok(None);
+        };
+        Ok(fs::read_dir(self.dir())?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter_map(|name| match name.strip_prefix(&base)? {
+                "" => Some(0),
+                index => index.strip_prefix('.')?.parse::<usize>().ok(),
+            })
+            .max())
+    }
+
+    fn dir(&self) -> PathBuf {
+        match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    fn rotated_path(&self, suffix: &str) -> PathBuf {
+        let mut file_name = self.path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".");
+        file_name.push(suffix);
+        self.path.with_file_name(file_name)
+    }
+
+    fn remove_expired(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return Ok(());
+        }
+        let Some(file_name) = self.path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(());
+        };
+        let prefix = format!("{}.", file_name);
+
+        //只删除rotate生成的文件,用户自己的output.log.bak等文件不受影响
+        let mut rotated_files = fs::read_dir(self.dir())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(&prefix))
+                    .is_some_and(is_rotated_suffix)
+            })
+            .map(|entry| entry.path())
+            .collect::<Vec<_>>();
+        //后缀均为时间,按名字排序即为按时间排序
+        rotated_files.sort();
+
+        let expired = rotated_files.len().saturating_sub(self.max_files);
+        for path in rotated_files.into_iter().take(expired) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+//时间后缀,同一时间多次切分时后面还有.序号
+fn is_rotated_suffix(suffix: &str) -> bool {
+    let (time, index) = match suffix.split_once('.') {
+        Some((time, index)) => (time, Some(index)),
+        None => (suffix, None),
+    };
+    if index.is_some_and(|index| index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit())) {
+        return false;
+    }
+    //chrono解析时不要求补零,长度需要单独检查
+    match time.len() {
+        10 => NaiveDate::parse_from_str(time, DAILY_SUFFIX).is_ok(),
+        17 => NaiveDateTime::parse_from_str(time, TIME_SUFFIX).is_ok(),
+        _ => false,
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rjr_rotation_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rotated_files(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("rjr.log."))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = test_dir("size");
+        let path = dir.join("rjr.log");
+        let mut file = RotatingFile::new(&path, LogRotation::Size, 10, 0).unwrap();
+
+        //空文件时即使超过大小也直接写入
+        file.write_all(b"0123456789\n").unwrap();
+        assert!(rotated_files(&dir).is_empty());
+        //同一行的后半部分不会被切分到新文件
+        file.write_all(b"ab").unwrap();
+        assert_eq!(rotated_files(&dir).len(), 1);
+        file.write_all(b"cdefghijkl\n").unwrap();
+        assert_eq!(rotated_files(&dir).len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "abcdefghijkl\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_daily() {
+        let dir = test_dir("daily");
+        let path = dir.join("rjr.log");
+        let mut file = RotatingFile::new(&path, LogRotation::Daily, 0, 0).unwrap();
+        file.write_all(b"today\n").unwrap();
+        assert!(rotated_files(&dir).is_empty());
+
+        let yesterday = Local::now().date_naive().pred_opt().unwrap();
+        file.date = yesterday;
+        file.write_all(b"tomorrow\n").unwrap();
+        assert_eq!(
+            rotated_files(&dir),
+            vec![format!("rjr.log.{}", yesterday.format("%Y-%m-%d"))]
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "tomorrow\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keep_max_files() {
+        let dir = test_dir("retention");
+        let path = dir.join("rjr.log");
+        let mut file = RotatingFile::new(&path, LogRotation::Size, 1, 2).unwrap();
+        for line in 0..5 {
+            file.write_all(format!("{}\n", line).as_bytes()).unwrap();
+        }
+
+        //最旧的两个文件被删除
+        let rotated = rotated_files(&dir);
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(fs::read_to_string(dir.join(&rotated[0])).unwrap(), "2\n");
+        assert_eq!(fs::read_to_string(dir.join(&rotated[1])).unwrap(), "3\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "4\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keep_unrelated_files() {
+        let dir = test_dir("unrelated");
+        let path = dir.join("rjr.log");
+        for name in ["rjr.log.bak", "rjr.log.old", "rjr.log.2024-01-01.bak"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let mut file = RotatingFile::new(&path, LogRotation::Size, 1, 1).unwrap();
+        for line in 0..3 {
+            file.write_all(format!("{}\n", line).as_bytes()).unwrap();
+        }
+
+        assert_eq!(rotated_files(&dir).len(), 4, "{:?}", rotated_files(&dir));
+        for name in ["rjr.log.bak", "rjr.log.old", "rjr.log.2024-01-01.bak"] {
+            assert_eq!(fs::read_to_string(dir.join(name)).unwrap(), name);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotated_suffix() {
+        assert!(is_rotated_suffix("2024-01-31"));
+        assert!(is_rotated_suffix("2024-01-31-235959"));
+        assert!(is_rotated_suffix("2024-01-31-235959.002"));
+        assert!(!is_rotated_suffix("bak"));
+        assert!(!is_rotated_suffix("2024-1-31"));
+        assert!(!is_rotated_suffix("2024-01-31."));
+        assert!(!is_rotated_suffix("2024-01-31.bak"));
+        assert!(!is_rotated_suffix("2024-01-31-235959.1.2"));
+    }
+
+    #[test]
+    fn never_rotate() {
+        let dir = test_dir("never");
+        let path = dir.join("rjr.log");
+        let mut file = RotatingFile::new(&path, LogRotation::Never, 1, 0).unwrap();
+        file.write_all(b"a\n").unwrap();
+        file.write_all(b"b\n").unwrap();
+        assert!(rotated_files(&dir).is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
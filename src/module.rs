@@ -1,10 +1,29 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
 
+use log::{error, info, warn};
 use tokio::runtime::{Builder, Runtime};
 
-use self::rw_engine::RwEngine;
+use crate::{
+    connection::shared_connection::SharedConnection,
+    connection_manager::ConnectionManager,
+    error::ModuleError,
+    packet::Packet,
+    relay_manager::relay::SharedRelayRoom,
+    server::ModuleConfig,
+};
 
-mod rw_engine;
+use self::{rw_engine::RwEngine, wasm::WasmModule};
+
+pub mod rw_engine;
+mod wasm;
 
 lazy_static! {
     static ref MODULE_RUNTIME:Runtime = Builder::new_multi_thread()
@@ -14,28 +33,403 @@ lazy_static! {
     .enable_io()
     .build()
     .expect("creat block runtime error");
-    pub static ref MODULE_MANAGER: Arc<RwLock<ModuleManager>> = Arc::new(RwLock::new(ModuleManager::new()));
+    pub static ref MODULE_MANAGER: ModuleManager = ModuleManager::new();
 }
 
-pub trait Module:Sync+Send {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketAction {
+    Continue,
+    //丢弃数据包,之后的模块和服务器都不再处理
+    Drop,
+}
 
+//模块的钩子都是同步调用的,耗时的操作需要自己spawn到MODULE_RUNTIME
+//钩子panic时模块会被卸载,不会影响服务器和其他模块
+//on_packet在处理器上对每个数据包依次调用所有模块,会直接增加转发延迟
+pub trait Module: Sync + Send {
+    fn on_start(&self, _connection_mg: &Arc<ConnectionManager>) {}
+    //卸载或重载配置时调用,panic后卸载的模块不会调用
+    fn on_stop(&self) {}
+    fn on_connection(&self, _shared_con: &Arc<SharedConnection>) {}
+    fn on_room_created(&self, _room: &Arc<SharedRelayRoom>) {}
+    //返回false时不为数据包调用on_packet,只在加载时读取一次
+    fn handles_packets(&self) -> bool {
+        true
+    }
+    //服务器处理之前调用,可以修改数据包
+    fn on_packet(&self, _shared_con: &Arc<SharedConnection>, _packet: &mut Packet) -> PacketAction {
+        PacketAction::Continue
+    }
+    //内置命令之外的房间命令,command不带前缀,返回Some时回复给玩家,不再交给其他模块
+    fn on_chat_command(
+        &self,
+        _shared_con: &Arc<SharedConnection>,
+        _command: &str,
+        _args: &[&str],
+    ) -> Option<String> {
+        None
+    }
+    //在/help中显示的命令和说明
+    fn chat_commands(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ModuleType {
     RwEngine,
 }
+
+impl ModuleType {
+    pub const ALL: [ModuleType; 1] = [ModuleType::RwEngine];
+
+    //配置文件中使用的名称
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModuleType::RwEngine => "rw_engine",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|module_type| module_type.name() == name)
+    }
+
+    fn create(&self) -> Box<dyn Module> {
+        match self {
+            ModuleType::RwEngine => Box::new(RwEngine::new()),
+        }
+    }
+}
+
+struct LoadedModule {
+    name: String,
+    //从模块目录加载的WASM模块的文件
+    file: Option<PathBuf>,
+    module: Box<dyn Module>,
+    handles_packets: bool,
+}
+
 pub struct ModuleManager {
-    module_list: Vec<Box<dyn Module>>,
+    module_list: RwLock<Vec<Arc<LoadedModule>>>,
+    //处理数据包的模块数,为0时处理数据包不需要复制模块列表
+    packet_modules: AtomicUsize,
+    connection_mg: RwLock<Option<Arc<ConnectionManager>>>,
+    dir: RwLock<String>,
 }
 
 impl ModuleManager {
     pub fn new() -> Self {
         ModuleManager {
-            module_list: Vec::new(),
+            module_list: RwLock::new(Vec::new()),
+            packet_modules: AtomicUsize::new(0),
+            connection_mg: RwLock::new(None),
+            dir: RwLock::new(String::new()),
+        }
+    }
+
+    //服务器启动后调用,之后才能加载模块
+    pub fn start(&self, config: &ModuleConfig, connection_mg: Arc<ConnectionManager>) {
+        *self
+            .connection_mg
+            .write()
+            .expect("write module connection manager error") = Some(connection_mg);
+        self.apply_config(config);
+    }
+
+    //卸载配置中去掉的模块,加载新增的模块,模块目录改变时重新加载WASM模块
+    pub fn apply_config(&self, config: &ModuleConfig) {
+        for name in self.module_names() {
+            if ModuleType::from_name(&name).is_some() && !config.enable.contains(&name) {
+                let _ = self.unload(&name);
+            }
+        }
+        for name in config.enable.iter() {
+            let result = match ModuleType::from_name(name) {
+                Some(module_type) => self.load_module(module_type),
+                None => Err(ModuleError::Unknown(name.clone())),
+            };
+            match result {
+                Ok(_) | Err(ModuleError::AlreadyLoaded(_)) => {}
+                Err(e) => warn!("加载模块{}失败:{}", name, e),
+            }
+        }
+
+        let dir_changed = {
+            let mut dir = self.dir.write().expect("write module dir error");
+            let changed = *dir != config.dir;
+            dir.clone_from(&config.dir);
+            changed
+        };
+        if dir_changed {
+            self.reload_dir();
+        }
+    }
+
+    //卸载所有WASM模块,重新加载模块目录中的.wasm文件,返回加载的数量
+    pub fn reload_dir(&self) -> usize {
+        for loaded in self.loaded_modules() {
+            if loaded.file.is_some() {
+                let _ = self.unload(&loaded.name);
+            }
+        }
+        let dir = self.dir.read().expect("read module dir error").clone();
+        if dir.is_empty() {
+            return 0;
         }
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                info!("不加载模块目录{}:{}", dir, e);
+                return 0;
+            }
+        };
+        let mut files = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+            .iter()
+            .filter(|file| match self.load_file(file) {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("加载模块{}失败:{}", file.display(), e);
+                    false
+                }
+            })
+            .count()
+    }
+
+    //加载内置模块或模块目录中的<name>.wasm
+    pub fn load_by_name(&self, name: &str) -> Result<(), ModuleError> {
+        if let Some(module_type) = ModuleType::from_name(name) {
+            return self.load_module(module_type);
+        }
+        let dir = self.dir.read().expect("read module dir error").clone();
+        let file = Path::new(&dir).join(format!("{}.wasm", name));
+        if dir.is_empty() || !file.is_file() {
+            return Err(ModuleError::Unknown(name.to_string()));
+        }
+        self.load_file(&file)
+    }
+
+    pub fn load_file(&self, file: &Path) -> Result<(), ModuleError> {
+        let module = WasmModule::from_file(file)
+            .map_err(|e| ModuleError::Invalid(file.display().to_string(), e.to_string()))?;
+        let name = module.name().to_string();
+        self.insert(&name, Some(file.to_path_buf()), Box::new(module))
+    }
+
+    pub fn load_module(&self, module_type: ModuleType) -> Result<(), ModuleError> {
+        if self.is_loaded(module_type.name()) {
+            return Err(ModuleError::AlreadyLoaded(module_type.name().to_string()));
+        }
+        self.load(module_type.name(), module_type.create())
+    }
+
+    pub fn load(&self, name: &str, module: Box<dyn Module>) -> Result<(), ModuleError> {
+        self.insert(name, None, module)
     }
 
-    pub fn init_module(&mut self, module_type: ModuleType) {
-        match module_type {
-            ModuleType::RwEngine => self.module_list.push(Box::new(RwEngine::new())),
+    fn insert(
+        &self,
+        name: &str,
+        file: Option<PathBuf>,
+        module: Box<dyn Module>,
+    ) -> Result<(), ModuleError> {
+        if self.is_loaded(name) {
+            return Err(ModuleError::AlreadyLoaded(name.to_string()));
         }
+        let connection_mg = self
+            .connection_mg
+            .read()
+            .expect("read module connection manager error")
+            .clone()
+            .ok_or(ModuleError::NotStarted)?;
+
+        //on_start在锁外调用,模块可以在其中访问MODULE_MANAGER
+        let loaded = Arc::new(LoadedModule {
+            name: name.to_string(),
+            file,
+            handles_packets: module.handles_packets(),
+            module,
+        });
+        if catch_hook(&loaded, "on_start", |module| {
+            module.on_start(&connection_mg)
+        })
+        .is_none()
+        {
+            return Err(ModuleError::StartPanic(name.to_string()));
+        }
+
+        let mut module_list = self.module_list.write().expect("write module list error");
+        if module_list.iter().any(|loaded| loaded.name == name) {
+            //同名模块在on_start期间被并发加载,这个实例已经启动,需要停止后再丢弃
+            drop(module_list);
+            catch_hook(&loaded, "on_stop", |module| module.on_stop());
+            return Err(ModuleError::AlreadyLoaded(name.to_string()));
+        }
+        if loaded.handles_packets {
+            self.packet_modules.fetch_add(1, Ordering::Relaxed);
+        }
+        module_list.push(loaded);
+        info!(module = name; "模块{}已加载", name);
+        Ok(())
+    }
+
+    pub fn unload(&self, name: &str) -> Result<(), ModuleError> {
+        let loaded = self
+            .remove(name)
+            .ok_or_else(|| ModuleError::NotLoaded(name.to_string()))?;
+        catch_hook(&loaded, "on_stop", |module| module.on_stop());
+        info!(module = name; "模块{}已卸载", name);
+        Ok(())
+    }
+
+    //模块名和WASM模块的文件
+    pub fn module_list(&self) -> Vec<(String, Option<PathBuf>)> {
+        self.loaded_modules()
+            .iter()
+            .map(|loaded| (loaded.name.clone(), loaded.file.clone()))
+            .collect()
+    }
+
+    pub fn module_names(&self) -> Vec<String> {
+        self.module_list
+            .read()
+            .expect("read module list error")
+            .iter()
+            .map(|loaded| loaded.name.clone())
+            .collect()
+    }
+
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.module_list
+            .read()
+            .expect("read module list error")
+            .iter()
+            .any(|loaded| loaded.name == name)
+    }
+
+    pub fn on_connection(&self, shared_con: &Arc<SharedConnection>) {
+        self.each_module("on_connection", |module| module.on_connection(shared_con));
+    }
+
+    pub fn on_room_created(&self, room: &Arc<SharedRelayRoom>) {
+        self.each_module("on_room_created", |module| module.on_room_created(room));
+    }
+
+    pub fn on_packet(
+        &self,
+        shared_con: &Arc<SharedConnection>,
+        packet: &mut Packet,
+    ) -> PacketAction {
+        if self.packet_modules.load(Ordering::Relaxed) == 0 {
+            return PacketAction::Continue;
+        }
+        for loaded in self.loaded_modules() {
+            if !loaded.handles_packets {
+                continue;
+            }
+            let action = self.call_hook(&loaded, "on_packet", |module| {
+                module.on_packet(shared_con, packet)
+            });
+            if action == Some(PacketAction::Drop) {
+                return PacketAction::Drop;
+            }
+        }
+        PacketAction::Continue
+    }
+
+    //没有模块处理时返回None
+    pub fn on_chat_command(
+        &self,
+        shared_con: &Arc<SharedConnection>,
+        command: &str,
+        args: &[&str],
+    ) -> Option<String> {
+        self.loaded_modules().into_iter().find_map(|loaded| {
+            self.call_hook(&loaded, "on_chat_command", |module| {
+                module.on_chat_command(shared_con, command, args)
+            })
+            .flatten()
+        })
+    }
+
+    pub fn chat_commands(&self) -> Vec<(String, String)> {
+        self.loaded_modules()
+            .into_iter()
+            .filter_map(|loaded| {
+                self.call_hook(&loaded, "chat_commands", |module| module.chat_commands())
+            })
+            .flatten()
+            .collect()
+    }
+
+    //复制一份列表,钩子调用期间不持有锁
+    fn loaded_modules(&self) -> Vec<Arc<LoadedModule>> {
+        self.module_list
+            .read()
+            .expect("read module list error")
+            .clone()
+    }
+
+    fn each_module(&self, hook: &'static str, f: impl Fn(&dyn Module)) {
+        for loaded in self.loaded_modules() {
+            self.call_hook(&loaded, hook, &f);
+        }
+    }
+
+    fn call_hook<R>(
+        &self,
+        loaded: &Arc<LoadedModule>,
+        hook: &'static str,
+        f: impl FnOnce(&dyn Module) -> R,
+    ) -> Option<R> {
+        let result = catch_hook(loaded, hook, f);
+        if result.is_none() {
+            self.remove(&loaded.name);
+        }
+        result
+    }
+
+    fn remove(&self, name: &str) -> Option<Arc<LoadedModule>> {
+        let mut module_list = self.module_list.write().expect("write module list error");
+        let index = module_list.iter().position(|loaded| loaded.name == name)?;
+        let loaded = module_list.remove(index);
+        if loaded.handles_packets {
+            self.packet_modules.fetch_sub(1, Ordering::Relaxed);
+        }
+        Some(loaded)
+    }
+}
+
+//钩子panic时返回None
+fn catch_hook<R>(
+    loaded: &LoadedModule,
+    hook: &'static str,
+    f: impl FnOnce(&dyn Module) -> R,
+) -> Option<R> {
+    match panic::catch_unwind(AssertUnwindSafe(|| f(loaded.module.as_ref()))) {
+        Ok(result) => Some(result),
+        Err(payload) => {
+            error!(
+                module = loaded.name.as_str();
+                "模块{}在{}中panic,已卸载:{}", loaded.name, hook, panic_message(&*payload)
+            );
+            None
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown"
     }
 }
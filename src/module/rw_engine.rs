@@ -1,96 +1,335 @@
 use std::{
     collections::HashMap,
-    sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc,
-    },
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
 };
 
 use log::{info, warn};
-use tokio::sync::RwLock;
+use tokio::sync::broadcast::error::RecvError;
 
 use crate::{
-    dummy::{shared_dummy::SharedDummy, Dummy, DummyConf, DummyVersion},
-    event::{EventType, EVENT_CHANNEL_MULTIPLE},
-    module::{Module, MODULE_RUNTIME},
-    packet::{common_packet::CommonPacket, PacketType},
+    connection::{permission_status::PermissionStatus, shared_connection::SharedConnection},
+    connection_manager::ConnectionManager,
+    dummy::{shared_dummy::SharedDummy, Dummy, DummyConf, DummyState, DummyVersion},
+    error::DummyError,
+    module::{Module, PacketAction, MODULE_RUNTIME},
+    packet::{
+        message::{Chat, ChatReceive, PacketForwardClientTo},
+        Packet, PacketType,
+    },
+    relay_manager::relay::SharedRelayRoom,
+    server::{reload::live_config, RwEngineConfig, RwEngineVersion},
 };
 
 pub mod image;
+pub mod paint;
+
+use paint::{paint, resolve_image, usage, PaintCommand};
+
+//等待假人进入大厅和房间的时间
+static JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+//检查房主是否已经离开的间隔
+static ROOM_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+//房间号 -> 房间中的假人,None表示假人正在加入
+pub type DummyMap = Arc<Mutex<HashMap<String, Option<Arc<SharedDummy>>>>>;
 
 pub struct RwEngine {
-    dummy_map: Arc<RwLock<HashMap<u32, Arc<SharedDummy>>>>,
-    dummy_size: Arc<AtomicU32>,
+    //为None时使用当前生效的配置,修改配置后对新房间生效
+    config: Option<RwEngineConfig>,
+    dummy_map: DummyMap,
 }
 impl RwEngine {
     pub fn new() -> Self {
-        let dummy_size = Arc::new(AtomicU32::new(0));
-        let dummy_map = Arc::new(RwLock::new(HashMap::new()));
+        RwEngine {
+            config: None,
+            dummy_map: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_config(config: RwEngineConfig) -> Self {
+        RwEngine {
+            config: Some(config),
+            ..Self::new()
+        }
+    }
+
+    //已加入或正在加入的房间
+    #[cfg(test)]
+    pub fn joined_rooms(&self) -> DummyMap {
+        self.dummy_map.clone()
+    }
+
+    fn config(&self) -> RwEngineConfig {
+        self.config
+            .clone()
+            .unwrap_or_else(|| live_config().module.rw_engine.clone())
+    }
 
-        let dummy_size_move = dummy_size.clone();
-        let dummy_map_move = dummy_map.clone();
+    //房主在聊天中输入了加入命令,聊天由房主转发给每个玩家
+    fn is_join_command(&self, packet: &Packet) -> bool {
+        let Ok(forward) = packet.to_message::<PacketForwardClientTo>() else {
+            return false;
+        };
+        if forward.packet_type != PacketType::CHAT as u32 {
+            return false;
+        }
+        let Ok(chat) = Packet::new(PacketType::CHAT, forward.payload).to_message::<Chat>() else {
+            return false;
+        };
+        //其他玩家的命令不会转发给房主,这里只能是房主自己发出的
+        command_args(&chat.message, &self.command(&self.config().command)) == Some("")
+    }
+
+    //其他玩家发出的假人命令,房主会用玩家的名字转发给所有人,不能靠名字区分
+    fn is_player_command(&self, packet: &Packet) -> bool {
+        let Ok(chat) = packet.to_message::<ChatReceive>() else {
+            return false;
+        };
+        let config = self.config();
+        [config.command, config.paint_command]
+            .iter()
+            .any(|command| command_args(&chat.message, &self.command(command)).is_some())
+    }
+
+    fn command(&self, command: &str) -> String {
+        format!("{}{}", live_config().message.command_prefix, command)
+    }
+}
+impl Module for RwEngine {
+    fn on_start(&self, _connection_mg: &Arc<ConnectionManager>) {
         info!("RwEnige 正在启动!");
+    }
+
+    fn on_stop(&self) {
+        //假人drop后断开连接
+        self.dummy_map
+            .lock()
+            .expect("lock rw engine dummy map error")
+            .clear();
+    }
+
+    fn on_room_created(&self, room: &Arc<SharedRelayRoom>) {
+        if self.config().auto_join || room.shared_data.custom.rw_engine {
+            join_room(self.config(), self.dummy_map.clone(), room.clone());
+        }
+    }
+
+    fn on_packet(&self, shared_con: &Arc<SharedConnection>, packet: &mut Packet) -> PacketAction {
+        let permission = *shared_con
+            .shared_data
+            .player_info
+            .permission_status
+            .read()
+            .expect("read permission status error");
+        //只有房主能使用假人的命令,其他玩家的命令不转发给房主
+        if packet.packet_type == PacketType::CHAT_RECEIVE
+            && matches!(permission, PermissionStatus::PlayerPermission)
+            && self.is_player_command(packet)
+        {
+            let shared_con = shared_con.clone();
+            MODULE_RUNTIME.spawn(async move {
+                shared_con.send_system_message("只有房主可以使用此命令").await;
+            });
+            return PacketAction::Drop;
+        }
+        if packet.packet_type != PacketType::PACKET_FORWARD_CLIENT_TO
+            || !matches!(permission, PermissionStatus::HostPermission)
+            || !self.is_join_command(packet)
+        {
+            return PacketAction::Continue;
+        }
+
+        let config = self.config();
+        let dummy_map = self.dummy_map.clone();
+        let shared_con = shared_con.clone();
         MODULE_RUNTIME.spawn(async move {
-            let dummy_size = dummy_size_move;
-            let dummy_map = dummy_map_move;
-
-            let mut event_reader = EVENT_CHANNEL_MULTIPLE.1.resubscribe();
-            loop {
-                let Ok(event) = event_reader.recv().await else {
-                    warn!("event recv error");
-                    continue;
-                };
-                match event.event_type {
-                    EventType::NewRoomAndHostOk(room) => {
-                        info!("ID为{}的房间已创建,正在添加假人.", room.shared_data.id);
-
-                        let new_dummy = Dummy::new_shared(
-                            DummyConf::new(
-                                "RwEngineSystem".to_string(),
-                                "com.shishanyue.rwe".to_string(),
-                                DummyVersion::Version1_15,
-                            ),
-                            "127.0.0.1:5124",
-                        )
-                        .await
-                        .unwrap();
-
-                        let packet = CommonPacket::get_preregister_info(
-                            &new_dummy.conf.dummy_name,
-                            &new_dummy.conf.domain,
-                            new_dummy.conf.packet_version,
-                            new_dummy.conf.client_version,
-                            "",
-                        )
-                        .await
-                        .unwrap();
-
-                        new_dummy.send_packet(packet).await;
-
-                        new_dummy
-                            .send_packet(
-                                CommonPacket::get_relay_hall_command(&room.shared_data.id)
-                                    .await
-                                    .unwrap(),
-                            )
-                            .await;
-
-                        dummy_size.fetch_add(1, Ordering::Relaxed);
-
-                        dummy_map
-                            .write()
-                            .await
-                            .insert(dummy_size.load(Ordering::Relaxed), new_dummy);
+            if let Some(room) = shared_con.get_relay_room().await {
+                join_room(config, dummy_map, room);
+            }
+        });
+        PacketAction::Continue
+    }
+}
+
+//每个房间只加入一个假人,房间结束后移除
+fn join_room(config: RwEngineConfig, dummy_map: DummyMap, room: Arc<SharedRelayRoom>) {
+    let id = room.shared_data.id.clone();
+    {
+        let mut dummy_map = dummy_map.lock().expect("lock rw engine dummy map error");
+        if dummy_map.contains_key(&id) {
+            return;
+        }
+        dummy_map.insert(id.clone(), None);
+    }
+
+    MODULE_RUNTIME.spawn(async move {
+        info!(room_id = id.as_str(); "RwEngine正在加入房间");
+        match connect_dummy(&config, &room).await {
+            Ok(dummy) => {
+                dummy_map
+                    .lock()
+                    .expect("lock rw engine dummy map error")
+                    .insert(id.clone(), Some(dummy.clone()));
+                info!(room_id = id.as_str(); "RwEngine已加入房间");
+                tokio::select! {
+                    _ = wait_room_end(&room, &dummy) => {}
+                    _ = host_commands(&config, &room, &dummy) => {}
+                }
+                info!(room_id = id.as_str(); "房间已结束,RwEngine离开");
+            }
+            Err(e) => warn!(room_id = id.as_str(); "RwEngine加入房间失败:{}", e),
+        }
+        dummy_map
+            .lock()
+            .expect("lock rw engine dummy map error")
+            .remove(&id);
+    });
+}
+
+async fn connect_dummy(
+    config: &RwEngineConfig,
+    room: &SharedRelayRoom,
+) -> Result<Arc<SharedDummy>, DummyError> {
+    let version = match config.version {
+        RwEngineVersion::Auto => DummyVersion::from_client_version(room.shared_data.custom.version),
+        RwEngineVersion::V1_15 => DummyVersion::Version1_15,
+        //1.16的版本号只能跟随房主
+        RwEngineVersion::V1_16 => DummyVersion::Version1_16(room.shared_data.custom.version),
+    };
+    let addr = if config.addr.is_empty() {
+        live_config().server.local_addr().to_string()
+    } else {
+        config.addr.clone()
+    };
+
+    let dummy = Dummy::new_shared(
+        DummyConf::new(
+            config.dummy_name.clone(),
+            "com.shishanyue.rwe".to_string(),
+            version,
+        ),
+        &addr,
+    )
+    .await?;
+    dummy.connect_to_relay().await;
+    dummy
+        .wait_state_timeout("relay hall", JOIN_TIMEOUT, |state| {
+            *state == DummyState::InHall
+        })
+        .await?;
+    dummy.join_to_room(&room.shared_data.id).await;
+    dummy
+        .wait_state_timeout("room", JOIN_TIMEOUT, |state| {
+            matches!(state, DummyState::InRoom { .. })
+        })
+        .await?;
+    //标记假人的连接,大厅显示的房间人数不计入假人
+    if let Some(local_addr) = dummy.local_addr().map(|addr| addr.to_string()) {
+        let (_, player_list) = room.get_room_member().await;
+        if let Some((_, con)) = player_list
+            .iter()
+            .find(|(_, con)| con.addr_string() == local_addr)
+        {
+            con.shared_data
+                .player_info
+                .is_dummy
+                .store(true, Ordering::Relaxed);
+        }
+    }
+    Ok(dummy)
+}
+
+//处理房主发给假人的聊天命令,返回时进行中的绘制也会停止
+async fn host_commands(config: &RwEngineConfig, room: &Arc<SharedRelayRoom>, dummy: &SharedDummy) {
+    let command = format!(
+        "{}{}",
+        live_config().message.command_prefix,
+        config.paint_command
+    );
+    let mut packet_rx = dummy.subscribe();
+    let mut painting: Option<Pin<Box<dyn Future<Output = ()> + Send>>> = None;
+    loop {
+        let is_painting = painting.is_some();
+        let painted = async { painting.as_mut().expect("painting is None").await };
+        let packet = tokio::select! {
+            packet = packet_rx.recv() => match packet {
+                Ok(packet) => packet,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+            _ = painted, if is_painting => {
+                painting = None;
+                continue;
+            }
+        };
+        if packet.packet_type != PacketType::CHAT {
+            continue;
+        }
+        let Ok(chat) = packet.to_message::<Chat>() else {
+            continue;
+        };
+        //假人只会收到房主转发的聊天,其他玩家的命令已在on_packet中丢弃
+        let Some(args) = command_args(&chat.message, &command) else {
+            continue;
+        };
+        let (Some(admin), _) = room.get_room_member().await else {
+            return;
+        };
+
+        let reply = match PaintCommand::parse(args, config.paint_max_size) {
+            Ok(PaintCommand::Stop) => match painting.take() {
+                Some(_) => "已停止绘制".to_string(),
+                None => "当前没有进行中的绘制".to_string(),
+            },
+            Ok(PaintCommand::Start(_)) if painting.is_some() => {
+                format!("正在绘制中,请先输入{} stop", command)
+            }
+            Ok(PaintCommand::Start(args)) => {
+                match resolve_image(Path::new(&config.image_dir), &args.file) {
+                    Ok(path) => {
+                        painting = Some(Box::pin(paint(
+                            config.clone(),
+                            room.clone(),
+                            admin,
+                            path,
+                            args,
+                        )));
+                        continue;
                     }
-                    EventType::NewPacket(_, _, PacketType::CHAT) => {}
-                    _ => {}
+                    Err(e) => e,
+                }
+            }
+            Err(e) => format!("{}\n{}", e, usage(&command)),
+        };
+        admin.send_system_message(&reply).await;
+    }
+}
+
+//聊天以command开头时返回之后的参数
+fn command_args<'a>(message: &'a str, command: &str) -> Option<&'a str> {
+    message
+        .trim()
+        .strip_prefix(command)
+        .filter(|args| args.is_empty() || args.starts_with(char::is_whitespace))
+}
+
+//假人被踢出或房主离开后房间结束
+async fn wait_room_end(room: &SharedRelayRoom, dummy: &SharedDummy) {
+    let mut check = tokio::time::interval(ROOM_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = dummy.wait_state(|state| *state == DummyState::Disconnected) => return,
+            _ = check.tick() => {
+                let (admin, _) = room.get_room_member().await;
+                if admin.is_none_or(|admin| admin.is_disconnected()) {
+                    return;
                 }
             }
-        });
-        RwEngine {
-            dummy_size,
-            dummy_map,
         }
     }
 }
-impl Module for RwEngine {}
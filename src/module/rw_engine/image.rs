@@ -1,20 +1,42 @@
 use bracket_color::{hsv::HSV, rgb::RGB};
-use image::{io::Reader as ImageReader, GenericImageView, Pixel};
+use image::{imageops::FilterType, io::Reader as ImageReader, GenericImageView, Pixel};
 use std::path::Path;
 
 use crate::packet::{super_packet::SuperPacket, Packet};
 
-pub async fn get_image_packet(image_path: &Path) -> anyhow::Result<Vec<Packet>> {
-    let img = ImageReader::open(image_path)?.decode()?;
+//地图上一格的大小
+static TILE_SIZE: f32 = 20.;
+
+//每个像素生成一个地形包,offset为图片左上角所在的格子
+//指定size时缩放到边长不超过size,否则只缩小超过max_size的图片
+pub fn get_image_packet(
+    image_path: &Path,
+    size: Option<u32>,
+    max_size: u32,
+    offset: (u32, u32),
+) -> anyhow::Result<Vec<Packet>> {
+    let mut img = ImageReader::open(image_path)?.decode()?;
+    let longest = img.width().max(img.height());
+    let size = size.unwrap_or(longest).min(max_size);
+    if longest != size {
+        img = img.resize(size, size, FilterType::Nearest);
+    }
+
     let mut packets = Vec::new();
     for (x, y, rgba) in img.pixels() {
-        //let packet = SuperPacket::set_terrain(x as f32 * 20., y as f32 * 20.).await;
         let rgb = rgba.to_rgb();
         let rgb = RGB::from_u8(rgb.0[0], rgb.0[1], rgb.0[2]);
         let hsv = rgb.to_hsv();
         let terrain = get_hsv_terrain_name(hsv);
 
-        packets.push(SuperPacket::set_terrain(x as f32 * 20., y as f32 * 20., &terrain).await);
+        let (Some(x), Some(y)) = (x.checked_add(offset.0), y.checked_add(offset.1)) else {
+            anyhow::bail!("坐标({}, {})超出范围", offset.0, offset.1);
+        };
+        packets.push(SuperPacket::set_terrain(
+            x as f32 * TILE_SIZE,
+            y as f32 * TILE_SIZE,
+            &terrain,
+        ));
     }
     Ok(packets)
 }
@@ -34,3 +56,48 @@ pub fn get_hsv_terrain_name(hsv: HSV) -> String {
 
     format!("hsv{:0>3}{:0>2}{:0>2}", h, s, v)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_image() {
+        let dir = std::env::temp_dir().join(format!("rjr_image_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("scale.png");
+        image::RgbImage::new(4, 2).save(&file).unwrap();
+
+        assert_eq!(get_image_packet(&file, None, 100, (0, 0)).unwrap().len(), 8);
+        assert_eq!(get_image_packet(&file, None, 2, (0, 0)).unwrap().len(), 2);
+        assert_eq!(
+            get_image_packet(&file, Some(2), 100, (0, 0)).unwrap().len(),
+            2
+        );
+        assert_eq!(
+            get_image_packet(&file, Some(8), 100, (5, 5)).unwrap().len(),
+            32
+        );
+        assert_eq!(
+            get_image_packet(&file, Some(8), 4, (0, 0)).unwrap().len(),
+            8
+        );
+        assert!(get_image_packet(&dir.join("missing.png"), None, 100, (0, 0)).is_err());
+        assert!(get_image_packet(&file, None, 100, (0, u32::MAX)).is_err());
+        assert!(get_image_packet(&file, None, 100, (u32::MAX - 1, 0)).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn terrain_name() {
+        assert_eq!(
+            get_hsv_terrain_name(RGB::from_u8(255, 0, 0).to_hsv()),
+            "hsv0009595"
+        );
+        assert_eq!(
+            get_hsv_terrain_name(RGB::from_u8(0, 0, 0).to_hsv()),
+            "hsv0000000"
+        );
+    }
+}
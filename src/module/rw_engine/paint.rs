@@ -0,0 +1,203 @@
+//房主用聊天命令把图片绘制成地图上的地形
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use log::info;
+use tokio::time::MissedTickBehavior;
+
+use super::image::get_image_packet;
+use crate::{
+    connection::shared_connection::SharedConnection, relay_manager::relay::SharedRelayRoom,
+    server::RwEngineConfig,
+};
+
+//每隔多久发送一批地形包
+static PAINT_TICK: Duration = Duration::from_millis(100);
+//每完成多少进度向房主报告一次
+static PROGRESS_STEP: usize = 10;
+//坐标的上限,地图远小于这个大小
+static MAX_PAINT_OFFSET: u32 = 10000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaintArgs {
+    pub file: String,
+    //缩放后的最大边长,为None时使用原图大小
+    pub size: Option<u32>,
+    //图片左上角所在的格子
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaintCommand {
+    Start(PaintArgs),
+    Stop,
+}
+
+impl PaintCommand {
+    //args为命令后的参数,错误时返回回复给房主的消息
+    pub fn parse(args: &str, max_size: u32) -> Result<Self, String> {
+        let mut words = args.split_whitespace();
+        let file = match words.next() {
+            Some(word) if word.eq_ignore_ascii_case("stop") => return Ok(Self::Stop),
+            Some(file) => file.to_string(),
+            None => return Err("缺少图片文件名".to_string()),
+        };
+        let mut numbers = Vec::new();
+        for word in words {
+            match word.parse::<u32>() {
+                Ok(number) => numbers.push(number),
+                Err(_) => return Err(format!("`{}`不是有效的数字", word)),
+            }
+        }
+        let (size, x, y) = match numbers[..] {
+            [] => (None, 0, 0),
+            [size] => (Some(size), 0, 0),
+            [size, x, y] => (Some(size), x, y),
+            _ => return Err("坐标需要同时指定x和y".to_string()),
+        };
+        if let Some(size) = size {
+            if size == 0 || size > max_size {
+                return Err(format!("尺寸必须在1到{}之间", max_size));
+            }
+        }
+        if x > MAX_PAINT_OFFSET || y > MAX_PAINT_OFFSET {
+            return Err(format!("坐标必须在0到{}之间", MAX_PAINT_OFFSET));
+        }
+        Ok(Self::Start(PaintArgs { file, size, x, y }))
+    }
+}
+
+pub fn usage(command: &str) -> String {
+    format!(
+        "用法:{0} <图片> [尺寸] [x y]  绘制图片\n{0} stop  停止绘制",
+        command
+    )
+}
+
+//只允许dir下的文件名,不能包含路径
+pub fn resolve_image(dir: &Path, file: &str) -> Result<PathBuf, String> {
+    let mut components = Path::new(file).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => {}
+        _ => return Err(format!("`{}`不是有效的图片文件名", file)),
+    }
+    let path = dir.join(file);
+    if !path.is_file() {
+        return Err(format!("图片`{}`不存在", file));
+    }
+    Ok(path)
+}
+
+//按paint_rate限速把地形包发给房主,future被drop时停止
+pub async fn paint(
+    config: RwEngineConfig,
+    room: Arc<SharedRelayRoom>,
+    admin: Arc<SharedConnection>,
+    path: PathBuf,
+    args: PaintArgs,
+) {
+    let max_size = config.paint_max_size;
+    let packets = match tokio::task::spawn_blocking(move || {
+        get_image_packet(&path, args.size, max_size, (args.x, args.y))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|packets| packets)
+    {
+        Ok(packets) => packets,
+        Err(e) => {
+            admin
+                .send_system_message(&format!("读取图片`{}`失败:{}", args.file, e))
+                .await;
+            return;
+        }
+    };
+
+    let total = packets.len();
+    info!(room_id = room.shared_data.id.as_str(); "开始绘制{},共{}格", args.file, total);
+    admin
+        .send_system_message(&format!("开始绘制{},共{}格", args.file, total))
+        .await;
+
+    let batch = (config.paint_rate as u128 * PAINT_TICK.as_millis() / 1000).max(1) as usize;
+    let mut tick = tokio::time::interval(PAINT_TICK);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut sent = 0;
+    let mut reported = 0;
+    let mut packets = packets.into_iter();
+    while sent < total {
+        tick.tick().await;
+        for packet in packets.by_ref().take(batch) {
+            room.send_packet_to_host(packet).await;
+            sent += 1;
+        }
+
+        let progress = sent * 100 / total;
+        if sent < total && progress >= reported + PROGRESS_STEP {
+            reported = progress - progress % PROGRESS_STEP;
+            admin
+                .send_system_message(&format!("绘制进度{}% ({}/{})", progress, sent, total))
+                .await;
+        }
+    }
+    admin
+        .send_system_message(&format!("{}绘制完成", args.file))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_paint_commands() {
+        assert_eq!(PaintCommand::parse(" STOP ", 100), Ok(PaintCommand::Stop));
+        assert_eq!(
+            PaintCommand::parse("a.png", 100),
+            Ok(PaintCommand::Start(PaintArgs {
+                file: "a.png".to_string(),
+                size: None,
+                x: 0,
+                y: 0,
+            }))
+        );
+        assert_eq!(
+            PaintCommand::parse("a.png 50 10 20", 100),
+            Ok(PaintCommand::Start(PaintArgs {
+                file: "a.png".to_string(),
+                size: Some(50),
+                x: 10,
+                y: 20,
+            }))
+        );
+        assert!(PaintCommand::parse("", 100).is_err());
+        assert!(PaintCommand::parse("a.png 0", 100).is_err());
+        assert!(PaintCommand::parse("a.png 101", 100).is_err());
+        assert!(PaintCommand::parse("a.png 50 10", 100).is_err());
+        assert!(PaintCommand::parse("a.png big", 100).is_err());
+        assert!(PaintCommand::parse("a.png 50 10000 10000", 100).is_ok());
+        assert!(PaintCommand::parse("a.png 50 10001 0", 100).is_err());
+        assert!(PaintCommand::parse("a.png 50 0 4294967295", 100).is_err());
+    }
+
+    #[test]
+    fn resolve_image_in_dir() {
+        let dir = std::env::temp_dir().join(format!("rjr_paint_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.png"), b"").unwrap();
+
+        assert_eq!(resolve_image(&dir, "a.png"), Ok(dir.join("a.png")));
+        assert!(resolve_image(&dir, "b.png").is_err());
+        assert!(resolve_image(&dir, "sub").is_err());
+        assert!(resolve_image(&dir, "../a.png").is_err());
+        assert!(resolve_image(&dir, "sub/../a.png").is_err());
+        assert!(resolve_image(&dir, "/etc/passwd").is_err());
+        assert!(resolve_image(&dir, ".").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,622 @@
+//在wasmi解释器中运行的模块,只能通过rjr命名空间下的函数访问服务器
+//
+//模块导出(都是可选的,有数据时需要导出memory和alloc):
+//  alloc(len) -> ptr                      服务器写入数据前分配内存
+//  on_start() / on_stop() / on_connection() / on_room_created()
+//  on_packet(type, ptr, len) -> i32       返回非0时丢弃数据包
+//  on_chat_command(ptr, len) -> i32       数据为"命令 参数...",返回非0表示已处理
+//
+//服务器提供:
+//  log(ptr, len)                          输出日志
+//  send_message(ptr, len)                 给当前连接发送系统消息
+//  send_packet(type, ptr, len)            给当前连接发送数据包
+//  set_packet(ptr, len)                   on_packet中替换数据包内容
+//  reply(ptr, len)                        on_chat_command中设置回复
+//  player_name(ptr, cap) -> len           当前连接的玩家名,超过cap的部分不写入
+//  room_id(ptr, cap) -> len               当前连接或新房间的房间号,没有时为0
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use log::info;
+use wasmi::{
+    Caller, Config, Engine, Error, Extern, Instance, Linker, Store, StoreLimits,
+    StoreLimitsBuilder, WasmParams, WasmResults,
+};
+
+use crate::{
+    connection::shared_connection::SharedConnection,
+    connection_manager::ConnectionManager,
+    packet::{codec::MAX_PACKET_LENGTH, Packet, PacketType},
+    relay_manager::relay::SharedRelayRoom,
+};
+
+use super::{Module, PacketAction, MODULE_RUNTIME};
+
+//每次调用钩子可以执行的指令数,用完后视为模块出错,防止死循环卡住服务器
+static HOOK_FUEL: u64 = 10_000_000;
+//模块线性内存的上限
+static MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+//模块一次传给服务器的数据的上限,和数据包的长度上限一致
+static MAX_GUEST_DATA: usize = MAX_PACKET_LENGTH as usize;
+//每次调用钩子最多发送的消息和数据包数,超出时视为模块出错
+static MAX_OUTGOING: usize = 64;
+static HOST_MODULE: &str = "rjr";
+static HOOKS: [&str; 6] = [
+    "on_start",
+    "on_stop",
+    "on_connection",
+    "on_room_created",
+    "on_packet",
+    "on_chat_command",
+];
+
+//每个模块只有一个实例,所有钩子都在同一个锁中串行执行
+//导出on_packet的模块会让所有处理器在这个锁上排队,不需要时不要导出
+pub struct WasmModule {
+    name: String,
+    //导出的钩子,没有导出的不调用
+    hooks: HashSet<&'static str>,
+    inner: Mutex<WasmInstance>,
+}
+
+struct WasmInstance {
+    store: Store<HostState>,
+    instance: Instance,
+}
+
+//钩子调用期间可以访问的数据
+#[derive(Default)]
+struct HookContext {
+    shared_con: Option<Arc<SharedConnection>>,
+    room_id: Option<String>,
+    packet: Option<Packet>,
+}
+
+impl HookContext {
+    fn connection(shared_con: &Arc<SharedConnection>) -> Self {
+        Self {
+            room_id: shared_con
+                .shared_data
+                .player_info
+                .room_id
+                .read()
+                .expect("read room id error")
+                .clone(),
+            shared_con: Some(shared_con.clone()),
+            packet: None,
+        }
+    }
+}
+
+enum Outgoing {
+    Message(String),
+    Packet(Packet),
+}
+
+struct HostState {
+    name: String,
+    limits: StoreLimits,
+    context: HookContext,
+    reply: Option<String>,
+    //钩子返回后按顺序发送
+    outgoing: Vec<Outgoing>,
+}
+
+impl WasmModule {
+    //模块名为不带扩展名的文件名
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .ok_or_else(|| anyhow::anyhow!("无效的模块文件名:{}", path.display()))?;
+        let wasm = std::fs::read(path)?;
+        Ok(Self::new(&name, &wasm)?)
+    }
+
+    pub fn new(name: &str, wasm: &[u8]) -> Result<Self, Error> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = wasmi::Module::new(&engine, wasm)?;
+
+        let mut store = Store::new(
+            &engine,
+            HostState {
+                name: name.to_string(),
+                limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(),
+                context: HookContext::default(),
+                reply: None,
+                outgoing: Vec::new(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(HOOK_FUEL)?;
+
+        let instance = host_linker(&engine)?
+            .instantiate(&mut store, &module)?
+            .start(&mut store)?;
+        let hooks = HOOKS
+            .into_iter()
+            .filter(|hook| instance.get_func(&store, hook).is_some())
+            .collect();
+
+        Ok(Self {
+            name: name.to_string(),
+            hooks,
+            inner: Mutex::new(WasmInstance { store, instance }),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    //调用出错(包括指令数用完)时panic,由模块管理器卸载
+    fn call<R>(
+        &self,
+        hook: &'static str,
+        context: HookContext,
+        f: impl FnOnce(&mut WasmInstance) -> Result<R, Error>,
+    ) -> (R, HostState) {
+        let mut inner = self.inner.lock().expect("lock wasm instance error");
+        inner.store.data_mut().context = context;
+        let result = inner
+            .store
+            .set_fuel(HOOK_FUEL)
+            .map_err(Error::from)
+            .and_then(|_| f(&mut inner));
+        let state = inner.store.data_mut();
+        let finished = HostState {
+            name: self.name.clone(),
+            limits: StoreLimits::default(),
+            context: std::mem::take(&mut state.context),
+            reply: state.reply.take(),
+            outgoing: std::mem::take(&mut state.outgoing),
+        };
+        //先释放锁再panic,避免锁中毒
+        drop(inner);
+        match result {
+            Ok(result) => (result, finished),
+            Err(e) => panic!("WASM模块{}的{}出错:{}", self.name, hook, e),
+        }
+    }
+
+    fn call_simple(&self, hook: &'static str, context: HookContext) {
+        if !self.hooks.contains(hook) {
+            return;
+        }
+        let (_, state) = self.call(hook, context, |inner| typed_call::<(), ()>(inner, hook, ()));
+        send_outgoing(state);
+    }
+
+    fn packet(&self, context: HookContext, packet: &mut Packet) -> PacketAction {
+        if !self.hooks.contains("on_packet") {
+            return PacketAction::Continue;
+        }
+        let packet_type = packet.packet_type as i32;
+        let payload = packet.payload.clone();
+        let (drop, mut state) = self.call(
+            "on_packet",
+            HookContext {
+                packet: Some(packet.clone()),
+                ..context
+            },
+            |inner| {
+                let (ptr, len) = write_guest(inner, &payload)?;
+                typed_call::<(i32, i32, i32), i32>(inner, "on_packet", (packet_type, ptr, len))
+            },
+        );
+        if let Some(modified) = state.context.packet.take() {
+            *packet = modified;
+        }
+        send_outgoing(state);
+        if drop != 0 {
+            PacketAction::Drop
+        } else {
+            PacketAction::Continue
+        }
+    }
+
+    fn chat_command(&self, context: HookContext, command: &str, args: &[&str]) -> Option<String> {
+        if !self.hooks.contains("on_chat_command") {
+            return None;
+        }
+        let text = std::iter::once(command)
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (handled, state) = self.call("on_chat_command", context, |inner| {
+            let (ptr, len) = write_guest(inner, text.as_bytes())?;
+            typed_call::<(i32, i32), i32>(inner, "on_chat_command", (ptr, len))
+        });
+        let reply = state.reply.clone();
+        send_outgoing(state);
+        (handled != 0).then(|| reply.unwrap_or_default())
+    }
+}
+
+impl Module for WasmModule {
+    fn on_start(&self, _connection_mg: &Arc<ConnectionManager>) {
+        self.call_simple("on_start", HookContext::default());
+    }
+
+    fn on_stop(&self) {
+        self.call_simple("on_stop", HookContext::default());
+    }
+
+    fn on_connection(&self, shared_con: &Arc<SharedConnection>) {
+        self.call_simple("on_connection", HookContext::connection(shared_con));
+    }
+
+    fn on_room_created(&self, room: &Arc<SharedRelayRoom>) {
+        self.call_simple(
+            "on_room_created",
+            HookContext {
+                room_id: Some(room.shared_data.id.clone()),
+                ..Default::default()
+            },
+        );
+    }
+
+    fn handles_packets(&self) -> bool {
+        self.hooks.contains("on_packet")
+    }
+
+    fn on_packet(&self, shared_con: &Arc<SharedConnection>, packet: &mut Packet) -> PacketAction {
+        self.packet(HookContext::connection(shared_con), packet)
+    }
+
+    fn on_chat_command(
+        &self,
+        shared_con: &Arc<SharedConnection>,
+        command: &str,
+        args: &[&str],
+    ) -> Option<String> {
+        self.chat_command(HookContext::connection(shared_con), command, args)
+    }
+}
+
+fn typed_call<P: WasmParams, R: WasmResults>(
+    inner: &mut WasmInstance,
+    hook: &str,
+    params: P,
+) -> Result<R, Error> {
+    inner
+        .instance
+        .get_typed_func::<P, R>(&inner.store, hook)?
+        .call(&mut inner.store, params)
+}
+
+//用模块导出的alloc分配内存并写入数据
+fn write_guest(inner: &mut WasmInstance, data: &[u8]) -> Result<(i32, i32), Error> {
+    let len = data.len() as i32;
+    let ptr = typed_call::<i32, i32>(inner, "alloc", len)?;
+    let memory = inner
+        .instance
+        .get_memory(&inner.store, "memory")
+        .ok_or_else(|| Error::new("missing memory export"))?;
+    memory
+        .write(&mut inner.store, ptr as u32 as usize, data)
+        .map_err(|e| Error::new(e.to_string()))?;
+    Ok((ptr, len))
+}
+
+fn read_guest(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Error::new("missing memory export"))?;
+    let len = usize::try_from(len).map_err(|_| Error::new("negative length"))?;
+    if len > MAX_GUEST_DATA {
+        return Err(Error::new(format!("data too long: {}", len)));
+    }
+    //先检查范围再复制,越界的长度不会分配内存
+    let start = ptr as u32 as usize;
+    start
+        .checked_add(len)
+        .and_then(|end| memory.data(caller).get(start..end))
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| Error::new("out of bounds memory access"))
+}
+
+fn push_outgoing(caller: &mut Caller<'_, HostState>, outgoing: Outgoing) -> Result<(), Error> {
+    let state = caller.data_mut();
+    if state.outgoing.len() >= MAX_OUTGOING {
+        return Err(Error::new(format!(
+            "more than {} messages and packets in one call",
+            MAX_OUTGOING
+        )));
+    }
+    state.outgoing.push(outgoing);
+    Ok(())
+}
+
+fn read_guest_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<String, Error> {
+    Ok(String::from_utf8_lossy(&read_guest(caller, ptr, len)?).to_string())
+}
+
+//最多写入cap字节,返回完整的长度
+fn write_guest_string(
+    caller: &mut Caller<'_, HostState>,
+    ptr: i32,
+    cap: i32,
+    s: &str,
+) -> Result<i32, Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Error::new("missing memory export"))?;
+    let len = s.len().min(usize::try_from(cap).unwrap_or_default());
+    memory
+        .write(caller, ptr as u32 as usize, &s.as_bytes()[..len])
+        .map_err(|e| Error::new(e.to_string()))?;
+    Ok(s.len() as i32)
+}
+
+fn host_linker(engine: &Engine) -> Result<Linker<HostState>, Error> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        HOST_MODULE,
+        "log",
+        |caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), Error> {
+            let message = read_guest_string(&caller, ptr, len)?;
+            info!(module = caller.data().name.as_str(); "{}", message);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "send_message",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), Error> {
+            let message = read_guest_string(&caller, ptr, len)?;
+            push_outgoing(&mut caller, Outgoing::Message(message))
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "send_packet",
+        |mut caller: Caller<'_, HostState>,
+         packet_type: i32,
+         ptr: i32,
+         len: i32|
+         -> Result<(), Error> {
+            let packet_type = PacketType::try_from(packet_type as u32)
+                .map_err(|_| Error::new(format!("unknown packet type {}", packet_type)))?;
+            let payload = read_guest(&caller, ptr, len)?;
+            push_outgoing(&mut caller, Outgoing::Packet(Packet::new(packet_type, payload)))
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "set_packet",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), Error> {
+            let payload = Bytes::from(read_guest(&caller, ptr, len)?);
+            let packet = caller
+                .data_mut()
+                .context
+                .packet
+                .as_mut()
+                .ok_or_else(|| Error::new("set_packet called outside on_packet"))?;
+            *packet = Packet::new(packet.packet_type, payload);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "reply",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), Error> {
+            let reply = read_guest_string(&caller, ptr, len)?;
+            caller.data_mut().reply = Some(reply);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "player_name",
+        |mut caller: Caller<'_, HostState>, ptr: i32, cap: i32| -> Result<i32, Error> {
+            let name = caller
+                .data()
+                .context
+                .shared_con
+                .as_ref()
+                .map(|shared_con| {
+                    shared_con
+                        .shared_data
+                        .player_info
+                        .player_name
+                        .read()
+                        .expect("read player name error")
+                        .clone()
+                })
+                .unwrap_or_default();
+            write_guest_string(&mut caller, ptr, cap, &name)
+        },
+    )?;
+    linker.func_wrap(
+        HOST_MODULE,
+        "room_id",
+        |mut caller: Caller<'_, HostState>, ptr: i32, cap: i32| -> Result<i32, Error> {
+            let room_id = caller.data().context.room_id.clone().unwrap_or_default();
+            write_guest_string(&mut caller, ptr, cap, &room_id)
+        },
+    )?;
+    Ok(linker)
+}
+
+//发送钩子中产生的消息,没有连接时丢弃
+fn send_outgoing(state: HostState) {
+    let (Some(shared_con), false) = (state.context.shared_con, state.outgoing.is_empty()) else {
+        return;
+    };
+    MODULE_RUNTIME.spawn(async move {
+        for outgoing in state.outgoing {
+            match outgoing {
+                Outgoing::Message(message) => shared_con.send_system_message(&message).await,
+                Outgoing::Packet(packet) => shared_con.send_packet(packet).await,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(wat: &str) -> WasmModule {
+        WasmModule::new("test", &wat::parse_str(wat).unwrap()).unwrap()
+    }
+
+    //简单的只增不减的分配器
+    static ALLOC: &str = r#"
+        (memory (export "memory") 1)
+        (global $next (mut i32) (i32.const 1024))
+        (func (export "alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+    "#;
+
+    #[test]
+    fn chat_command_reply() {
+        let module = module(&format!(
+            r#"(module
+                (import "rjr" "reply" (func $reply (param i32 i32)))
+                {}
+                (data (i32.const 0) "pong")
+                (func (export "on_chat_command") (param $ptr i32) (param $len i32) (result i32)
+                    ;; 只处理以p开头的命令
+                    (if (i32.ne (i32.load8_u (local.get $ptr)) (i32.const 112))
+                        (then (return (i32.const 0))))
+                    (call $reply (i32.const 0) (i32.const 4))
+                    (i32.const 1)))"#,
+            ALLOC
+        ));
+        assert_eq!(
+            module.chat_command(HookContext::default(), "ping", &["a"]),
+            Some("pong".to_string())
+        );
+        assert_eq!(
+            module.chat_command(HookContext::default(), "help", &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn packet_can_be_modified_or_dropped() {
+        let module = module(&format!(
+            r#"(module
+                (import "rjr" "set_packet" (func $set_packet (param i32 i32)))
+                {}
+                (data (i32.const 0) "edited")
+                (func (export "on_packet") (param $type i32) (param $ptr i32) (param $len i32) (result i32)
+                    (if (i32.eq (local.get $type) (i32.const 111))
+                        (then (return (i32.const 1))))
+                    (call $set_packet (i32.const 0) (i32.const 6))
+                    (i32.const 0)))"#,
+            ALLOC
+        ));
+        let mut packet = Packet::new(PacketType::CHAT_RECEIVE, &b"hello"[..]);
+        assert_eq!(
+            module.packet(HookContext::default(), &mut packet),
+            PacketAction::Continue
+        );
+        assert_eq!(packet.packet_type, PacketType::CHAT_RECEIVE);
+        assert_eq!(&packet.payload[..], b"edited");
+
+        let mut packet = Packet::new(PacketType::DISCONNECT, &b"bye"[..]);
+        assert_eq!(
+            module.packet(HookContext::default(), &mut packet),
+            PacketAction::Drop
+        );
+    }
+
+    #[test]
+    fn missing_hooks_are_skipped() {
+        let module = module("(module)");
+        let mut packet = Packet::new(PacketType::CHAT_RECEIVE, &b"hello"[..]);
+        assert_eq!(
+            module.packet(HookContext::default(), &mut packet),
+            PacketAction::Continue
+        );
+        assert_eq!(
+            module.chat_command(HookContext::default(), "ping", &[]),
+            None
+        );
+        module.call_simple("on_start", HookContext::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "on_start")]
+    fn endless_loop_runs_out_of_fuel() {
+        module(r#"(module (func (export "on_start") (loop (br 0))))"#)
+            .call_simple("on_start", HookContext::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "on_chat_command")]
+    fn out_of_bounds_reply_traps() {
+        module(&format!(
+            r#"(module
+                (import "rjr" "reply" (func $reply (param i32 i32)))
+                {}
+                (func (export "on_chat_command") (param i32 i32) (result i32)
+                    (call $reply (i32.const 65530) (i32.const 100))
+                    (i32.const 1)))"#,
+            ALLOC
+        ))
+        .chat_command(HookContext::default(), "ping", &[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn read_past_memory_traps() {
+        module(&format!(
+            r#"(module
+                (import "rjr" "log" (func $log (param i32 i32)))
+                {}
+                (func (export "on_start")
+                    (call $log (i32.const 60000) (i32.const 40000))))"#,
+            ALLOC
+        ))
+        .call_simple("on_start", HookContext::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "too long")]
+    fn read_longer_than_packet_traps() {
+        module(&format!(
+            r#"(module
+                (import "rjr" "log" (func $log (param i32 i32)))
+                {}
+                (func (export "on_start")
+                    (call $log (i32.const 0) (i32.const 0x7fffffff))))"#,
+            ALLOC
+        ))
+        .call_simple("on_start", HookContext::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "more than 64")]
+    fn too_many_outgoing_messages_trap() {
+        module(&format!(
+            r#"(module
+                (import "rjr" "send_message" (func $send_message (param i32 i32)))
+                {}
+                (func (export "on_start")
+                    (loop (call $send_message (i32.const 0) (i32.const 1)) (br 0))))"#,
+            ALLOC
+        ))
+        .call_simple("on_start", HookContext::default());
+    }
+
+    #[test]
+    fn rejects_invalid_modules() {
+        assert!(WasmModule::new("test", b"not wasm").is_err());
+        //导入了不存在的函数
+        let wasm = wat::parse_str(r#"(module (import "rjr" "exit" (func)))"#).unwrap();
+        assert!(WasmModule::new("test", &wasm).is_err());
+    }
+}
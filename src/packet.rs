@@ -1,11 +1,15 @@
 pub mod super_packet;
 pub mod common_packet;
+pub mod decoder;
+pub mod message;
+pub mod codec;
+#[cfg(test)]
+mod fuzz;
 
-
-use std::io::{self, Cursor};
-
+use bytes::Bytes;
 use num_enum::TryFromPrimitive;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use self::codec::PACKET_HEADER_LENGTH;
 
 #[allow(non_camel_case_types)]
 #[allow(clippy::upper_case_acronyms)]
@@ -84,130 +88,27 @@ pub enum PacketType {
     NOT_RESOLVED = u32::MAX,
 }
 
+//数据包只保存数据部分,头部在发送时生成
+//payload是引用计数的,克隆和转发给多个玩家时不会复制数据
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Packet {
     pub packet_type: PacketType,
-    pub packet_length: u32,
-    pub packet_buffer: Cursor<Vec<u8>>,
-    pub is_prepared: bool,
+    pub payload: Bytes,
 }
 
 impl Packet {
-    pub async fn new(packet_type: PacketType) -> Self {
-        let mut buffer = Cursor::new(Vec::new());
-        buffer.write_u64(0).await.unwrap();
-        Packet {
-            packet_type,
-            packet_length: 0,
-            packet_buffer: buffer,
-            is_prepared: false,
-        }
-    }
-
-    pub fn decode_from_buffer(
-        packet_length: u32,
-        packet_type: PacketType,
-        packet_buffer: Cursor<Vec<u8>>,
-    ) -> Self {
+    pub fn new(packet_type: PacketType, payload: impl Into<Bytes>) -> Self {
         Packet {
             packet_type,
-            packet_length,
-            packet_buffer,
-            is_prepared: true,
-        }
-    }
-    pub async fn prepare(&mut self) {
-        if !self.is_prepared {
-            let packet_type = self.packet_type as u32;
-
-            self.packet_length = self.packet_buffer.position() as u32 - 8;
-            self.packet_buffer.set_position(0);
-
-            self.packet_buffer
-                .write_u32(self.packet_length)
-                .await
-                .unwrap();
-            self.packet_buffer.write_u32(packet_type).await.unwrap();
-            self.is_prepared = true;
+            payload: payload.into(),
         }
     }
-}
 
-pub trait PacketReadWriteExt {
-    async fn read_string(&mut self) -> Option<String>;
-    async fn read_if_is_string(&mut self) -> Option<String>;
-    async fn write_string(&mut self, s: &str) -> io::Result<usize>;
-    async fn write_is_string(&mut self, s: &str) -> io::Result<usize>;
-    async fn read_stream_bytes(&mut self) -> Vec<u8>;
-    async fn write_u8(&mut self,n: u8) -> io::Result<()>;
-    async fn write_u32(&mut self,n: u32) -> io::Result<()>;
-    async fn write_u64(&mut self,n: u64) -> io::Result<()>;
-    async fn write_u16(&mut self,n: u16) -> io::Result<()>;
-    async fn write_f32(&mut self,n: f32) -> io::Result<()>;
-    async fn write_all(&mut self,buf: &[u8]) -> io::Result<()>;
-    
-}
-
-impl PacketReadWriteExt for Packet {
-    async fn read_string(&mut self) -> Option<String> {
-        let str_len = self.packet_buffer.read_u16().await.unwrap();
-        let mut str = vec![0; str_len as usize];
-        self.packet_buffer.read_exact(&mut str).await.unwrap();
-        Some(String::from_utf8_lossy(&str).to_string())
-    }
-    async fn read_if_is_string(&mut self) -> Option<String> {
-        if self.packet_buffer.read_u8().await.unwrap() == 1 {
-            self.read_string().await
-        } else {
-            None
-        }
-    }
-    async fn write_string(&mut self, s: &str) -> io::Result<usize> {
-        self.packet_buffer.write_u16(s.len() as u16).await.unwrap();
-        self.packet_buffer.write(s.as_bytes()).await
-    }
-    async fn write_is_string(&mut self, s: &str) -> io::Result<usize> {
-        if s.is_empty() {
-            self.packet_buffer.write_u8(0).await.unwrap();
-            Ok(0)
-        } else {
-            self.packet_buffer.write_u8(1).await.unwrap();
-            self.write_string(s).await
-        }
-    }
-    async fn read_stream_bytes(&mut self) -> Vec<u8> {
-        let mut packet_bytes = vec![0; self.packet_buffer.read_u32().await.unwrap() as usize];
-
-        self.packet_buffer
-            .read_exact(&mut packet_bytes)
-            .await
-            .unwrap();
-
-        packet_bytes
-    }
-    
-    async fn write_u8(&mut self,n: u8) -> io::Result<()> {
-        self.packet_buffer.write_u8(n).await
-    }
-    
-    async fn write_u32(&mut self,n: u32) -> io::Result<()> {
-        self.packet_buffer.write_u32(n).await
-    }
-    
-    
-    async fn write_u64(&mut self,n: u64) -> io::Result<()> {
-        self.packet_buffer.write_u64(n).await
-    }
-    
-    async fn write_all(&mut self,buf: &[u8]) -> io::Result<()> {
-        self.packet_buffer.write_all(buf).await
-    }
-    
-    async fn write_f32(&mut self,n: f32) -> io::Result<()> {
-        self.packet_buffer.write_f32(n).await
-    }
-    
-    async fn write_u16(&mut self,n: u16) -> io::Result<()> {
-        self.packet_buffer.write_u16(n).await
+    //i32长度+u32类型
+    pub fn header(&self) -> [u8; PACKET_HEADER_LENGTH] {
+        let mut header = [0; PACKET_HEADER_LENGTH];
+        header[..4].copy_from_slice(&(self.payload.len() as u32).to_be_bytes());
+        header[4..].copy_from_slice(&(self.packet_type as u32).to_be_bytes());
+        header
     }
 }
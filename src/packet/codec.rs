@@ -0,0 +1,277 @@
+use std::io;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::error::{PacketDecodeError, ReceiverError};
+
+use super::{Packet, PacketType};
+
+//i32长度+u32类型
+pub const PACKET_HEADER_LENGTH: usize = 8;
+//超过这个长度的数据包视为非法
+pub const MAX_PACKET_LENGTH: i32 = 1024 * 50;
+
+//从数据包中读取数据,数据不足时返回错误而不是panic
+//读出的数据是原数据的切片,不会复制
+pub trait PacketBuf {
+    fn read_bytes_checked(&mut self, length: usize) -> Result<Bytes, PacketDecodeError>;
+    fn read_u8_checked(&mut self) -> Result<u8, PacketDecodeError>;
+    fn read_u32_checked(&mut self) -> Result<u32, PacketDecodeError>;
+    fn read_u64_checked(&mut self) -> Result<u64, PacketDecodeError>;
+    fn read_string_checked(&mut self) -> Result<String, PacketDecodeError>;
+    fn read_is_string_checked(&mut self) -> Result<Option<String>, PacketDecodeError>;
+}
+
+impl PacketBuf for Bytes {
+    fn read_bytes_checked(&mut self, length: usize) -> Result<Bytes, PacketDecodeError> {
+        if self.len() < length {
+            return Err(PacketDecodeError::UnexpectedEof);
+        }
+        Ok(self.split_to(length))
+    }
+
+    fn read_u8_checked(&mut self) -> Result<u8, PacketDecodeError> {
+        if self.remaining() < 1 {
+            return Err(PacketDecodeError::UnexpectedEof);
+        }
+        Ok(self.get_u8())
+    }
+
+    fn read_u32_checked(&mut self) -> Result<u32, PacketDecodeError> {
+        if self.remaining() < 4 {
+            return Err(PacketDecodeError::UnexpectedEof);
+        }
+        Ok(self.get_u32())
+    }
+
+    fn read_u64_checked(&mut self) -> Result<u64, PacketDecodeError> {
+        if self.remaining() < 8 {
+            return Err(PacketDecodeError::UnexpectedEof);
+        }
+        Ok(self.get_u64())
+    }
+
+    //u16长度+UTF-8
+    fn read_string_checked(&mut self) -> Result<String, PacketDecodeError> {
+        if self.remaining() < 2 {
+            return Err(PacketDecodeError::UnexpectedEof);
+        }
+        let length = u16::from_be_bytes([self[0], self[1]]) as usize;
+        if self.remaining() < 2 + length {
+            return Err(PacketDecodeError::UnexpectedEof);
+        }
+        self.advance(2);
+        Ok(String::from_utf8_lossy(&self.split_to(length)).to_string())
+    }
+
+    //u8标记为1时后面是字符串
+    fn read_is_string_checked(&mut self) -> Result<Option<String>, PacketDecodeError> {
+        match self.first() {
+            None => Err(PacketDecodeError::UnexpectedEof),
+            Some(1) => {
+                let mut rest = self.slice(1..);
+                let s = rest.read_string_checked()?;
+                *self = rest;
+                Ok(Some(s))
+            }
+            Some(_) => {
+                self.advance(1);
+                Ok(None)
+            }
+        }
+    }
+}
+
+pub trait PacketBufMut: BufMut {
+    //长度只有u16,过长的字符串在字符边界截断,避免长度和数据错位
+    fn put_string(&mut self, s: &str) {
+        let mut length = s.len().min(u16::MAX as usize);
+        while !s.is_char_boundary(length) {
+            length -= 1;
+        }
+        self.put_u16(length as u16);
+        self.put_slice(&s.as_bytes()[..length]);
+    }
+
+    fn put_is_string(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => {
+                self.put_u8(1);
+                self.put_string(s);
+            }
+            None => self.put_u8(0),
+        }
+    }
+}
+
+impl<B: BufMut> PacketBufMut for B {}
+
+//按长度+类型的头部切分TCP流,配合FramedRead/FramedWrite使用
+//一次读取可以解析出多个数据包,写入的数据包积攒在缓冲区中一起flush
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PacketCodec;
+
+impl Decoder for PacketCodec {
+    type Item = Packet;
+    type Error = ReceiverError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, ReceiverError> {
+        if src.len() < PACKET_HEADER_LENGTH {
+            src.reserve(PACKET_HEADER_LENGTH - src.len());
+            return Ok(None);
+        }
+
+        let packet_length = i32::from_be_bytes([src[0], src[1], src[2], src[3]]);
+        let packet_type =
+            PacketType::try_from(u32::from_be_bytes([src[4], src[5], src[6], src[7]]))
+                .unwrap_or_default();
+
+        if packet_length <= 0 {
+            return Err(ReceiverError::InvalidInput(
+                "packet length below zero".to_string(),
+            ));
+        }
+        if packet_length >= MAX_PACKET_LENGTH || packet_type == PacketType::NOT_RESOLVED {
+            return Err(ReceiverError::InvalidInput(
+                "packet length too long".to_string(),
+            ));
+        }
+
+        let frame_length = PACKET_HEADER_LENGTH + packet_length as usize;
+        if src.len() < frame_length {
+            src.reserve(frame_length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(PACKET_HEADER_LENGTH);
+        let payload = src.split_to(packet_length as usize).freeze();
+        Ok(Some(Packet::new(packet_type, payload)))
+    }
+}
+
+impl Encoder<Packet> for PacketCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> io::Result<()> {
+        dst.reserve(PACKET_HEADER_LENGTH + packet.payload.len());
+        dst.put_slice(&packet.header());
+        dst.put_slice(&packet.payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::packet::PacketType;
+
+    #[test]
+    fn read_primitives() {
+        let mut buf = BytesMut::new();
+        buf.put_u8(7);
+        buf.put_u32(0xdead_beef);
+        buf.put_u64(u64::MAX - 1);
+        buf.put_string("房间");
+        buf.put_is_string(None);
+        buf.put_is_string(Some("x"));
+        let mut buf = buf.freeze();
+
+        assert_eq!(buf.read_u8_checked(), Ok(7));
+        assert_eq!(buf.read_u32_checked(), Ok(0xdead_beef));
+        assert_eq!(buf.read_u64_checked(), Ok(u64::MAX - 1));
+        assert_eq!(buf.read_string_checked(), Ok("房间".to_string()));
+        assert_eq!(buf.read_is_string_checked(), Ok(None));
+        assert_eq!(buf.read_is_string_checked(), Ok(Some("x".to_string())));
+        assert_eq!(buf.read_u8_checked(), Err(PacketDecodeError::UnexpectedEof));
+    }
+
+    #[test]
+    fn failed_read_consumes_nothing() {
+        let mut buf = Bytes::from_static(&[1, 0, 5, b'a']);
+        assert_eq!(
+            buf.read_is_string_checked(),
+            Err(PacketDecodeError::UnexpectedEof)
+        );
+        assert_eq!(buf.len(), 4);
+        assert_eq!(buf.read_u64_checked(), Err(PacketDecodeError::UnexpectedEof));
+        assert_eq!(buf.len(), 4);
+    }
+
+    #[test]
+    fn get_bytes_shares_buffer() {
+        let data = Bytes::from(vec![1, 2, 3, 4]);
+        let mut buf = data.clone();
+        let taken = buf.read_bytes_checked(2).unwrap();
+        assert_eq!(taken.as_ptr(), data.as_ptr());
+        assert_eq!(&buf[..], &[3, 4]);
+    }
+
+    fn header(packet_length: i32, packet_type: u32) -> Vec<u8> {
+        let mut buf = packet_length.to_be_bytes().to_vec();
+        buf.extend_from_slice(&packet_type.to_be_bytes());
+        buf
+    }
+
+    fn frame(packet_type: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf = header(payload.len() as i32, packet_type);
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn decode_several_packets_from_one_read() {
+        let mut src = BytesMut::new();
+        src.put_slice(&frame(108, &[0; 8]));
+        src.put_slice(&frame(141, b"abc"));
+        //第三个数据包只收到一部分
+        src.put_slice(&frame(10, b"tick")[..6]);
+
+        let mut codec = PacketCodec;
+        let first = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(first.packet_type, PacketType::HEART_BEAT);
+        assert_eq!(first.payload.len(), 8);
+        let second = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(second.packet_type, PacketType::CHAT);
+        assert_eq!(&second.payload[..], b"abc");
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+
+        src.put_slice(&frame(10, b"tick")[6..]);
+        let third = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(third.packet_type, PacketType::TICK);
+        assert_eq!(&third.payload[..], b"tick");
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn reject_invalid_header() {
+        let mut codec = PacketCodec;
+        for header in [
+            frame(108, &[]),
+            header(-1, 108),
+            header(MAX_PACKET_LENGTH, 108),
+            frame(9999, b"x"),
+        ] {
+            let mut src = BytesMut::from(&header[..]);
+            assert!(codec.decode(&mut src).is_err());
+        }
+    }
+
+    #[test]
+    fn encode_frame() {
+        let mut dst = BytesMut::new();
+        let mut codec = PacketCodec;
+        codec
+            .encode(Packet::new(PacketType::HEART_BEAT, vec![0xab; 3]), &mut dst)
+            .unwrap();
+        codec
+            .encode(Packet::new(PacketType::CHAT, vec![1]), &mut dst)
+            .unwrap();
+        assert_eq!(
+            &dst[..],
+            [0, 0, 0, 3, 0, 0, 0, 108, 0xab, 0xab, 0xab, 0, 0, 0, 1, 0, 0, 0, 141, 1]
+        );
+    }
+}
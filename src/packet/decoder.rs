@@ -0,0 +1,384 @@
+use std::fmt;
+
+use super::{Packet, PacketType};
+
+//普通输出时数据最多显示的字节数,{:#}输出全部
+static HEX_PREVIEW_LENGTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    U8(u8),
+    U32(u32),
+    U64(u64),
+    Bool(bool),
+    Str(String),
+    OptStr(Option<String>),
+    Packet(Box<DecodedPacket>),
+}
+
+//按PacketType解析出的数据包,无法识别的部分保留在rest中
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedPacket {
+    pub packet_type: u32,
+    pub fields: Vec<(&'static str, FieldValue)>,
+    pub rest: Vec<u8>,
+}
+
+impl Packet {
+    pub fn decoded(&self) -> DecodedPacket {
+        decode_packet(self.packet_type as u32, &self.payload)
+    }
+}
+
+pub fn decode_packet(packet_type: u32, payload: &[u8]) -> DecodedPacket {
+    let mut reader = FieldReader {
+        buf: payload,
+        fields: Vec::new(),
+    };
+    //数据不完整时保留已经解析的字段,剩下的按原样输出
+    let _ = decode_fields(
+        PacketType::try_from(packet_type).unwrap_or_default(),
+        &mut reader,
+    );
+    DecodedPacket {
+        packet_type,
+        fields: reader.fields,
+        rest: reader.buf.to_vec(),
+    }
+}
+
+fn decode_fields(packet_type: PacketType, reader: &mut FieldReader) -> Option<()> {
+    match packet_type {
+        PacketType::PREREGISTER_INFO_RECEIVE => {
+            reader.string("domain")?;
+            let packet_version = reader.u32("packet_version")?;
+            reader.u32("client_version")?;
+            if packet_version >= 1 {
+                reader.u32("unknown")?;
+            }
+            if packet_version >= 2 {
+                reader.opt_string("query_string")?;
+            }
+            if packet_version >= 3 {
+                reader.string("player_name")?;
+            }
+        }
+        PacketType::RELAY_VERSION_INFO => {
+            reader.u8("unknown")?;
+            reader.u32("version")?;
+            reader.u32("unknown")?;
+            reader.u8("unknown")?;
+        }
+        PacketType::RELAY_117 => {
+            reader.u8("unknown")?;
+            reader.u32("message_type")?;
+            reader.string("message")?;
+        }
+        PacketType::RELAY_118_117_RETURN => {
+            reader.u8("unknown")?;
+            reader.u32("unknown")?;
+            reader.string("command")?;
+        }
+        PacketType::RELAY_BECOME_SERVER => {
+            let protocol = reader.u8("protocol")?;
+            if protocol >= 2 {
+                reader.u8("unknown")?;
+                reader.u8("unknown")?;
+                reader.u8("unknown")?;
+                reader.string("team")?;
+                reader.bool("mods")?;
+                reader.bool("public")?;
+                reader.u8("unknown")?;
+                reader.string("description")?;
+                reader.bool("public")?;
+                reader.opt_string("uuid")?;
+            }
+        }
+        PacketType::FORWARD_CLIENT_ADD => {
+            let protocol = reader.u8("protocol")?;
+            if protocol >= 1 {
+                reader.u32("index")?;
+                reader.string("uuid")?;
+                reader.u8("unknown")?;
+                reader.opt_string("ip")?;
+            }
+        }
+        PacketType::PACKET_FORWARD_CLIENT_FROM => {
+            reader.u32("index")?;
+            reader.u32("length")?;
+            //长度在前,类型在后
+            reader.packet("packet", false)?;
+        }
+        PacketType::PACKET_FORWARD_CLIENT_TO | PacketType::PACKET_FORWARD_CLIENT_TO_REPEATED => {
+            reader.u32("index")?;
+            //类型在前,长度在后
+            reader.packet("packet", true)?;
+        }
+        PacketType::CHAT => {
+            reader.string("message")?;
+            reader.u8("unknown")?;
+            reader.opt_string("sender")?;
+            reader.u32("team")?;
+            reader.u32("team")?;
+        }
+        PacketType::HEART_BEAT => {
+            reader.u64("time")?;
+        }
+        PacketType::HEART_BEAT_RESPONSE => {
+            reader.u64("time")?;
+            reader.u8("unknown")?;
+            reader.u8("unknown")?;
+        }
+        PacketType::KICK => {
+            reader.string("reason")?;
+        }
+        _ => {}
+    }
+    Some(())
+}
+
+struct FieldReader<'a> {
+    buf: &'a [u8],
+    fields: Vec<(&'static str, FieldValue)>,
+}
+
+impl<'a> FieldReader<'a> {
+    //不足length字节时不消耗数据
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < length {
+            return None;
+        }
+        let (taken, rest) = self.buf.split_at(length);
+        self.buf = rest;
+        Some(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)
+            .map(|taken| taken.try_into().expect("length checked"))
+    }
+
+    fn push(&mut self, name: &'static str, value: FieldValue) {
+        self.fields.push((name, value));
+    }
+
+    fn u8(&mut self, name: &'static str) -> Option<u8> {
+        let [n] = self.take_array()?;
+        self.push(name, FieldValue::U8(n));
+        Some(n)
+    }
+
+    fn bool(&mut self, name: &'static str) -> Option<bool> {
+        let [n] = self.take_array()?;
+        self.push(name, FieldValue::Bool(n != 0));
+        Some(n != 0)
+    }
+
+    fn u32(&mut self, name: &'static str) -> Option<u32> {
+        let n = u32::from_be_bytes(self.take_array()?);
+        self.push(name, FieldValue::U32(n));
+        Some(n)
+    }
+
+    fn u64(&mut self, name: &'static str) -> Option<u64> {
+        let n = u64::from_be_bytes(self.take_array()?);
+        self.push(name, FieldValue::U64(n));
+        Some(n)
+    }
+
+    fn read_string(&mut self) -> Option<String> {
+        let origin = self.buf;
+        let length = u16::from_be_bytes(self.take_array()?) as usize;
+        match self.take(length) {
+            Some(s) => Some(String::from_utf8_lossy(s).to_string()),
+            None => {
+                self.buf = origin;
+                None
+            }
+        }
+    }
+
+    fn string(&mut self, name: &'static str) -> Option<()> {
+        let s = self.read_string()?;
+        self.push(name, FieldValue::Str(s));
+        Some(())
+    }
+
+    fn opt_string(&mut self, name: &'static str) -> Option<()> {
+        let origin = self.buf;
+        let [flag] = self.take_array()?;
+        let s = if flag == 1 {
+            match self.read_string() {
+                Some(s) => Some(s),
+                None => {
+                    self.buf = origin;
+                    return None;
+                }
+            }
+        } else {
+            None
+        };
+        self.push(name, FieldValue::OptStr(s));
+        Some(())
+    }
+
+    //被转发的完整数据包
+    fn packet(&mut self, name: &'static str, type_first: bool) -> Option<()> {
+        let origin = self.buf;
+        let mut read_u32 = || self.take_array().map(u32::from_be_bytes);
+        let (packet_type, length) = match (read_u32(), read_u32()) {
+            (Some(first), Some(second)) if type_first => (first, second),
+            (Some(first), Some(second)) => (second, first),
+            _ => {
+                self.buf = origin;
+                return None;
+            }
+        };
+        let Some(payload) = self.take(length as usize) else {
+            self.buf = origin;
+            return None;
+        };
+        self.push(
+            name,
+            FieldValue::Packet(Box::new(decode_packet(packet_type, payload))),
+        );
+        Some(())
+    }
+}
+
+fn packet_type_name(packet_type: u32) -> String {
+    match PacketType::try_from(packet_type) {
+        Ok(known) => format!("{:?}({})", known, packet_type),
+        Err(_) => format!("UNKNOWN({})", packet_type),
+    }
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    let shown = if f.alternate() {
+        bytes
+    } else {
+        &bytes[..bytes.len().min(HEX_PREVIEW_LENGTH)]
+    };
+    for byte in shown {
+        write!(f, "{:02x}", byte)?;
+    }
+    if shown.len() < bytes.len() {
+        write!(f, "..(+{} bytes)", bytes.len() - shown.len())?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::U8(n) => write!(f, "{}", n),
+            FieldValue::U32(n) => write!(f, "{}", n),
+            FieldValue::U64(n) => write!(f, "{}", n),
+            FieldValue::Bool(b) => write!(f, "{}", b),
+            FieldValue::Str(s) => write!(f, "{:?}", s),
+            FieldValue::OptStr(Some(s)) => write!(f, "{:?}", s),
+            FieldValue::OptStr(None) => write!(f, "None"),
+            FieldValue::Packet(packet) => packet.fmt(f),
+        }
+    }
+}
+
+//RELAY_117(117) { unknown: 1, message_type: 5, message: "..." }
+//无法解析的数据以十六进制输出
+impl fmt::Display for DecodedPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", packet_type_name(self.packet_type))?;
+        if self.fields.is_empty() {
+            if !self.rest.is_empty() {
+                write!(f, " ")?;
+                write_hex(f, &self.rest)?;
+            }
+            return Ok(());
+        }
+
+        write!(f, " {{ ")?;
+        for (index, (name, value)) in self.fields.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: ", name)?;
+            value.fmt(f)?;
+        }
+        if !self.rest.is_empty() {
+            write!(f, ", rest: ")?;
+            write_hex(f, &self.rest)?;
+        }
+        write!(f, " }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::packet::message::{Chat, HeartBeat, Kick, PacketForwardClientFrom};
+
+    #[test]
+    fn decode_known_packets() {
+        let kick = Packet::from_message(&Kick {
+            reason: "bye".to_string(),
+        })
+        .decoded();
+        assert_eq!(
+            kick.fields,
+            vec![("reason", FieldValue::Str("bye".to_string()))]
+        );
+        assert!(kick.rest.is_empty());
+        assert_eq!(kick.to_string(), r#"KICK(150) { reason: "bye" }"#);
+
+        let chat = Packet::from_message(&Chat {
+            message: "hi".to_string(),
+            sender: None,
+            team: 3,
+        })
+        .decoded();
+        assert_eq!(
+            chat.fields[0],
+            ("message", FieldValue::Str("hi".to_string()))
+        );
+        assert!(chat.fields.contains(&("sender", FieldValue::OptStr(None))));
+        assert!(chat.rest.is_empty());
+    }
+
+    #[test]
+    fn decode_forwarded_packet() {
+        let inner = Packet::from_message(&HeartBeat { time: 42 });
+        let from = Packet::from_message(&PacketForwardClientFrom::new(7, &inner)).decoded();
+        assert_eq!(from.fields[0], ("index", FieldValue::U32(7)));
+        let Some((_, FieldValue::Packet(packet))) = from.fields.last() else {
+            panic!("missing forwarded packet: {:?}", from.fields);
+        };
+        assert_eq!(packet.packet_type, PacketType::HEART_BEAT as u32);
+        assert_eq!(packet.fields, vec![("time", FieldValue::U64(42))]);
+        assert!(from.rest.is_empty());
+    }
+
+    #[test]
+    fn keep_truncated_and_unknown_data() {
+        //字符串长度超过剩余数据时保留已经解析的字段和原始数据
+        let message = decode_packet(PacketType::RELAY_117 as u32, &[1, 0, 0, 0, 5, 0, 9, b'b']);
+        assert_eq!(
+            message.fields,
+            vec![
+                ("unknown", FieldValue::U8(1)),
+                ("message_type", FieldValue::U32(5))
+            ]
+        );
+        assert_eq!(message.rest, vec![0, 9, b'b']);
+
+        let unknown = decode_packet(0xdead_beef, &[1, 2, 0xff]);
+        assert!(unknown.fields.is_empty());
+        assert_eq!(unknown.rest, vec![1, 2, 0xff]);
+        assert_eq!(unknown.to_string(), "UNKNOWN(3735928559) 0102ff");
+
+        let long = decode_packet(0xdead_beef, &Bytes::from(vec![0; HEX_PREVIEW_LENGTH + 1]));
+        assert!(long.to_string().ends_with("..(+1 bytes)"));
+        assert!(!format!("{:#}", long).contains(".."));
+    }
+}
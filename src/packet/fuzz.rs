@@ -0,0 +1,268 @@
+//用随机数据测试数据包的解析,这些数据都来自不可信的客户端
+//proptest发现的失败用例会保存在proptest-regressions目录下
+use bytes::{BufMut, Bytes, BytesMut};
+use proptest::prelude::*;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::error::PacketDecodeError;
+
+use super::{
+    codec::{PacketBuf, PacketBufMut, PacketCodec, MAX_PACKET_LENGTH},
+    decoder::decode_packet,
+    message::*,
+    Packet, PacketType,
+};
+
+static KNOWN_TYPES: &[PacketType] = &[
+    PacketType::PREREGISTER_INFO_RECEIVE,
+    PacketType::RELAY_VERSION_INFO,
+    PacketType::RELAY_117,
+    PacketType::RELAY_118_117_RETURN,
+    PacketType::RELAY_BECOME_SERVER,
+    PacketType::FORWARD_CLIENT_ADD,
+    PacketType::PACKET_FORWARD_CLIENT_FROM,
+    PacketType::PACKET_FORWARD_CLIENT_TO,
+    PacketType::PACKET_FORWARD_CLIENT_TO_REPEATED,
+    PacketType::CHAT,
+    PacketType::CHAT_RECEIVE,
+    PacketType::HEART_BEAT,
+    PacketType::HEART_BEAT_RESPONSE,
+    PacketType::KICK,
+    PacketType::TICK,
+];
+
+fn packet_type() -> impl Strategy<Value = PacketType> {
+    prop::sample::select(KNOWN_TYPES)
+}
+
+fn bytes(max: usize) -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(any::<u8>(), 0..max)
+}
+
+//在随机位置把数据切开,模拟TCP分段
+fn split(data: &[u8], cuts: &[usize]) -> Vec<Vec<u8>> {
+    let mut cuts = cuts
+        .iter()
+        .map(|cut| cut % (data.len() + 1))
+        .collect::<Vec<_>>();
+    cuts.sort_unstable();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    for cut in cuts.into_iter().chain([data.len()]) {
+        chunks.push(data[start..cut].to_vec());
+        start = cut;
+    }
+    chunks
+}
+
+//按顺序送入数据并尽量多地解析,出错后停止,和接收端的行为一致
+fn decode_stream(chunks: &[Vec<u8>]) -> Result<Vec<Packet>, ()> {
+    let mut codec = PacketCodec;
+    let mut src = BytesMut::new();
+    let mut packets = Vec::new();
+    for chunk in chunks {
+        src.put_slice(chunk);
+        loop {
+            match codec.decode(&mut src) {
+                Ok(Some(packet)) => packets.push(packet),
+                Ok(None) => break,
+                Err(_) => return Err(()),
+            }
+        }
+    }
+    Ok(packets)
+}
+
+fn decode_all_messages(data: &[u8]) {
+    macro_rules! decode {
+        ($($message:ty),*) => {
+            $(let _ = <$message>::decode(&mut Bytes::copy_from_slice(data));)*
+        };
+    }
+    decode!(
+        PreregisterInfo,
+        RelayVersionInfo,
+        RelayHallMessage,
+        RelayHallCommand,
+        RelayBecomeServer,
+        ForwardClientAdd,
+        PacketForwardClientFrom,
+        PacketForwardClientTo,
+        Chat,
+        HeartBeat,
+        HeartBeatResponse,
+        Kick,
+        ReconnectTo,
+        ChatReceive,
+        ServerInfo,
+        TeamList
+    );
+}
+
+proptest! {
+    #[test]
+    fn framing_never_panics(data in bytes(1024), cuts in prop::collection::vec(any::<usize>(), 0..8)) {
+        let _ = decode_stream(&split(&data, &cuts));
+    }
+
+    #[test]
+    fn framing_survives_any_split(
+        packets in prop::collection::vec((packet_type(), bytes(256)), 1..8),
+        cuts in prop::collection::vec(any::<usize>(), 0..16),
+    ) {
+        let packets = packets
+            .into_iter()
+            .filter(|(_, payload)| !payload.is_empty())
+            .map(|(packet_type, payload)| Packet::new(packet_type, payload))
+            .collect::<Vec<_>>();
+        let mut encoded = BytesMut::new();
+        for packet in packets.iter() {
+            PacketCodec.encode(packet.clone(), &mut encoded).unwrap();
+        }
+        prop_assert_eq!(decode_stream(&split(&encoded, &cuts)), Ok(packets));
+    }
+
+    #[test]
+    fn framing_rejects_bad_length(length in prop_oneof![i32::MIN..=0, MAX_PACKET_LENGTH..=i32::MAX], rest in bytes(64)) {
+        let mut data = length.to_be_bytes().to_vec();
+        data.extend_from_slice(&(PacketType::HEART_BEAT as u32).to_be_bytes());
+        data.extend_from_slice(&rest);
+        prop_assert_eq!(decode_stream(&[data]), Err(()));
+    }
+
+    //读取失败时不能消耗数据,之后的读取仍然从原位置开始
+    #[test]
+    fn readers_never_panic(data in bytes(128), reads in prop::collection::vec(0..6u8, 0..32)) {
+        let mut buf = Bytes::from(data);
+        for read in reads {
+            let before = buf.len();
+            let failed = match read {
+                0 => buf.read_u8_checked().is_err(),
+                1 => buf.read_u32_checked().is_err(),
+                2 => buf.read_u64_checked().is_err(),
+                3 => buf.read_string_checked().is_err(),
+                4 => buf.read_is_string_checked().is_err(),
+                _ => buf.read_bytes_checked(before / 2 + 1).is_err(),
+            };
+            if failed {
+                prop_assert_eq!(buf.len(), before);
+            }
+        }
+    }
+
+    #[test]
+    fn messages_never_panic(data in bytes(256)) {
+        decode_all_messages(&data);
+    }
+
+    #[test]
+    fn decoder_never_panics(packet_type in any::<u32>(), known in packet_type(), data in bytes(256)) {
+        for packet_type in [packet_type, known as u32] {
+            let decoded = decode_packet(packet_type, &data);
+            let _ = format!("{} {:#}", decoded, decoded);
+        }
+    }
+
+    #[test]
+    fn preregister_round_trip(
+        domain in ".{0,32}",
+        packet_version in 0..5u32,
+        client_version in any::<u32>(),
+        unknown in any::<u32>(),
+        query_string in proptest::option::of(".{0,32}"),
+        player_name in ".{0,32}",
+        rest in bytes(16),
+    ) {
+        let info = PreregisterInfo {
+            domain,
+            packet_version,
+            client_version,
+            unknown: if packet_version >= 1 { unknown } else { 0 },
+            query_string: query_string.filter(|_| packet_version >= 2),
+            player_name: (packet_version >= 3).then_some(player_name),
+            rest: Bytes::from(rest),
+        };
+        let packet = Packet::from_message(&info);
+        prop_assert_eq!(packet.to_message::<PreregisterInfo>(), Ok(info));
+    }
+
+    #[test]
+    fn hall_command_round_trip(command in ".{0,64}") {
+        let packet = Packet::from_message(&RelayHallCommand { command: command.clone() });
+        prop_assert_eq!(packet.to_message::<RelayHallCommand>().map(|m| m.command), Ok(command));
+    }
+
+    //转发的数据包长度字段可以是任意值
+    #[test]
+    fn forward_length_is_checked(index in any::<u32>(), length in any::<u32>(), data in bytes(64)) {
+        let mut buf = BytesMut::new();
+        buf.put_u32(index);
+        buf.put_u32(PacketType::TICK as u32);
+        buf.put_u32(length);
+        buf.put_slice(&data);
+        let packet = Packet::new(PacketType::PACKET_FORWARD_CLIENT_TO, buf.freeze());
+        let result = packet.to_message::<PacketForwardClientTo>();
+        if length as usize <= data.len() {
+            prop_assert_eq!(result.map(|m| m.payload.len()), Ok(length as usize));
+        } else {
+            prop_assert_eq!(result, Err(PacketDecodeError::UnexpectedEof));
+        }
+    }
+}
+
+//以前的解析代码直接unwrap,下面这些数据会让处理线程panic
+#[test]
+fn truncated_packets_regression() {
+    let cases: &[(PacketType, &[u8])] = &[
+        //客户端信息在版本号之后被截断
+        (
+            PacketType::PREREGISTER_INFO_RECEIVE,
+            &[0, 1, b'a', 0, 0, 0, 3, 0, 0, 0],
+        ),
+        //字符串长度超过数据长度
+        (PacketType::PREREGISTER_INFO_RECEIVE, &[0xff, 0xff, b'a']),
+        //大厅命令不足5个字节
+        (PacketType::RELAY_118_117_RETURN, &[1, 0]),
+        (
+            PacketType::RELAY_118_117_RETURN,
+            &[1, 0, 0, 0, 0, 0, 9, b'S'],
+        ),
+        //转发的数据长度超过剩余数据
+        (
+            PacketType::PACKET_FORWARD_CLIENT_TO,
+            &[0, 0, 0, 1, 0, 0, 0, 10, 0xff, 0xff, 0xff, 0xff],
+        ),
+        (PacketType::HEART_BEAT, &[0, 0, 0]),
+    ];
+    for (packet_type, payload) in cases {
+        let packet = Packet::new(*packet_type, Bytes::from_static(payload));
+        let result = match packet_type {
+            PacketType::PREREGISTER_INFO_RECEIVE => {
+                packet.to_message::<PreregisterInfo>().map(drop)
+            }
+            PacketType::RELAY_118_117_RETURN => packet.to_message::<RelayHallCommand>().map(drop),
+            PacketType::PACKET_FORWARD_CLIENT_TO => {
+                packet.to_message::<PacketForwardClientTo>().map(drop)
+            }
+            _ => packet.to_message::<HeartBeat>().map(drop),
+        };
+        assert_eq!(
+            result,
+            Err(PacketDecodeError::UnexpectedEof),
+            "{:?}",
+            packet
+        );
+    }
+}
+
+#[test]
+fn string_length_fits_u16() {
+    //超过u16的字符串会写出错误的长度,解析时和后面的数据错位
+    let mut buf = BytesMut::new();
+    buf.put_string(&"a".repeat(u16::MAX as usize + 1));
+    buf.put_u8(7);
+    let mut buf = buf.freeze();
+    let s = buf.read_string_checked().unwrap();
+    assert_eq!(s.len(), u16::MAX as usize);
+    assert_eq!(buf.read_u8_checked(), Ok(7));
+}
@@ -0,0 +1,1004 @@
+use std::io::{Read, Write};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::error::PacketDecodeError;
+
+use super::{
+    codec::{PacketBuf, PacketBufMut, MAX_PACKET_LENGTH},
+    Packet, PacketType,
+};
+
+//服务器的relay版本
+pub const RELAY_VERSION: u32 = 151;
+//大厅消息在RELAY_117中的类型
+pub const HALL_MESSAGE_TYPE: u32 = 5;
+//RELAY_BECOME_SERVER和FORWARD_CLIENT_ADD的新协议
+pub const RELAY_BECOME_SERVER_PROTOCOL: u8 = 2;
+pub const FORWARD_CLIENT_ADD_PROTOCOL: u8 = 1;
+//房主作为游戏服务器时的标识
+pub const SERVER_ID: &str = "com.corrodinggames.rts.server";
+
+//每种数据包对应一个结构体,数据均为大端
+//字符串为u16长度+UTF-8,可选字符串前面多一个u8标记
+pub trait PacketMessage: Sized {
+    const PACKET_TYPE: PacketType;
+
+    fn encode(&self, buf: &mut BytesMut);
+
+    //从数据包的数据部分读取,多余的数据留在buf中
+    //读出的Bytes字段与数据包共享内存
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError>;
+}
+
+impl Packet {
+    pub fn from_message<M: PacketMessage>(message: &M) -> Self {
+        let mut buf = BytesMut::new();
+        message.encode(&mut buf);
+        Packet::new(M::PACKET_TYPE, buf.freeze())
+    }
+
+    pub fn to_message<M: PacketMessage>(&self) -> Result<M, PacketDecodeError> {
+        if self.packet_type != M::PACKET_TYPE {
+            return Err(PacketDecodeError::WrongType {
+                expected: M::PACKET_TYPE,
+                actual: self.packet_type,
+            });
+        }
+        M::decode(&mut self.payload.clone())
+    }
+}
+
+//客户端连接后发送的第一个数据包
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreregisterInfo {
+    pub domain: String,
+    pub packet_version: u32,
+    pub client_version: u32,
+    //packet_version >= 1
+    pub unknown: u32,
+    //packet_version >= 2
+    pub query_string: Option<String>,
+    //packet_version >= 3
+    pub player_name: Option<String>,
+    //语言等服务器不关心的数据
+    pub rest: Bytes,
+}
+
+impl PacketMessage for PreregisterInfo {
+    const PACKET_TYPE: PacketType = PacketType::PREREGISTER_INFO_RECEIVE;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_string(&self.domain);
+        buf.put_u32(self.packet_version);
+        buf.put_u32(self.client_version);
+        if self.packet_version >= 1 {
+            buf.put_u32(self.unknown);
+        }
+        if self.packet_version >= 2 {
+            buf.put_is_string(self.query_string.as_deref());
+        }
+        if self.packet_version >= 3 {
+            buf.put_string(self.player_name.as_deref().unwrap_or_default());
+        }
+        buf.put_slice(&self.rest);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let domain = buf.read_string_checked()?;
+        let packet_version = buf.read_u32_checked()?;
+        let client_version = buf.read_u32_checked()?;
+        let unknown = if packet_version >= 1 {
+            buf.read_u32_checked()?
+        } else {
+            0
+        };
+        let query_string = if packet_version >= 2 {
+            buf.read_is_string_checked()?
+        } else {
+            None
+        };
+        let player_name = if packet_version >= 3 {
+            Some(buf.read_string_checked()?)
+        } else {
+            None
+        };
+        Ok(Self {
+            domain,
+            packet_version,
+            client_version,
+            unknown,
+            query_string,
+            player_name,
+            rest: std::mem::take(buf),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayVersionInfo {
+    pub version: u32,
+}
+
+impl PacketMessage for RelayVersionInfo {
+    const PACKET_TYPE: PacketType = PacketType::RELAY_VERSION_INFO;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(0);
+        buf.put_u32(self.version);
+        buf.put_u32(1);
+        buf.put_u8(0);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        buf.read_u8_checked()?;
+        let version = buf.read_u32_checked()?;
+        buf.read_u32_checked()?;
+        buf.read_u8_checked()?;
+        Ok(Self { version })
+    }
+}
+
+//服务器发给大厅中玩家的消息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayHallMessage {
+    pub message: String,
+}
+
+impl PacketMessage for RelayHallMessage {
+    const PACKET_TYPE: PacketType = PacketType::RELAY_117;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(1);
+        buf.put_u32(HALL_MESSAGE_TYPE);
+        buf.put_string(&self.message);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        buf.read_u8_checked()?;
+        buf.read_u32_checked()?;
+        Ok(Self {
+            message: buf.read_string_checked()?,
+        })
+    }
+}
+
+//玩家在大厅中输入的命令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayHallCommand {
+    pub command: String,
+}
+
+impl PacketMessage for RelayHallCommand {
+    const PACKET_TYPE: PacketType = PacketType::RELAY_118_117_RETURN;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(1);
+        buf.put_u32(0);
+        buf.put_string(&self.command);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        //跳过无用的一个byte和一个int32
+        buf.read_u8_checked()?;
+        buf.read_u32_checked()?;
+        Ok(Self {
+            command: buf.read_string_checked()?,
+        })
+    }
+}
+
+//告诉房主房间已创建,只支持新协议
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayBecomeServer {
+    pub team: String,
+    pub mods: bool,
+    pub public: bool,
+    pub description: String,
+    pub uuid: Option<String>,
+}
+
+impl RelayBecomeServer {
+    //描述中带有房间号,如{RW-RJR Relay}.Room ID : S1234
+    pub fn room_id(&self) -> Option<String> {
+        let rest = self.description.split("Room ID : ").nth(1)?;
+        let id = rest
+            .chars()
+            .take_while(|c| *c == 'S' || c.is_ascii_digit())
+            .collect::<String>();
+        Some(id).filter(|id| !id.is_empty())
+    }
+}
+
+impl PacketMessage for RelayBecomeServer {
+    const PACKET_TYPE: PacketType = PacketType::RELAY_BECOME_SERVER;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(RELAY_BECOME_SERVER_PROTOCOL);
+        buf.put_u8(1);
+        buf.put_u8(1);
+        buf.put_u8(1);
+        buf.put_string(&self.team);
+        buf.put_u8(self.mods as u8);
+        buf.put_u8(self.public as u8);
+        buf.put_u8(1);
+        buf.put_string(&self.description);
+        buf.put_u8(self.public as u8);
+        buf.put_is_string(self.uuid.as_deref());
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let protocol = buf.read_u8_checked()?;
+        if protocol < RELAY_BECOME_SERVER_PROTOCOL {
+            return Err(PacketDecodeError::UnsupportedProtocol(protocol));
+        }
+        buf.read_u8_checked()?;
+        buf.read_u8_checked()?;
+        buf.read_u8_checked()?;
+        let team = buf.read_string_checked()?;
+        let mods = buf.read_u8_checked()? != 0;
+        let public = buf.read_u8_checked()? != 0;
+        buf.read_u8_checked()?;
+        let description = buf.read_string_checked()?;
+        buf.read_u8_checked()?;
+        let uuid = buf.read_is_string_checked()?;
+        Ok(Self {
+            team,
+            mods,
+            public,
+            description,
+            uuid,
+        })
+    }
+}
+
+//通知房主有新玩家加入,只支持新协议
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardClientAdd {
+    pub index: u32,
+    pub uuid: String,
+    pub ip: Option<String>,
+}
+
+impl PacketMessage for ForwardClientAdd {
+    const PACKET_TYPE: PacketType = PacketType::FORWARD_CLIENT_ADD;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(FORWARD_CLIENT_ADD_PROTOCOL);
+        buf.put_u32(self.index);
+        buf.put_string(&self.uuid);
+        buf.put_u8(0);
+        buf.put_is_string(self.ip.as_deref());
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let protocol = buf.read_u8_checked()?;
+        if protocol < FORWARD_CLIENT_ADD_PROTOCOL {
+            return Err(PacketDecodeError::UnsupportedProtocol(protocol));
+        }
+        let index = buf.read_u32_checked()?;
+        let uuid = buf.read_string_checked()?;
+        buf.read_u8_checked()?;
+        let ip = buf.read_is_string_checked()?;
+        Ok(Self { index, uuid, ip })
+    }
+}
+
+//玩家发给房主的数据包
+//index | u32长度+8 | u32长度 | u32类型 | 数据
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketForwardClientFrom {
+    pub index: u32,
+    pub packet_type: u32,
+    pub payload: Bytes,
+}
+
+impl PacketForwardClientFrom {
+    pub fn new(index: u32, packet: &Packet) -> Self {
+        Self {
+            index,
+            packet_type: packet.packet_type as u32,
+            payload: packet.payload.clone(),
+        }
+    }
+}
+
+impl PacketMessage for PacketForwardClientFrom {
+    const PACKET_TYPE: PacketType = PacketType::PACKET_FORWARD_CLIENT_FROM;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.index);
+        buf.put_u32(self.payload.len() as u32 + 8);
+        buf.put_u32(self.payload.len() as u32);
+        buf.put_u32(self.packet_type);
+        buf.put_slice(&self.payload);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let index = buf.read_u32_checked()?;
+        buf.read_u32_checked()?;
+        let length = buf.read_u32_checked()?;
+        let packet_type = buf.read_u32_checked()?;
+        let payload = buf.read_bytes_checked(length as usize)?;
+        Ok(Self {
+            index,
+            packet_type,
+            payload,
+        })
+    }
+}
+
+//房主发给玩家的数据包
+//index | u32类型 | u32长度 | 数据
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketForwardClientTo {
+    pub index: u32,
+    pub packet_type: u32,
+    pub payload: Bytes,
+}
+
+impl PacketForwardClientTo {
+    pub fn new(index: u32, packet: &Packet) -> Self {
+        Self {
+            index,
+            packet_type: packet.packet_type as u32,
+            payload: packet.payload.clone(),
+        }
+    }
+}
+
+impl PacketMessage for PacketForwardClientTo {
+    const PACKET_TYPE: PacketType = PacketType::PACKET_FORWARD_CLIENT_TO;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.index);
+        buf.put_u32(self.packet_type);
+        buf.put_u32(self.payload.len() as u32);
+        buf.put_slice(&self.payload);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let index = buf.read_u32_checked()?;
+        let packet_type = buf.read_u32_checked()?;
+        let length = buf.read_u32_checked()?;
+        let payload = buf.read_bytes_checked(length as usize)?;
+        Ok(Self {
+            index,
+            packet_type,
+            payload,
+        })
+    }
+}
+
+//服务器发给房间中玩家的聊天消息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chat {
+    pub message: String,
+    pub sender: Option<String>,
+    pub team: u32,
+}
+
+impl PacketMessage for Chat {
+    const PACKET_TYPE: PacketType = PacketType::CHAT;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_string(&self.message);
+        buf.put_u8(3);
+        buf.put_is_string(self.sender.as_deref());
+        buf.put_u32(self.team);
+        buf.put_u32(self.team);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let message = buf.read_string_checked()?;
+        buf.read_u8_checked()?;
+        let sender = buf.read_is_string_checked()?;
+        let team = buf.read_u32_checked()?;
+        buf.read_u32_checked()?;
+        Ok(Self {
+            message,
+            sender,
+            team,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeartBeat {
+    pub time: u64,
+}
+
+impl PacketMessage for HeartBeat {
+    const PACKET_TYPE: PacketType = PacketType::HEART_BEAT;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u64(self.time);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        Ok(Self {
+            time: buf.read_u64_checked()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeartBeatResponse {
+    pub time: u64,
+}
+
+impl PacketMessage for HeartBeatResponse {
+    const PACKET_TYPE: PacketType = PacketType::HEART_BEAT_RESPONSE;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u64(self.time);
+        buf.put_u8(1);
+        buf.put_u8(60);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let time = buf.read_u64_checked()?;
+        buf.read_u8_checked()?;
+        buf.read_u8_checked()?;
+        Ok(Self { time })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Kick {
+    pub reason: String,
+}
+
+impl PacketMessage for Kick {
+    const PACKET_TYPE: PacketType = PacketType::KICK;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_string(&self.reason);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        Ok(Self {
+            reason: buf.read_string_checked()?,
+        })
+    }
+}
+
+//让客户端重连到另一台服务器
+//格式参考RW-HPS的fromRelayJumpsToAnotherServer,客户端重连后仍需输入房间号
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectTo {
+    pub addr: String,
+}
+
+impl PacketMessage for ReconnectTo {
+    const PACKET_TYPE: PacketType = PacketType::PACKET_RECONNECT_TO;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(0);
+        buf.put_u32(3);
+        buf.put_u8(0);
+        buf.put_u32(1);
+        buf.put_string(&self.addr);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        buf.read_u8_checked()?;
+        buf.read_u32_checked()?;
+        buf.read_u8_checked()?;
+        buf.read_u32_checked()?;
+        Ok(Self {
+            addr: buf.read_string_checked()?,
+        })
+    }
+}
+
+//玩家在房间中发出的聊天,由房主转发给所有人
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatReceive {
+    pub message: String,
+}
+
+impl PacketMessage for ChatReceive {
+    const PACKET_TYPE: PacketType = PacketType::CHAT_RECEIVE;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_string(&self.message);
+        buf.put_u8(0);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let message = buf.read_string_checked()?;
+        buf.read_u8_checked()?;
+        Ok(Self { message })
+    }
+}
+
+//房间设置,SERVER_INFO和TEAM_LIST中都有
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomSettings {
+    pub fog: u32,
+    pub credits: u32,
+    pub max_unit: u32,
+    pub init_unit: u32,
+    pub income: f32,
+    pub no_nukes: bool,
+    pub shared_control: bool,
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        Self {
+            fog: 2,
+            credits: 0,
+            max_unit: 200,
+            init_unit: 1,
+            income: 1.,
+            no_nukes: false,
+            shared_control: false,
+        }
+    }
+}
+
+//房主在玩家注册后发送的服务器信息
+//格式参考RW-HPS的1.15服务端,未知的字段使用固定值
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    pub version: u32,
+    pub map_name: String,
+    pub settings: RoomSettings,
+    //收到的玩家是否为房间管理员
+    pub is_admin: bool,
+}
+
+impl PacketMessage for ServerInfo {
+    const PACKET_TYPE: PacketType = PacketType::SERVER_INFO;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_string(SERVER_ID);
+        buf.put_u32(self.version);
+        //自定义地图
+        buf.put_u32(0);
+        buf.put_string(&self.map_name);
+        buf.put_u32(self.settings.credits);
+        buf.put_u32(self.settings.fog);
+        buf.put_u8(1);
+        buf.put_u32(1);
+        buf.put_u8(7);
+        buf.put_u8(0);
+        buf.put_u8(self.is_admin as u8);
+        buf.put_u32(self.settings.max_unit);
+        buf.put_u32(self.settings.max_unit);
+        buf.put_u32(self.settings.init_unit);
+        buf.put_f32(self.settings.income);
+        buf.put_u8(self.settings.no_nukes as u8);
+        buf.put_u8(0);
+        buf.put_u8(0);
+        buf.put_u8(self.settings.shared_control as u8);
+        buf.put_u8(0);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        buf.read_string_checked()?;
+        let version = buf.read_u32_checked()?;
+        buf.read_u32_checked()?;
+        let map_name = buf.read_string_checked()?;
+        let credits = buf.read_u32_checked()?;
+        let fog = buf.read_u32_checked()?;
+        buf.read_bytes_checked(1 + 4 + 1 + 1)?;
+        let is_admin = buf.read_u8_checked()? != 0;
+        let max_unit = buf.read_u32_checked()?;
+        buf.read_u32_checked()?;
+        let init_unit = buf.read_u32_checked()?;
+        let income = f32::from_bits(buf.read_u32_checked()?);
+        let no_nukes = buf.read_u8_checked()? != 0;
+        buf.read_bytes_checked(2)?;
+        let shared_control = buf.read_u8_checked()? != 0;
+        buf.read_u8_checked()?;
+        Ok(Self {
+            version,
+            map_name,
+            settings: RoomSettings {
+                fog,
+                credits,
+                max_unit,
+                init_unit,
+                income,
+                no_nukes,
+                shared_control,
+            },
+            is_admin,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TeamPlayer {
+    pub site: u32,
+    pub team: u32,
+    pub name: String,
+    pub ping: u32,
+}
+
+//房间中的玩家列表,每个玩家收到的site是自己的位置
+//玩家数据经过gzip压缩,前面是"teams"和压缩后的长度
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeamList {
+    pub site: u32,
+    pub start_game: bool,
+    //按位置排列,空位为None
+    pub players: Vec<Option<TeamPlayer>>,
+    pub settings: RoomSettings,
+}
+
+impl TeamList {
+    fn encode_players(&self) -> Vec<u8> {
+        let mut players = BytesMut::new();
+        for player in self.players.iter() {
+            match player {
+                Some(player) => {
+                    players.put_u8(1);
+                    players.put_u32(0);
+                    players.put_u8(0);
+                    players.put_u32(player.site);
+                    players.put_u32(player.team);
+                    players.put_string(&player.name);
+                    players.put_u8(0);
+                    players.put_u32(player.ping);
+                    players.put_u64(0);
+                    //不是AI
+                    players.put_u8(0);
+                    players.put_u32(0);
+                    players.put_u32(player.site);
+                    players.put_u8(0);
+                }
+                None => players.put_u8(0),
+            }
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        //写入Vec不会失败
+        let _ = encoder.write_all(&players);
+        encoder.finish().unwrap_or_default()
+    }
+
+    fn decode_players(
+        compressed: &[u8],
+        max_player: u32,
+    ) -> Result<Vec<Option<TeamPlayer>>, PacketDecodeError> {
+        let mut data = Vec::new();
+        GzDecoder::new(compressed)
+            .take(MAX_PACKET_LENGTH as u64)
+            .read_to_end(&mut data)
+            .map_err(|_| PacketDecodeError::InvalidCompressedData)?;
+        let mut buf = Bytes::from(data);
+        let mut players = Vec::new();
+        for _ in 0..max_player {
+            if buf.read_u8_checked()? == 0 {
+                players.push(None);
+                continue;
+            }
+            buf.read_bytes_checked(4 + 1)?;
+            let site = buf.read_u32_checked()?;
+            let team = buf.read_u32_checked()?;
+            let name = buf.read_string_checked()?;
+            buf.read_u8_checked()?;
+            let ping = buf.read_u32_checked()?;
+            buf.read_bytes_checked(8 + 1 + 4 + 4 + 1)?;
+            players.push(Some(TeamPlayer {
+                site,
+                team,
+                name,
+                ping,
+            }));
+        }
+        Ok(players)
+    }
+}
+
+impl PacketMessage for TeamList {
+    const PACKET_TYPE: PacketType = PacketType::TEAM_LIST;
+
+    fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u32(self.site);
+        buf.put_u8(self.start_game as u8);
+        buf.put_u32(self.players.len() as u32);
+        let players = self.encode_players();
+        buf.put_string("teams");
+        buf.put_u32(players.len() as u32);
+        buf.put_slice(&players);
+        buf.put_u32(self.settings.fog);
+        buf.put_u32(self.settings.credits);
+        buf.put_u8(1);
+        buf.put_u32(1);
+        buf.put_u8(0);
+        buf.put_u32(self.settings.max_unit);
+        buf.put_u32(self.settings.max_unit);
+        buf.put_u32(self.settings.init_unit);
+        buf.put_f32(self.settings.income);
+        buf.put_u8(self.settings.no_nukes as u8);
+        buf.put_u8(0);
+        buf.put_u8(0);
+        buf.put_u8(self.settings.shared_control as u8);
+        //游戏未暂停
+        buf.put_u8(0);
+    }
+
+    fn decode(buf: &mut Bytes) -> Result<Self, PacketDecodeError> {
+        let site = buf.read_u32_checked()?;
+        let start_game = buf.read_u8_checked()? != 0;
+        let max_player = buf.read_u32_checked()?;
+        buf.read_string_checked()?;
+        let length = buf.read_u32_checked()?;
+        let players = Self::decode_players(&buf.read_bytes_checked(length as usize)?, max_player)?;
+        let fog = buf.read_u32_checked()?;
+        let credits = buf.read_u32_checked()?;
+        buf.read_bytes_checked(1 + 4 + 1)?;
+        let max_unit = buf.read_u32_checked()?;
+        buf.read_u32_checked()?;
+        let init_unit = buf.read_u32_checked()?;
+        let income = f32::from_bits(buf.read_u32_checked()?);
+        let no_nukes = buf.read_u8_checked()? != 0;
+        buf.read_bytes_checked(2)?;
+        let shared_control = buf.read_u8_checked()? != 0;
+        buf.read_u8_checked()?;
+        Ok(Self {
+            site,
+            start_game,
+            players,
+            settings: RoomSettings {
+                fog,
+                credits,
+                max_unit,
+                init_unit,
+                income,
+                no_nukes,
+                shared_control,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<M: PacketMessage + std::fmt::Debug + PartialEq>(message: M) -> Bytes {
+        let mut buf = BytesMut::new();
+        message.encode(&mut buf);
+        let buf = buf.freeze();
+        let mut reader = buf.clone();
+        assert_eq!(M::decode(&mut reader).unwrap(), message);
+        assert!(reader.is_empty(), "{} bytes left", reader.len());
+        buf
+    }
+
+    #[test]
+    fn chat_receive_round_trip() {
+        round_trip(ChatReceive {
+            message: "你好".to_string(),
+        });
+    }
+
+    #[test]
+    fn server_info_round_trip() {
+        round_trip(ServerInfo {
+            version: 176,
+            map_name: "[z;p10]Crossing Large (10p)".to_string(),
+            settings: RoomSettings {
+                income: 1.5,
+                no_nukes: true,
+                ..Default::default()
+            },
+            is_admin: false,
+        });
+    }
+
+    #[test]
+    fn team_list_round_trip() {
+        let player = |site: u32, name: &str| TeamPlayer {
+            site,
+            team: site % 2,
+            name: name.to_string(),
+            ping: 50,
+        };
+        round_trip(TeamList {
+            site: 2,
+            start_game: false,
+            players: vec![Some(player(0, "房主")), None, Some(player(2, "玩家")), None],
+            settings: RoomSettings::default(),
+        });
+    }
+
+    #[test]
+    fn team_list_rejects_bad_gzip() {
+        let mut buf = BytesMut::new();
+        buf.put_u32(0);
+        buf.put_u8(0);
+        buf.put_u32(1);
+        buf.put_string("teams");
+        buf.put_u32(3);
+        buf.put_slice(&[1, 2, 3]);
+        assert_eq!(
+            TeamList::decode(&mut buf.freeze()),
+            Err(PacketDecodeError::InvalidCompressedData)
+        );
+    }
+
+    #[test]
+    fn preregister_info_round_trip() {
+        for packet_version in 0..=4 {
+            round_trip(PreregisterInfo {
+                domain: "com.corrodinggames.rts".to_string(),
+                packet_version,
+                client_version: 176,
+                unknown: if packet_version >= 1 { 2 } else { 0 },
+                query_string: (packet_version >= 2).then(|| "query".to_string()),
+                player_name: (packet_version >= 3).then(|| "玩家".to_string()),
+                rest: Bytes::from_static(&[0, 2, b'z', b'h', 0, 0]),
+            });
+        }
+    }
+
+    #[test]
+    fn relay_version_info_bytes() {
+        let buf = round_trip(RelayVersionInfo {
+            version: RELAY_VERSION,
+        });
+        assert_eq!(&buf[..], [0, 0, 0, 0, 151, 0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn relay_hall_message_bytes() {
+        let buf = round_trip(RelayHallMessage {
+            message: "hi".to_string(),
+        });
+        assert_eq!(&buf[..], [1, 0, 0, 0, 5, 0, 2, b'h', b'i']);
+    }
+
+    #[test]
+    fn relay_hall_command_round_trip() {
+        round_trip(RelayHallCommand {
+            command: "S1234".to_string(),
+        });
+        round_trip(RelayHallCommand {
+            command: String::new(),
+        });
+    }
+
+    #[test]
+    fn relay_become_server_round_trip() {
+        round_trip(RelayBecomeServer {
+            team: "RJR Team".to_string(),
+            mods: true,
+            public: false,
+            description: "{RW-RJR Relay}.Room ID : S1234".to_string(),
+            uuid: Some("6a3f".to_string()),
+        });
+        round_trip(RelayBecomeServer {
+            team: String::new(),
+            mods: false,
+            public: true,
+            description: String::new(),
+            uuid: None,
+        });
+    }
+
+    #[test]
+    fn relay_become_server_old_protocol() {
+        assert_eq!(
+            RelayBecomeServer::decode(&mut Bytes::from_static(&[1, 0, 0])),
+            Err(PacketDecodeError::UnsupportedProtocol(1))
+        );
+    }
+
+    #[test]
+    fn forward_client_add_bytes() {
+        let buf = round_trip(ForwardClientAdd {
+            index: 3,
+            uuid: "u".to_string(),
+            ip: Some("1.2.3.4".to_string()),
+        });
+        assert_eq!(&buf[..9], [1, 0, 0, 0, 3, 0, 1, b'u', 0]);
+        round_trip(ForwardClientAdd {
+            index: 0,
+            uuid: String::new(),
+            ip: None,
+        });
+    }
+
+    #[test]
+    fn forward_client_from_bytes() {
+        let buf = round_trip(PacketForwardClientFrom {
+            index: 2,
+            packet_type: PacketType::CHAT_RECEIVE as u32,
+            payload: Bytes::from(vec![0xaa, 0xbb]),
+        });
+        assert_eq!(
+            &buf[..],
+            [0, 0, 0, 2, 0, 0, 0, 10, 0, 0, 0, 2, 0, 0, 0, 140, 0xaa, 0xbb]
+        );
+    }
+
+    #[test]
+    fn forward_client_to_bytes() {
+        let buf = round_trip(PacketForwardClientTo {
+            index: 1,
+            packet_type: PacketType::TICK as u32,
+            payload: Bytes::from(vec![7]),
+        });
+        assert_eq!(&buf[..], [0, 0, 0, 1, 0, 0, 0, 10, 0, 0, 0, 1, 7]);
+    }
+
+    #[test]
+    fn chat_round_trip() {
+        round_trip(Chat {
+            message: "欢迎".to_string(),
+            sender: Some("RJR Server:".to_string()),
+            team: 5,
+        });
+        round_trip(Chat {
+            message: String::new(),
+            sender: None,
+            team: 0,
+        });
+    }
+
+    #[test]
+    fn heart_beat_round_trip() {
+        round_trip(HeartBeat { time: u64::MAX });
+        let buf = round_trip(HeartBeatResponse { time: 1 });
+        assert_eq!(&buf[..], [0, 0, 0, 0, 0, 0, 0, 1, 1, 60]);
+    }
+
+    #[test]
+    fn kick_and_reconnect_round_trip() {
+        round_trip(Kick {
+            reason: "bye".to_string(),
+        });
+        round_trip(ReconnectTo {
+            addr: "example.com:5123".to_string(),
+        });
+    }
+
+    #[test]
+    fn truncated_data() {
+        let mut buf = BytesMut::new();
+        PacketForwardClientTo {
+            index: 1,
+            packet_type: 10,
+            payload: Bytes::from(vec![1, 2, 3]),
+        }
+        .encode(&mut buf);
+        for length in 0..buf.len() {
+            assert_eq!(
+                PacketForwardClientTo::decode(&mut buf.clone().freeze().split_to(length)),
+                Err(PacketDecodeError::UnexpectedEof)
+            );
+        }
+    }
+
+    #[test]
+    fn packet_conversion() {
+        let message = HeartBeat { time: 42 };
+        let packet = Packet::from_message(&message);
+        assert_eq!(packet.to_message::<HeartBeat>(), Ok(message));
+        assert_eq!(
+            packet.to_message::<Kick>(),
+            Err(PacketDecodeError::WrongType {
+                expected: PacketType::KICK,
+                actual: PacketType::HEART_BEAT,
+            })
+        );
+    }
+
+    #[test]
+    fn forwarded_payload_is_not_copied() {
+        let packet = Packet::from_message(&PacketForwardClientTo {
+            index: 1,
+            packet_type: PacketType::TICK as u32,
+            payload: Bytes::from(vec![0; 64]),
+        });
+        let message = packet.to_message::<PacketForwardClientTo>().unwrap();
+        assert_eq!(message.payload.as_ptr(), packet.payload[12..].as_ptr());
+    }
+}
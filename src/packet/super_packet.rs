@@ -1,6 +1,6 @@
-use tokio::io::AsyncWriteExt;
+use bytes::{BufMut, BytesMut};
 
-use super::{Packet, PacketReadWriteExt, PacketType};
+use super::{codec::PacketBufMut, message::PacketForwardClientFrom, Packet, PacketType};
 
 /*
 02 00 00 00 45 00 00 8e 21 9d 40 00 40 06 00 00
@@ -45,52 +45,21 @@ static SET_TERRAIN_PACKET_2: [u8; 8] = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff
 pub struct SuperPacket;
 
 impl SuperPacket {
-    pub async fn set_terrain(x: f32, y: f32, terrain_name: &str) -> Packet {
-        let mut packet = Packet::new(PacketType::GAMECOMMAND_RECEIVE).await;
-
-        packet
-            .packet_buffer
-            .write_all(&SET_TERRAIN_PACKET_1)
-            .await
-            .expect("error");
-
-        packet.write_f32(x).await.expect("error");
-        packet.write_f32(y).await.expect("error");
-
-        packet
-            .packet_buffer
-            .write_all(&SET_TERRAIN_PACKET_2)
-            .await
-            .expect("error");
-
-        packet
-            .write_string(&format!("SetTerrainType{}", terrain_name))
-            .await
-            .expect("error");
-        packet.write_u32(0).await.unwrap();
-        packet.write_u32(0).await.unwrap();
-        packet.write_u8(0).await.unwrap();
-
-        Self::packet_to_host(packet).await
+    pub fn set_terrain(x: f32, y: f32, terrain_name: &str) -> Packet {
+        let mut buf = BytesMut::new();
+        buf.put_slice(&SET_TERRAIN_PACKET_1);
+        buf.put_f32(x);
+        buf.put_f32(y);
+        buf.put_slice(&SET_TERRAIN_PACKET_2);
+        buf.put_string(&format!("SetTerrainType{}", terrain_name));
+        buf.put_u32(0);
+        buf.put_u32(0);
+        buf.put_u8(0);
+
+        Self::packet_to_host(Packet::new(PacketType::GAMECOMMAND_RECEIVE, buf.freeze()))
     }
 
-    pub async fn packet_to_host(mut packet: Packet) -> Packet {
-        packet.prepare().await;
-        let mut send_packet = Packet::new(PacketType::PACKET_FORWARD_CLIENT_FROM).await;
-
-        send_packet.write_u32(0).await.unwrap();
-        send_packet
-            .packet_buffer
-            .write_u32(packet.packet_length + 8)
-            .await
-            .unwrap();
-
-        send_packet
-            .packet_buffer
-            .write_all(&packet.packet_buffer.into_inner())
-            .await
-            .unwrap();
-
-        send_packet
+    pub fn packet_to_host(packet: Packet) -> Packet {
+        Packet::from_message(&PacketForwardClientFrom::new(0, &packet))
     }
 }
@@ -6,6 +6,7 @@ use std::{
 };
 
 use chrono::{Datelike, Timelike, Utc};
+use log::info;
 use rand::{Rng, SeedableRng};
 use tokio::{
     runtime::Runtime,
@@ -14,8 +15,10 @@ use tokio::{
 };
 
 use crate::{
+    cluster::{Cluster, RemoteRoom},
     connection::{player_net_api::CustomRelayData, shared_connection::SharedConnection},
     core::creat_block_runtime,
+    server::reload::live_config,
     NOW,
 };
 
@@ -25,6 +28,8 @@ use self::relay::SharedRelayRoom;
 pub enum RelayManagerAPI {
     GetRelay(String, oneshot::Sender<Option<Arc<SharedRelayRoom>>>),
     InsertNewRelay(Arc<SharedRelayRoom>),
+    RemoveRelay(String, oneshot::Sender<Option<Arc<SharedRelayRoom>>>),
+    GetRelayList(oneshot::Sender<Vec<Arc<SharedRelayRoom>>>),
 }
 
 #[derive(Debug)]
@@ -38,10 +43,15 @@ pub struct SharedRelayManager {
     handle: JoinHandle<()>,
     pub relay_rt: Arc<Runtime>,
     pub id_rand: Arc<RwLock<rand::rngs::StdRng>>,
+    //未开启集群时为None
+    pub cluster: Option<Arc<Cluster>>,
 }
 
 impl SharedRelayManager {
-    pub async fn new(relay_mg_thread_number: usize) -> Arc<SharedRelayManager> {
+    pub async fn new(
+        relay_mg_thread_number: usize,
+        cluster: Option<Arc<Cluster>>,
+    ) -> Arc<SharedRelayManager> {
         let (relay_mg_api_tx, relay_mg_api_rx) = mpsc::channel(10);
 
         let relay_mg = RelayManager::new().await;
@@ -80,6 +90,13 @@ impl SharedRelayManager {
                             .room_map
                             .insert(new_shared.shared_data.id.clone(), new_shared);
                     }
+                    RelayManagerAPI::RemoveRelay(id, removed_tx) => {
+                        let id = format!("S{}", id);
+                        let _ = removed_tx.send(relay_mg.room_map.remove(&id));
+                    }
+                    RelayManagerAPI::GetRelayList(relay_list_tx) => {
+                        let _ = relay_list_tx.send(relay_mg.room_map.values().cloned().collect());
+                    }
                 }
             }
         });
@@ -92,6 +109,7 @@ impl SharedRelayManager {
                 NOW.elapsed().as_secs()
                     + (now.hour() + now.month() + now.day() + now.minute() + now.second()) as u64,
             ))),
+            cluster,
         })
     }
 
@@ -104,6 +122,42 @@ impl SharedRelayManager {
         shared_relay_rx.await.expect("recv relay index error")
     }
 
+    pub async fn get_relay_list(&self) -> Vec<Arc<SharedRelayRoom>> {
+        let (relay_list_tx, relay_list_rx) = oneshot::channel();
+        self.relay_mg_api_tx
+            .send(RelayManagerAPI::GetRelayList(relay_list_tx))
+            .await
+            .expect("send relay mg api tx error");
+        relay_list_rx.await.expect("recv relay list error")
+    }
+
+    //从房间表中移除房间并踢出其中的所有连接
+    pub async fn close_relay(&self, id: &str, reason: &str) -> bool {
+        let (removed_tx, removed_rx) = oneshot::channel();
+        self.relay_mg_api_tx
+            .send(RelayManagerAPI::RemoveRelay(id.to_string(), removed_tx))
+            .await
+            .expect("send relay mg api tx error");
+        match removed_rx.await.expect("recv removed relay error") {
+            Some(shared_relay) => {
+                info!(room_id = shared_relay.shared_data.id.as_str(); "房间已关闭:{}", reason);
+                if let Some(cluster) = &self.cluster {
+                    cluster.room_removed(&shared_relay.shared_data.id);
+                }
+                shared_relay.close(reason).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    //本节点没有该房间时到集群的其他节点查找,id不带S前缀
+    pub fn find_remote_relay(&self, id: &str) -> Option<RemoteRoom> {
+        self.cluster
+            .as_ref()
+            .and_then(|cluster| cluster.find_room(&format!("S{}", id)))
+    }
+
     pub async fn new_relay_id(
         &self,
         admin: Arc<SharedConnection>,
@@ -111,19 +165,30 @@ impl SharedRelayManager {
         mut custom: CustomRelayData,
     ) -> Arc<SharedRelayRoom> {
         if custom.max_player_size == -1 {
-            custom.max_player_size = 10;
+            custom.max_player_size = live_config().game.max_player_size;
         }
 
         let id = if let Some(id) = id {
             id
         } else {
             loop {
-                let tmp_id = self
-                    .id_rand
-                    .write()
-                    .expect("get rand id the write lock error")
-                    .gen_range(100..9999)
-                    .to_string();
+                let tmp_id = match &self.cluster {
+                    //以node_id为前缀保证房间号在集群内唯一
+                    Some(cluster) => format!(
+                        "{}{:04}",
+                        cluster.node_id(),
+                        self.id_rand
+                            .write()
+                            .expect("get rand id the write lock error")
+                            .gen_range(0..10000)
+                    ),
+                    None => self
+                        .id_rand
+                        .write()
+                        .expect("get rand id the write lock error")
+                        .gen_range(100..9999)
+                        .to_string(),
+                };
                 if (self.get_relay(&tmp_id).await).is_none() {
                     break format!("S{}", tmp_id);
                 }
@@ -137,6 +202,9 @@ impl SharedRelayManager {
             .send(RelayManagerAPI::InsertNewRelay(shared_relay_room.clone()))
             .await
             .expect("send relay mg api tx error");
+        if let Some(cluster) = &self.cluster {
+            cluster.room_added(&shared_relay_room.shared_data.id);
+        }
 
         shared_relay_room
     }
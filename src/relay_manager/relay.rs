@@ -6,7 +6,12 @@ use std::{
     },
 };
 
-use tokio::{runtime::Runtime, sync::mpsc, task::JoinHandle};
+use log::debug;
+use tokio::{
+    runtime::Runtime,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
 
 use crate::{
     connection::{player_net_api::CustomRelayData, shared_connection::SharedConnection},
@@ -19,11 +24,18 @@ pub struct SharedRelayRoomData {
     pub custom: CustomRelayData,
 }
 
+pub type RelayRoomMember = (
+    Option<Arc<SharedConnection>>,
+    Vec<(u32, Arc<SharedConnection>)>,
+);
+
 #[derive(Debug)]
 pub enum RelayRoomAPI {
     SendToHost(Packet),
     AddRelayPlayer(Arc<SharedConnection>),
     SendToOthers(u32, Packet),
+    GetRoomMember(oneshot::Sender<RelayRoomMember>),
+    Close(String),
 }
 #[derive(Debug)]
 pub struct RelayRoom {
@@ -80,10 +92,12 @@ impl SharedRelayRoom {
             //let shared_relay_room =
             loop {
                 match relay_api_rx.recv().await.expect("Relay API recv error") {
-                    RelayRoomAPI::SendToHost(packet) => match relay_room.admin.upgrade() {
-                        Some(admin) => admin.send_packet(packet).await,
-                        None => todo!(),
-                    },
+                    //房主已经断开,房间即将关闭
+                    RelayRoomAPI::SendToHost(packet) => {
+                        if let Some(admin) = relay_room.admin.upgrade() {
+                            admin.send_packet(packet).await
+                        }
+                    }
                     RelayRoomAPI::AddRelayPlayer(shared_con) => {
                         let index = relay_room.site.fetch_add(1, Ordering::SeqCst);
 
@@ -96,13 +110,39 @@ impl SharedRelayRoom {
                     }
 
                     RelayRoomAPI::SendToOthers(index, packet) => {
+                        //index由房主发来,可能是已经离开或不存在的玩家
                         match relay_room.player_map.get(&index) {
                             Some(weak_shared) => {
                                 if let Some(other) = weak_shared.upgrade() {
                                     other.send_packet(packet).await
                                 }
                             }
-                            None => todo!(),
+                            None => debug!(
+                                room_id = relay_room.shared_relay_room.shared_data.id.as_str();
+                                "房主转发给不存在的玩家{}", index
+                            ),
+                        }
+                    }
+                    RelayRoomAPI::GetRoomMember(member_tx) => {
+                        let mut player_list = relay_room
+                            .player_map
+                            .iter()
+                            .filter_map(|(index, weak_shared)| {
+                                weak_shared.upgrade().map(|shared| (*index, shared))
+                            })
+                            .collect::<Vec<_>>();
+                        player_list.sort_by_key(|(index, _)| *index);
+
+                        let _ = member_tx.send((relay_room.admin.upgrade(), player_list));
+                    }
+                    RelayRoomAPI::Close(reason) => {
+                        for (_, weak_shared) in relay_room.player_map.drain() {
+                            if let Some(player) = weak_shared.upgrade() {
+                                player.kick(&reason).await;
+                            }
+                        }
+                        if let Some(admin) = relay_room.admin.upgrade() {
+                            admin.kick(&reason).await;
                         }
                     }
                 }
@@ -139,6 +179,22 @@ impl SharedRelayRoom {
             .await
             .unwrap();
     }
+
+    pub async fn get_room_member(&self) -> RelayRoomMember {
+        let (member_tx, member_rx) = oneshot::channel();
+        self.relay_api_tx
+            .send(RelayRoomAPI::GetRoomMember(member_tx))
+            .await
+            .unwrap();
+        member_rx.await.expect("recv room member error")
+    }
+
+    pub async fn close(&self, reason: &str) {
+        self.relay_api_tx
+            .send(RelayRoomAPI::Close(reason.to_string()))
+            .await
+            .unwrap();
+    }
 }
 
 impl Drop for SharedRelayRoom {
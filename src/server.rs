@@ -1,37 +1,273 @@
 pub mod config;
+pub mod listener;
+pub mod proxy_protocol;
+pub mod reload;
 pub mod utils;
 
-use std::net::SocketAddr;
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
 
-use serde::{Deserialize, Serialize};
+use log::LevelFilter;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
+    //端口范围,两端均包含
     pub port_range: Vec<(u32,u32)>,
     pub thread_number: usize,
     pub enable_web: bool,
+    //检查配置文件是否被修改的间隔,0为不自动重载
+    pub config_watch_secs: u64,
+    //为空时在0.0.0.0上监听port_range
+    pub listen: Vec<ListenConfig>,
 }
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListenConfig {
+    pub addr: IpAddr,
+    //为空时使用server.port_range
+    pub port_range: Vec<(u32, u32)>,
+    //IPv6地址是否同时接受IPv4连接
+    pub dual_stack: bool,
+    pub nodelay: bool,
+    //是否先读取负载均衡发送的PROXY protocol头部(v1/v2),开启后没有头部的连接会被断开
+    pub proxy_protocol: bool,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebConfig {
+    pub listen_addr: String,
+    //为空时会在启动时随机生成并打印到日志
+    pub token: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterConfig {
+    pub enable: bool,
+    //集群内唯一,同时作为房间号的前缀
+    pub node_id: u32,
+    //节点之间通信使用的监听地址
+    pub listen_addr: String,
+    //玩家连接本节点使用的地址,跳转到本节点时会发给玩家
+    pub public_addr: String,
+    //其他节点的通信地址
+    pub peers: Vec<String>,
+    //节点之间的共享密钥
+    pub secret: String,
+    //跳转时是否额外发送PACKET_RECONNECT_TO让客户端自动重连
+    pub reconnect_packet: bool,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub enable: bool,
+    //实际文件名会加上开始抓包的时间
+    pub file: String,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Never,
+    Daily,
+    Size,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    //模块名 = 等级,如 "rws_rjr::connection_manager" = "debug"
+    pub module_level: BTreeMap<String, LevelFilter>,
+    //为空时不写入文件
+    pub file: String,
+    pub format: LogFormat,
+    pub rotation: LogRotation,
+    //rotation = "size" 时单个文件的最大大小
+    pub max_file_size_mb: u64,
+    //保留的历史日志文件数量,0为不删除
+    pub max_files: usize,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct UplistApi {
-
+    pub enable: bool,
+    pub game_name: String,
+    pub port: usize,
+    pub player_max_size: usize,
+    pub game_map: String,
+    pub created_by: String,
+    pub private_ip: String,
+    pub game_status: String,
+}
+//新房间的默认设置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    pub max_player_size: i32,
+    pub max_unit_size: u32,
+    pub income: f32,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModuleConfig {
+    //启用的内置模块名,如rw_engine
+    pub enable: Vec<String>,
+    //启动时加载其中所有的.wasm模块,为空时不加载
+    pub dir: String,
+    pub rw_engine: RwEngineConfig,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RwEngineVersion {
+    //和房主的客户端版本一致
+    Auto,
+    #[serde(rename = "1.15")]
+    V1_15,
+    #[serde(rename = "1.16")]
+    V1_16,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RwEngineConfig {
+    //假人连接的地址,为空时连接本服务器的第一个监听地址
+    pub addr: String,
+    //为true时加入所有新房间,否则只加入用new engine创建或房主输入命令的房间
+    pub auto_join: bool,
+    //房主在聊天中输入的命令,不带前缀
+    pub command: String,
+    pub dummy_name: String,
+    pub version: RwEngineVersion,
+    //房主绘制图片的命令,不带前缀
+    pub paint_command: String,
+    //只能绘制该目录下的图片
+    pub image_dir: String,
+    //图片缩放后的最大边长,单位为格
+    pub paint_max_size: u32,
+    //每秒发给房主的地形包数
+    pub paint_rate: u32,
+}
+//服务器启动后由假人房主常驻的房间,断开后自动重开
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LobbyConfig {
+    //房主的玩家名
+    pub name: String,
+    //在大厅输入的开房命令
+    pub room_command: String,
+    pub map_name: String,
+    pub max_player: u32,
+    //玩家注册后房主发送的欢迎消息,{player}会被替换为玩家名,为空时不发送
+    pub welcome: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageConfig {
+    //进入Relay大厅时的欢迎语,{player}会被替换为玩家名
+    pub hall_welcome: String,
+    //加入房间后发给玩家的消息
+    pub room_welcome: String,
+    //大厅中motd命令显示的公告,为空时显示暂无公告
+    pub motd: String,
+    //房间中聊天命令的前缀,以此开头的聊天不会转发给房主
+    pub command_prefix: String,
 }
-#[derive(Debug, Serialize, Deserialize, Default)]
-pub struct GameConfig {}
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+//缺失的字段均使用默认值
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AllConfig {
     pub server: ServerConfig,
+    pub web: WebConfig,
+    pub cluster: ClusterConfig,
+    pub log: LogConfig,
+    pub capture: CaptureConfig,
     pub uplist: UplistApi,
     pub game: GameConfig,
-    pub banlist: Vec<SocketAddr>,
+    pub message: MessageConfig,
+    #[serde(deserialize_with = "deserialize_banlist")]
+    pub banlist: Vec<IpAddr>,
+    pub lobby: Vec<LobbyConfig>,
+    pub module: ModuleConfig,
+}
+
+//旧版本的banlist是带端口的地址,读取时只保留IP,封禁对该IP的所有端口生效
+fn deserialize_banlist<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<IpAddr>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|addr| {
+            addr.parse::<IpAddr>()
+                .or_else(|_| addr.parse::<SocketAddr>().map(|addr| addr.ip()))
+                .map_err(|_| D::Error::custom(format!("`{}`不是有效的IP地址", addr)))
+        })
+        .collect()
+}
+
+impl ServerConfig {
+    //实际需要监听的地址,未配置port_range的使用server.port_range
+    pub fn listen_list(&self) -> Vec<ListenConfig> {
+        if self.listen.is_empty() {
+            return vec![ListenConfig {
+                port_range: self.port_range.clone(),
+                ..Default::default()
+            }];
+        }
+        self.listen
+            .iter()
+            .map(|listen| ListenConfig {
+                port_range: if listen.port_range.is_empty() {
+                    self.port_range.clone()
+                } else {
+                    listen.port_range.clone()
+                },
+                ..listen.clone()
+            })
+            .collect()
+    }
+
+    //服务器内部的假人连接第一个监听地址,监听所有地址时连接本机
+    pub fn local_addr(&self) -> SocketAddr {
+        let listen_config = self.listen_list().remove(0);
+        let ip = match listen_config.addr {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        let port = listen_config
+            .port_range
+            .first()
+            .map(|(start, _)| *start as u16)
+            .unwrap_or_default();
+        SocketAddr::new(ip, port)
+    }
 }
 
 impl std::fmt::Display for AllConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "端口:{:?}\n线程总数:{}",
-            self.server.port_range, self.server.thread_number
+            "监听:{}\n线程总数:{}\nWeb API:{}",
+            self.server
+                .listen_list()
+                .iter()
+                .map(|listen| format!("{} {:?}", listen.addr, listen.port_range))
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.server.thread_number,
+            if self.server.enable_web {
+                self.web.listen_addr.as_str()
+            } else {
+                "未启用"
+            }
         )
     }
 }
@@ -1,8 +1,19 @@
-use std::{io::ErrorKind, path::Path};
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    utils::ConfigError, AllConfig, CaptureConfig, ClusterConfig, GameConfig, ListenConfig,
+    LobbyConfig, LogConfig, LogFormat, LogRotation, MessageConfig, ModuleConfig, RwEngineConfig,
+    RwEngineVersion, ServerConfig, UplistApi, WebConfig,
+};
 
-use super::{AllConfig, ServerConfig};
+use crate::module::ModuleType;
 
-use log::{info, warn};
+use log::info;
 use tokio::{
     fs::{read_to_string, File},
     io::AsyncWriteExt,
@@ -10,47 +21,660 @@ use tokio::{
 
 use toml;
 
+pub static CONFIG_FILE_NAME: &str = "config.toml";
+
+//命令行中覆盖配置文件的选项,热重载后同样会被应用
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOverride {
+    pub port_range: Option<Vec<(u32, u32)>>,
+    pub thread_number: Option<usize>,
+    pub log_level: Option<log::LevelFilter>,
+}
+
+impl ConfigOverride {
+    pub fn apply(&self, config: &mut AllConfig) {
+        if let Some(port_range) = &self.port_range {
+            config.server.port_range = port_range.clone();
+        }
+        if let Some(thread_number) = self.thread_number {
+            config.server.thread_number = thread_number;
+        }
+        if let Some(log_level) = self.log_level {
+            config.log.level = log_level;
+        }
+    }
+}
+pub static MAX_THREAD_NUMBER: usize = 1024;
+//node_id会作为房间号前缀,不宜过长
+pub static MAX_CLUSTER_NODE_ID: u32 = 999;
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
+            //两端均包含,只监听5123
             port_range: vec![(5123,5123)],
             thread_number: 30,
             enable_web: false,
+            config_watch_secs: 5,
+            listen: Vec::new(),
         }
     }
 }
 
-pub async fn load_config(path: &Path) -> anyhow::Result<AllConfig> {
-    match read_to_string(path).await {
-        Ok(s) => {
-            info!("配置文件已找到");
-            Ok(toml::from_str::<AllConfig>(&s)
-                .expect("配置文件错误,请检查或删除配置文件并再次运行"))
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port_range: Vec::new(),
+            dual_stack: true,
+            nodelay: true,
+            proxy_protocol: false,
         }
-        Err(e) => {
-            if e.kind() == ErrorKind::NotFound {
-                info!("配置文件未找到，已创建新配置文件");
-                Ok(save_default_config(path)
-                    .await
-                    .expect("写入默认配置文件失败"))
-            } else {
-                warn!("{}", e);
-                panic!("{}", e);
-            }
+    }
+}
+
+impl Default for UplistApi {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            game_name: "RW-Relay-RJR".to_string(),
+            port: 5123,
+            player_max_size: 100,
+            game_map: "RW-RJR".to_string(),
+            created_by: "shishanyue".to_string(),
+            private_ip: "192.168.80.1".to_string(),
+            game_status: "开了".to_string(),
+        }
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            max_player_size: 10,
+            max_unit_size: 300,
+            income: 1.0,
+        }
+    }
+}
+
+impl Default for LobbyConfig {
+    fn default() -> Self {
+        Self {
+            name: "RJR Lobby".to_string(),
+            room_command: "new".to_string(),
+            map_name: "[z;p10]Crossing Large (10p)".to_string(),
+            max_player: 10,
+            welcome: "欢迎{player}来到大厅房间".to_string(),
+        }
+    }
+}
+
+impl Default for ModuleConfig {
+    fn default() -> Self {
+        Self {
+            enable: Vec::new(),
+            dir: "modules".to_string(),
+            rw_engine: RwEngineConfig::default(),
+        }
+    }
+}
+
+impl Default for RwEngineConfig {
+    fn default() -> Self {
+        Self {
+            addr: String::new(),
+            auto_join: false,
+            command: "engine".to_string(),
+            dummy_name: "RwEngineSystem".to_string(),
+            version: RwEngineVersion::Auto,
+            paint_command: "paint".to_string(),
+            image_dir: "images".to_string(),
+            paint_max_size: 100,
+            paint_rate: 200,
+        }
+    }
+}
+
+impl Default for MessageConfig {
+    fn default() -> Self {
+        Self {
+            hall_welcome: "[Relay CN]{player} 欢迎使用RJR,这台服务是非官方的Relay房间\nThis server is CN's unofficial Relay room\n输入ID可进入房间,输入new/mods可创建房间\n输入/help可以获得更多帮助".to_string(),
+            room_welcome: "欢迎".to_string(),
+            motd: String::new(),
+            command_prefix: "/".to_string(),
+        }
+    }
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: "127.0.0.1:5200".to_string(),
+            token: String::new(),
+        }
+    }
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            node_id: 1,
+            listen_addr: "0.0.0.0:5300".to_string(),
+            public_addr: String::new(),
+            peers: Vec::new(),
+            secret: String::new(),
+            reconnect_packet: false,
+        }
+    }
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            file: "capture/relay.rjrcap".to_string(),
         }
     }
 }
 
-pub async fn save_default_config(path: &Path) -> anyhow::Result<AllConfig> {
-    info!("正在写入默认配置文件到:{}", path.to_str().unwrap());
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: log::LevelFilter::Info,
+            module_level: BTreeMap::from([("hyper".to_string(), log::LevelFilter::Info)]),
+            file: "output.log".to_string(),
+            format: LogFormat::Text,
+            rotation: LogRotation::Daily,
+            max_file_size_mb: 50,
+            max_files: 7,
+        }
+    }
+}
+
+//命令行或环境变量指定的路径优先,其次是工作目录,再其次是程序所在目录
+//都不存在时在工作目录创建默认配置
+pub fn resolve_config_path(config_path: Option<PathBuf>) -> PathBuf {
+    if let Some(config_path) = config_path {
+        return config_path;
+    }
+
+    let current_dir_config = std::env::current_dir()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .unwrap_or_else(|_| PathBuf::from(CONFIG_FILE_NAME));
+    if current_dir_config.exists() {
+        return current_dir_config;
+    }
+
+    if let Some(exe_dir_config) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(CONFIG_FILE_NAME)))
+        .filter(|exe_dir_config| exe_dir_config.exists())
+    {
+        return exe_dir_config;
+    }
+
+    current_dir_config
+}
+
+pub async fn load_config(path: &Path) -> Result<AllConfig, ConfigError> {
+    match read_config(path).await {
+        Err(ConfigError::Read { source, .. }) if source.kind() == ErrorKind::NotFound => {
+            info!("配置文件未找到，已创建新配置文件");
+            save_default_config(path).await
+        }
+        res => res,
+    }
+}
+
+//读取配置文件,文件不存在时返回错误
+//需要在应用ConfigOverride之后再调用validate
+pub async fn read_config(path: &Path) -> Result<AllConfig, ConfigError> {
+    let s = read_to_string(path).await.map_err(|e| ConfigError::Read {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+    info!("配置文件已找到");
+    toml::from_str::<AllConfig>(&s).map_err(|e| ConfigError::from_toml(path.to_path_buf(), &s, e))
+}
+
+//写在默认配置文件开头的说明
+static DEFAULT_CONFIG_HEADER: &str = "\
+# port_range中的端口范围两端均包含,[[5123, 5125]]会监听5123、5124和5125
+# 旧版本不包含结束端口,只监听一个端口时请写成[[5123, 5123]]
+";
+
+pub fn default_config_content() -> Result<String, toml::ser::Error> {
+    Ok(format!(
+        "{}{}",
+        DEFAULT_CONFIG_HEADER,
+        toml::to_string(&AllConfig::default())?
+    ))
+}
+
+pub async fn save_default_config(path: &Path) -> Result<AllConfig, ConfigError> {
+    info!("正在写入默认配置文件到:{}", path.display());
     let default = AllConfig::default();
-    let mut new_file = File::create(path).await?;
+    let content = default_config_content()?;
+
+    let write_error = |e| ConfigError::Write {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let mut new_file = File::create(path).await.map_err(write_error)?;
     new_file
-        .write_all(
-            toml::to_string(&default)
-                .expect("默认配置转换TOML失败")
-                .as_bytes(),
-        )
-        .await?;
+        .write_all(content.as_bytes())
+        .await
+        .map_err(write_error)?;
     Ok(default)
 }
+
+impl AllConfig {
+    //不影响启动但可能和预期不一致的配置
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        warn_port_range("server.port_range", &self.server.port_range, &mut warnings);
+        for (index, listen) in self.server.listen.iter().enumerate() {
+            warn_port_range(
+                &format!("server.listen[{}].port_range", index),
+                &listen.port_range,
+                &mut warnings,
+            );
+        }
+        warnings
+    }
+
+    //config_override需要已经应用到配置上,命令行指定的端口在错误信息中显示为--port
+    pub fn validate(
+        &self,
+        path: &Path,
+        config_override: &ConfigOverride,
+    ) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+
+        let port_range_field = if config_override.port_range.is_some() {
+            "--port"
+        } else {
+            "server.port_range"
+        };
+        for (index, listen) in self.server.listen_list().iter().enumerate() {
+            if !listen.port_range.is_empty() {
+                continue;
+            }
+            errors.push(if self.server.listen.is_empty() {
+                format!("{}: 至少需要一个端口范围", port_range_field)
+            } else {
+                format!(
+                    "server.listen[{}].port_range: 为空时使用{},两者不能都为空",
+                    index, port_range_field
+                )
+            });
+        }
+        validate_port_range(port_range_field, &self.server.port_range, &mut errors);
+        for (index, listen) in self.server.listen.iter().enumerate() {
+            validate_port_range(
+                &format!("server.listen[{}].port_range", index),
+                &listen.port_range,
+                &mut errors,
+            );
+        }
+
+        let listen_list = self.server.listen_list();
+        for (index, listen) in listen_list.iter().enumerate() {
+            for (other_index, other) in listen_list.iter().enumerate().skip(index + 1) {
+                let overlapping = addrs_conflict(listen, other)
+                    && listen.port_range.iter().any(|(start, end)| {
+                        other
+                            .port_range
+                            .iter()
+                            .any(|(other_start, other_end)| start <= other_end && other_start <= end)
+                    });
+                if overlapping {
+                    errors.push(format!(
+                        "server.listen[{}]与server.listen[{}]的地址和端口重叠",
+                        index, other_index
+                    ));
+                }
+            }
+        }
+
+        if self.server.thread_number == 0 || self.server.thread_number > MAX_THREAD_NUMBER {
+            errors.push(format!(
+                "server.thread_number: 线程数必须在1到{}之间,当前为{}",
+                MAX_THREAD_NUMBER, self.server.thread_number
+            ));
+        }
+
+        if self.server.enable_web && self.web.listen_addr.parse::<SocketAddr>().is_err() {
+            errors.push(format!(
+                "web.listen_addr: `{}`不是有效的监听地址",
+                self.web.listen_addr
+            ));
+        }
+
+        if self.cluster.enable {
+            if self.cluster.node_id == 0 || self.cluster.node_id > MAX_CLUSTER_NODE_ID {
+                errors.push(format!(
+                    "cluster.node_id: 必须在1到{}之间,当前为{}",
+                    MAX_CLUSTER_NODE_ID, self.cluster.node_id
+                ));
+            }
+            if self.cluster.listen_addr.parse::<SocketAddr>().is_err() {
+                errors.push(format!(
+                    "cluster.listen_addr: `{}`不是有效的监听地址",
+                    self.cluster.listen_addr
+                ));
+            }
+            if self.cluster.public_addr.is_empty() {
+                errors.push("cluster.public_addr: 开启集群时不能为空".to_string());
+            }
+        }
+
+        if self.capture.enable && self.capture.file.is_empty() {
+            errors.push("capture.file: 开启抓包时不能为空".to_string());
+        }
+
+        if self.game.max_player_size < 1 {
+            errors.push(format!(
+                "game.max_player_size: 必须大于0,当前为{}",
+                self.game.max_player_size
+            ));
+        }
+        if !self.game.income.is_finite() || self.game.income < 0. {
+            errors.push(format!("game.income: `{}`不是有效的倍率", self.game.income));
+        }
+
+        if self.message.command_prefix.is_empty()
+            || self.message.command_prefix.contains(char::is_whitespace)
+        {
+            errors.push(format!(
+                "message.command_prefix: `{}`不能为空或包含空白字符",
+                self.message.command_prefix
+            ));
+        }
+
+        for (index, lobby) in self.lobby.iter().enumerate() {
+            if lobby.name.is_empty() {
+                errors.push(format!("lobby[{}].name: 不能为空", index));
+            }
+            if lobby.max_player == 0 {
+                errors.push(format!("lobby[{}].max_player: 必须大于0", index));
+            }
+        }
+
+        for name in self.module.enable.iter() {
+            if ModuleType::from_name(name).is_none() {
+                errors.push(format!(
+                    "module.enable: 未知的模块`{}`,可用的模块:{}",
+                    name,
+                    ModuleType::ALL.map(|module_type| module_type.name()).join(",")
+                ));
+            }
+        }
+
+        let rw_engine = &self.module.rw_engine;
+        if !rw_engine.addr.is_empty() && rw_engine.addr.parse::<SocketAddr>().is_err() {
+            errors.push(format!(
+                "module.rw_engine.addr: `{}`不是有效的地址",
+                rw_engine.addr
+            ));
+        }
+        if rw_engine.command.is_empty() || rw_engine.command.contains(char::is_whitespace) {
+            errors.push(format!(
+                "module.rw_engine.command: `{}`不能为空或包含空白字符",
+                rw_engine.command
+            ));
+        }
+        if rw_engine.dummy_name.is_empty() {
+            errors.push("module.rw_engine.dummy_name: 不能为空".to_string());
+        }
+        if rw_engine.paint_command.is_empty()
+            || rw_engine.paint_command.contains(char::is_whitespace)
+            || rw_engine.paint_command == rw_engine.command
+        {
+            errors.push(format!(
+                "module.rw_engine.paint_command: `{}`不能为空、包含空白字符或与command相同",
+                rw_engine.paint_command
+            ));
+        }
+        if rw_engine.image_dir.is_empty() {
+            errors.push("module.rw_engine.image_dir: 不能为空".to_string());
+        }
+        if rw_engine.paint_max_size == 0 {
+            errors.push("module.rw_engine.paint_max_size: 必须大于0".to_string());
+        }
+        if rw_engine.paint_rate == 0 {
+            errors.push("module.rw_engine.paint_rate: 必须大于0".to_string());
+        }
+
+        if self.log.rotation == LogRotation::Size && self.log.max_file_size_mb == 0 {
+            errors.push("log.max_file_size_mb: 按大小切分时必须大于0".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid {
+                path: path.to_path_buf(),
+                errors,
+            })
+        }
+    }
+}
+
+//同一端口上会冲突的地址:相同地址,同一协议的通配地址和其他地址
+//以及双栈的[::],它同时占用了IPv4的所有地址
+fn addrs_conflict(listen: &ListenConfig, other: &ListenConfig) -> bool {
+    let covers = |listen: &ListenConfig, addr: IpAddr| {
+        listen.addr == addr
+            || (listen.addr.is_unspecified() && listen.addr.is_ipv4() == addr.is_ipv4())
+            || (listen.addr.is_unspecified() && listen.addr.is_ipv6() && listen.dual_stack)
+    };
+    covers(listen, other.addr) || covers(other, listen.addr)
+}
+
+//端口范围两端均包含,而旧版本不包含结束端口
+//结束端口大于起始端口时旧版本不监听结束端口,现在会多监听一个,启动时提醒
+fn warn_port_range(field: &str, port_range: &[(u32, u32)], warnings: &mut Vec<String>) {
+    for (index, (start, end)) in port_range.iter().enumerate() {
+        if start < end {
+            warnings.push(format!(
+                "{}[{}]: ({}, {})会监听{}到{}的所有端口,端口范围两端均包含,旧版本不监听{},不需要该端口时请改为({}, {})",
+                field,
+                index,
+                start,
+                end,
+                start,
+                end,
+                end,
+                start,
+                end - 1
+            ));
+        }
+    }
+}
+
+fn validate_port_range(field: &str, port_range: &[(u32, u32)], errors: &mut Vec<String>) {
+    for (index, (start, end)) in port_range.iter().enumerate() {
+        if *start == 0 || *end > u16::MAX as u32 {
+            errors.push(format!(
+                "{}[{}]: 端口必须在1到65535之间,当前为({}, {})",
+                field, index, start, end
+            ));
+        } else if start > end {
+            errors.push(format!(
+                "{}[{}]: 起始端口{}大于结束端口{}",
+                field, index, start, end
+            ));
+        }
+    }
+    for (index, (start, end)) in port_range.iter().enumerate() {
+        for (other_index, (other_start, other_end)) in port_range.iter().enumerate().skip(index + 1)
+        {
+            if start <= other_end && other_start <= end {
+                errors.push(format!(
+                    "{}[{}]与{}[{}]重叠",
+                    field, index, field, other_index
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banlist_accepts_old_socket_addrs() {
+        let config =
+            toml::from_str::<AllConfig>(r#"banlist = ["1.2.3.4:5123", "::1", "[::2]:80"]"#)
+                .unwrap();
+        assert_eq!(
+            config.banlist,
+            vec![
+                IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)),
+                "::1".parse::<IpAddr>().unwrap(),
+                "::2".parse::<IpAddr>().unwrap(),
+            ]
+        );
+        assert!(toml::from_str::<AllConfig>(r#"banlist = ["1.2.3"]"#).is_err());
+    }
+
+    fn validate_errors(config: &AllConfig) -> Vec<String> {
+        match config.validate(Path::new("config.toml"), &ConfigOverride::default()) {
+            Ok(()) => Vec::new(),
+            Err(ConfigError::Invalid { errors, .. }) => errors,
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        let config = AllConfig::default();
+        assert!(validate_errors(&config).is_empty());
+        assert!(config.warnings().is_empty());
+    }
+
+    #[test]
+    fn validate_server_config() {
+        let mut config = AllConfig::default();
+        config.server.port_range = vec![(0, 10), (20, 10), (30, 40), (35, 50)];
+        config.server.thread_number = 0;
+        let errors = validate_errors(&config);
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].starts_with("server.port_range[0]: 端口必须在1到65535之间"));
+        assert!(errors[1].starts_with("server.port_range[1]: 起始端口20大于结束端口10"));
+        assert_eq!(errors[2], "server.port_range[2]与server.port_range[3]重叠");
+        assert!(errors[3].starts_with("server.thread_number"));
+
+        let mut config = AllConfig::default();
+        config.server.port_range = Vec::new();
+        assert_eq!(
+            validate_errors(&config),
+            vec!["server.port_range: 至少需要一个端口范围".to_string()]
+        );
+
+        let mut config = AllConfig::default();
+        config.server.listen = vec![
+            ListenConfig::default(),
+            ListenConfig {
+                port_range: vec![(5000, 5123)],
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            validate_errors(&config),
+            vec!["server.listen[0]与server.listen[1]的地址和端口重叠".to_string()]
+        );
+    }
+
+    #[test]
+    fn listen_addr_conflicts() {
+        let listen = |addr: &str, dual_stack: bool, port: u32| ListenConfig {
+            addr: addr.parse().unwrap(),
+            dual_stack,
+            port_range: vec![(port, port)],
+            ..Default::default()
+        };
+        let conflicts = |listen: Vec<ListenConfig>| {
+            let mut config = AllConfig::default();
+            config.server.listen = listen;
+            !validate_errors(&config).is_empty()
+        };
+
+        assert!(conflicts(vec![listen("::", true, 5123), listen("0.0.0.0", true, 5123)]));
+        assert!(conflicts(vec![listen("127.0.0.1", true, 5123), listen("::", true, 5123)]));
+        assert!(!conflicts(vec![listen("::", false, 5123), listen("0.0.0.0", true, 5123)]));
+        assert!(conflicts(vec![listen("0.0.0.0", true, 5123), listen("127.0.0.1", true, 5123)]));
+        assert!(conflicts(vec![listen("::", false, 5123), listen("::1", true, 5123)]));
+        assert!(!conflicts(vec![listen("127.0.0.1", true, 5123), listen("127.0.0.2", true, 5123)]));
+        assert!(!conflicts(vec![listen("::", true, 5123), listen("0.0.0.0", true, 5124)]));
+    }
+
+    #[test]
+    fn port_range_errors_name_cli_override() {
+        let config_override = ConfigOverride {
+            port_range: Some(vec![(10, 5)]),
+            ..Default::default()
+        };
+        let mut config = AllConfig::default();
+        config_override.apply(&mut config);
+        match config.validate(Path::new("config.toml"), &config_override) {
+            Err(ConfigError::Invalid { errors, .. }) => {
+                assert_eq!(errors, vec!["--port[0]: 起始端口10大于结束端口5".to_string()])
+            }
+            res => panic!("unexpected result: {:?}", res.map_err(|e| e.to_string())),
+        }
+
+        let mut config = AllConfig::default();
+        config.server.port_range = Vec::new();
+        config.server.listen = vec![ListenConfig::default()];
+        assert_eq!(
+            validate_errors(&config),
+            vec!["server.listen[0].port_range: 为空时使用server.port_range,两者不能都为空".to_string()]
+        );
+    }
+
+    #[test]
+    fn warn_changed_port_ranges() {
+        let mut config = AllConfig::default();
+        config.server.port_range = vec![(5123, 5123), (5124, 5125), (6000, 6002)];
+        config.server.listen = vec![ListenConfig {
+            port_range: vec![(7000, 7100)],
+            ..Default::default()
+        }];
+        let warnings = config.warnings();
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+        assert!(warnings[0].starts_with("server.port_range[1]: (5124, 5125)会监听5124到5125"));
+        assert!(warnings[0].ends_with("请改为(5124, 5124)"));
+        assert!(warnings[1].starts_with("server.port_range[2]: (6000, 6002)"));
+        assert!(warnings[1].ends_with("请改为(6000, 6001)"));
+        assert!(warnings[2].starts_with("server.listen[0].port_range[0]: (7000, 7100)"));
+        assert!(validate_errors(&config).is_empty());
+    }
+
+    #[test]
+    fn default_config_documents_port_range() {
+        let content = default_config_content().unwrap();
+        assert!(content.starts_with("# port_range中的端口范围两端均包含"));
+        assert_eq!(toml::from_str::<AllConfig>(&content).unwrap(), AllConfig::default());
+    }
+
+    #[test]
+    fn toml_error_position() {
+        let content = "[server]\nthread_number = 1\nport_range = \"5123\"\n";
+        let e = toml::from_str::<AllConfig>(content).unwrap_err();
+        match ConfigError::from_toml(PathBuf::from("config.toml"), content, e) {
+            ConfigError::Parse { position, .. } => assert_eq!(position, Some((3, 14))),
+            e => panic!("unexpected error: {}", e),
+        }
+
+        //中文按字符计算列
+        let content = "[message]\nroom_welcome = \"欢迎\" x\n";
+        let e = toml::from_str::<AllConfig>(content).unwrap_err();
+        let e = ConfigError::from_toml(PathBuf::from("config.toml"), content, e);
+        assert!(e.to_string().starts_with("配置文件config.toml第2行第21列有误"), "{}", e);
+    }
+}
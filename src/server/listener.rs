@@ -0,0 +1,28 @@
+use std::net::SocketAddr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+
+use super::ListenConfig;
+
+//同时等待的连接数
+static LISTEN_BACKLOG: i32 = 1024;
+
+pub fn bind_listener(
+    addr: SocketAddr,
+    listen_config: &ListenConfig,
+) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(!listen_config.dual_stack)?;
+    }
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    socket.set_nonblocking(true)?;
+
+    TcpListener::from_std(socket.into())
+}
@@ -0,0 +1,182 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::ProxyProtocolError;
+
+//建立连接后等待头部的最长时间
+pub static PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+//v2头部的固定签名
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+//v1头部最长107字节(包括\r\n)
+const V1_MAX_LENGTH: usize = 107;
+
+//读取HAProxy的PROXY protocol头部,同时支持v1和v2
+//只读取头部本身,之后的数据仍然留在流中交给PacketCodec
+//返回None表示负载均衡自身的连接(LOCAL/UNKNOWN),此时应使用原始地址
+pub async fn read_proxy_header<R>(reader: &mut R) -> Result<Option<SocketAddr>, ProxyProtocolError>
+where
+    R: AsyncRead + Unpin,
+{
+    //v1最短的头部"PROXY UNKNOWN\r\n"也有15字节,先读12字节判断版本
+    let mut prefix = [0; 12];
+    reader.read_exact(&mut prefix).await?;
+
+    if prefix == V2_SIGNATURE {
+        read_v2(reader).await
+    } else if prefix.starts_with(b"PROXY ") {
+        read_v1(reader, &prefix).await
+    } else {
+        Err(ProxyProtocolError::Missing)
+    }
+}
+
+async fn read_v1<R>(reader: &mut R, prefix: &[u8]) -> Result<Option<SocketAddr>, ProxyProtocolError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(ProxyProtocolError::Invalid(
+                "v1 header too long".to_string(),
+            ));
+        }
+        line.push(reader.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| ProxyProtocolError::Invalid("v1 header is not ascii".to_string()))?;
+    let parts = line.split(' ').collect::<Vec<_>>();
+
+    match parts.get(1).copied() {
+        Some("UNKNOWN") => Ok(None),
+        Some(protocol @ ("TCP4" | "TCP6")) => {
+            let [_, _, src_ip, _dst_ip, src_port, _dst_port] = parts[..] else {
+                return Err(ProxyProtocolError::Invalid(format!("v1 header `{}`", line)));
+            };
+            let src_ip = src_ip
+                .parse::<IpAddr>()
+                .map_err(|_| ProxyProtocolError::Invalid(format!("v1 address `{}`", src_ip)))?;
+            if src_ip.is_ipv4() != (protocol == "TCP4") {
+                return Err(ProxyProtocolError::Invalid(format!(
+                    "v1 address `{}` is not {}",
+                    src_ip, protocol
+                )));
+            }
+            let src_port = src_port
+                .parse::<u16>()
+                .map_err(|_| ProxyProtocolError::Invalid(format!("v1 port `{}`", src_port)))?;
+            Ok(Some(SocketAddr::new(src_ip, src_port)))
+        }
+        _ => Err(ProxyProtocolError::Invalid(format!("v1 header `{}`", line))),
+    }
+}
+
+async fn read_v2<R>(reader: &mut R) -> Result<Option<SocketAddr>, ProxyProtocolError>
+where
+    R: AsyncRead + Unpin,
+{
+    let version_command = reader.read_u8().await?;
+    let family = reader.read_u8().await?;
+    let length = reader.read_u16().await? as usize;
+
+    if version_command >> 4 != 2 {
+        return Err(ProxyProtocolError::Invalid(format!(
+            "v2 version {}",
+            version_command >> 4
+        )));
+    }
+
+    //地址之后可能还有TLV,需要整段读完
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+
+    match version_command & 0x0F {
+        //LOCAL,负载均衡自身的健康检查等
+        0x0 => return Ok(None),
+        0x1 => {}
+        command => {
+            return Err(ProxyProtocolError::Invalid(format!(
+                "v2 command {}",
+                command
+            )))
+        }
+    }
+
+    let too_short = || ProxyProtocolError::Invalid("v2 address too short".to_string());
+    match family {
+        //TCP over IPv4
+        0x11 => {
+            let body: &[u8; 12] = body
+                .get(..12)
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(too_short)?;
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        //TCP over IPv6
+        0x21 => {
+            let body: &[u8; 36] = body
+                .get(..36)
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(too_short)?;
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port)))
+        }
+        //UNSPEC或者非TCP的地址,无法使用,当作未知处理
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn read(data: &[u8]) -> (Result<Option<SocketAddr>, ProxyProtocolError>, &[u8]) {
+        let mut reader = data;
+        let result = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(read_proxy_header(&mut reader));
+        (result, reader)
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_header_never_panics(data in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = read(&data);
+        }
+
+        #[test]
+        fn arbitrary_v2_body_never_panics(
+            command in any::<u8>(),
+            family in any::<u8>(),
+            body in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let mut data = V2_SIGNATURE.to_vec();
+            data.extend_from_slice(&[command, family]);
+            data.extend_from_slice(&(body.len() as u16).to_be_bytes());
+            data.extend_from_slice(&body);
+            let _ = read(&data);
+        }
+
+        //头部之后的数据属于游戏协议,不能被读走
+        #[test]
+        fn v1_leaves_payload(ip in any::<std::net::Ipv4Addr>(), port in any::<u16>(), rest in prop::collection::vec(any::<u8>(), 0..32)) {
+            let mut data = format!("PROXY TCP4 {} 127.0.0.1 {} 5123\r\n", ip, port).into_bytes();
+            data.extend_from_slice(&rest);
+            let (result, remaining) = read(&data);
+            prop_assert_eq!(result.unwrap(), Some(SocketAddr::new(IpAddr::V4(ip), port)));
+            prop_assert_eq!(remaining, &rest[..]);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    capture::apply_capture_config, connection_manager::ConnectionManager, logger,
+    module::MODULE_MANAGER, uplist::apply_uplist_config,
+};
+
+use super::{
+    config::{read_config, ConfigOverride},
+    utils::ConfigError,
+    AllConfig,
+};
+
+lazy_static! {
+    static ref CONFIG_PATH: RwLock<PathBuf> = RwLock::new(PathBuf::new());
+    static ref CONFIG_OVERRIDE: RwLock<ConfigOverride> = RwLock::new(ConfigOverride::default());
+    static ref LIVE_CONFIG: RwLock<Arc<AllConfig>> = RwLock::new(Arc::new(AllConfig::default()));
+    //同时只能有一个请求改写配置文件
+    static ref SAVE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReloadReport {
+    //已经生效的配置段
+    pub applied: Vec<String>,
+    //修改后需要重启才能生效的配置项
+    pub restart_required: Vec<String>,
+}
+
+impl std::fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.applied.is_empty() && self.restart_required.is_empty() {
+            return write!(f, "配置没有变化");
+        }
+        if !self.applied.is_empty() {
+            write!(f, "已生效:{}", self.applied.join(", "))?;
+        }
+        if !self.restart_required.is_empty() {
+            if !self.applied.is_empty() {
+                writeln!(f)?;
+            }
+            write!(f, "需要重启才能生效:{}", self.restart_required.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+pub fn init_live_config(
+    path: &Path,
+    config: AllConfig,
+    config_override: ConfigOverride,
+) -> Arc<AllConfig> {
+    *CONFIG_PATH.write().expect("write config path error") = path.to_path_buf();
+    *CONFIG_OVERRIDE.write().expect("write config override error") = config_override;
+    let config = Arc::new(config);
+    *LIVE_CONFIG.write().expect("write live config error") = config.clone();
+    config
+}
+
+//当前生效的配置,热重载后会返回新的配置
+pub fn live_config() -> Arc<AllConfig> {
+    LIVE_CONFIG.read().expect("read live config error").clone()
+}
+
+pub fn config_path() -> PathBuf {
+    CONFIG_PATH.read().expect("read config path error").clone()
+}
+
+pub async fn reload_config(connection_mg: &ConnectionManager) -> Result<ReloadReport, ConfigError> {
+    let path = config_path();
+    let mut new_config = read_config(&path).await?;
+    let config_override = CONFIG_OVERRIDE
+        .read()
+        .expect("read config override error")
+        .clone();
+    config_override.apply(&mut new_config);
+    new_config.validate(&path, &config_override)?;
+    for warning in new_config.warnings() {
+        warn!("{}", warning);
+    }
+    let old_config = live_config();
+
+    let mut report = ReloadReport::default();
+
+    let mut restart_required = |changed: bool, field: &str| {
+        if changed {
+            report.restart_required.push(field.to_string());
+        }
+    };
+    restart_required(
+        old_config.server.port_range != new_config.server.port_range,
+        "server.port_range",
+    );
+    restart_required(
+        old_config.server.listen != new_config.server.listen,
+        "server.listen",
+    );
+    restart_required(
+        old_config.server.thread_number != new_config.server.thread_number,
+        "server.thread_number",
+    );
+    restart_required(
+        old_config.server.enable_web != new_config.server.enable_web,
+        "server.enable_web",
+    );
+    restart_required(
+        old_config.server.config_watch_secs != new_config.server.config_watch_secs,
+        "server.config_watch_secs",
+    );
+    restart_required(old_config.web != new_config.web, "web");
+    restart_required(old_config.cluster != new_config.cluster, "cluster");
+    restart_required(old_config.lobby != new_config.lobby, "lobby");
+
+    if old_config.banlist != new_config.banlist {
+        let old_banlist = old_config.banlist.iter().collect::<HashSet<_>>();
+        let new_banlist = new_config.banlist.iter().collect::<HashSet<_>>();
+        for ip in old_banlist.difference(&new_banlist) {
+            connection_mg.unban(ip);
+        }
+        for ip in new_banlist.difference(&old_banlist) {
+            connection_mg.ban(**ip, "你已被封禁").await;
+        }
+        report.applied.push("banlist".to_string());
+    }
+
+    if old_config.log != new_config.log {
+        match logger::apply_log_config(&new_config.log) {
+            Ok(_) => report.applied.push("log".to_string()),
+            Err(e) => warn!("应用日志配置失败:{}", e),
+        }
+    }
+
+    if old_config.capture != new_config.capture {
+        match apply_capture_config(&new_config.capture) {
+            Ok(_) => report.applied.push("capture".to_string()),
+            Err(e) => warn!("应用抓包配置失败:{}", e),
+        }
+    }
+
+    if old_config.uplist != new_config.uplist {
+        apply_uplist_config(&new_config.uplist);
+        report.applied.push("uplist".to_string());
+    }
+
+    //以下配置在使用时从live_config读取,替换后即生效
+    if old_config.game != new_config.game {
+        report.applied.push("game".to_string());
+    }
+    if old_config.message != new_config.message {
+        report.applied.push("message".to_string());
+    }
+
+    if old_config.module != new_config.module {
+        MODULE_MANAGER.apply_config(&new_config.module);
+        report.applied.push("module".to_string());
+    }
+
+    *LIVE_CONFIG.write().expect("write live config error") = Arc::new(new_config);
+
+    info!("配置已重新加载\n{}", report);
+    Ok(report)
+}
+
+//把当前的封禁列表写回配置文件,只修改banlist,保留其他内容和注释
+//同时更新生效的配置,之后重载时不会把Web API添加的封禁当作被删除
+pub async fn save_banlist(connection_mg: &ConnectionManager) -> Result<(), ConfigError> {
+    let _guard = SAVE_LOCK.lock().await;
+    let path = config_path();
+    let mut banlist = connection_mg.get_banlist();
+    banlist.sort();
+
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| ConfigError::Read {
+            path: path.clone(),
+            source: e,
+        })?;
+    let mut document = content
+        .parse::<toml_edit::Document>()
+        .map_err(|e| ConfigError::Parse {
+            path: path.clone(),
+            position: None,
+            message: e.message().to_string(),
+        })?;
+    document["banlist"] = toml_edit::value(
+        banlist
+            .iter()
+            .map(IpAddr::to_string)
+            .collect::<toml_edit::Array>(),
+    );
+    tokio::fs::write(&path, document.to_string())
+        .await
+        .map_err(|e| ConfigError::Write {
+            path: path.clone(),
+            source: e,
+        })?;
+
+    let mut live_config = LIVE_CONFIG.write().expect("write live config error");
+    let mut config = AllConfig::clone(&live_config);
+    config.banlist = banlist;
+    *live_config = Arc::new(config);
+    Ok(())
+}
+
+//定时检查配置文件的修改时间,变化后自动重载
+pub fn watch_config(connection_mg: Arc<ConnectionManager>, interval: Duration) {
+    tokio::spawn(async move {
+        let modified_time = || async {
+            tokio::fs::metadata(config_path())
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        };
+        let mut last_modified: Option<SystemTime> = modified_time().await;
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let modified = modified_time().await;
+            if modified.is_none() || modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            info!("检测到配置文件变化,正在重新加载");
+            if let Err(e) = reload_config(&connection_mg).await {
+                warn!("重新加载配置失败,继续使用原配置:\n{}", e);
+            }
+        }
+    });
+}
@@ -1,4 +1,56 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-pub enum ConfigError {}
+pub enum ConfigError {
+    #[error("无法读取配置文件{}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("无法写入配置文件{}: {source}", path.display())]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("配置文件{}{}有误: {message}", path.display(), format_position(.position))]
+    Parse {
+        path: PathBuf,
+        //(行, 列),从1开始
+        position: Option<(usize, usize)>,
+        message: String,
+    },
+    #[error("配置文件{}校验失败:\n{}", path.display(), .errors.join("\n"))]
+    Invalid { path: PathBuf, errors: Vec<String> },
+    #[error("默认配置转换TOML失败: {0}")]
+    Serialize(#[from] toml::ser::Error),
+}
+
+impl ConfigError {
+    pub fn from_toml(path: PathBuf, content: &str, e: toml::de::Error) -> Self {
+        let position = e.span().map(|span| {
+            let before = &content[..span.start.min(content.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before
+                .rsplit('\n')
+                .next()
+                .map(|s| s.chars().count())
+                .unwrap_or(0)
+                + 1;
+            (line, column)
+        });
+        ConfigError::Parse {
+            path,
+            position,
+            message: e.message().to_string(),
+        }
+    }
+}
+
+fn format_position(position: &Option<(usize, usize)>) -> String {
+    match position {
+        Some((line, column)) => format!("第{}行第{}列", line, column),
+        None => String::new(),
+    }
+}
@@ -1,14 +1,13 @@
 use std::{
-    collections::HashMap,
-    sync::Arc,
+    sync::{Arc, Mutex},
     time::{Duration, SystemTime},
 };
 
-use log::info;
+use log::{info, warn};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use reqwest::{
     header::{
-        HeaderMap, CONNECTION, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE, HOST, USER_AGENT,
+        HeaderMap, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST, USER_AGENT,
     },
     Client,
 };
@@ -16,6 +15,12 @@ use tokio::task::JoinHandle;
 use url::Url;
 use uuid::Uuid;
 
+use crate::server::UplistApi;
+
+lazy_static! {
+    static ref UPLIST: Mutex<Option<Uplist>> = Mutex::new(None);
+}
+
 type Token = String;
 type ServerUuid = String;
 static UPLIST_URL: [&str; 2] = [
@@ -89,6 +94,32 @@ impl Uplist {
     }
 }
 
+impl Drop for Uplist {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+//按配置启动或停止Uplist,配置变化时重新注册
+pub fn apply_uplist_config(uplist_config: &UplistApi) {
+    let mut uplist = UPLIST.lock().expect("lock uplist error");
+    *uplist = None;
+    if uplist_config.enable {
+        info!("Uplist正在以{}注册", uplist_config.game_name);
+        *uplist = Some(Uplist::new(
+            "false",
+            &uplist_config.game_name,
+            uplist_config.port,
+            1,
+            uplist_config.player_max_size,
+            &uplist_config.game_map,
+            &uplist_config.created_by,
+            &uplist_config.private_ip,
+            &uplist_config.game_status,
+        ));
+    }
+}
+
 async fn uplist_fn(
     uplist_data: Arc<UplistData>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -104,14 +135,17 @@ async fn uplist_fn(
     headers.append("Language", "zh".parse().unwrap());
     let mut client = reqwest::Client::new();
 
-    uplist_add(&mut client, &uplist_data, &headers).await?;
+    if let Err(e) = uplist_add(&mut client, &uplist_data, &headers).await {
+        warn!("uplist add error:{}", e);
+        return Err(e);
+    }
 
-    let update_body = format!("action=update&id={}&private_token={}&password_required={}&created_by={}&private_ip=34.92.10.132&port_number={}&game_map={}&game_mode=skirmishMap&game_status={}&player_count={}&max_player_count={}",uplist_data.server_uuid,uplist_data.token,uplist_data.passwd,uplist_data.created_by,uplist_data.port,uplist_data.game_map,uplist_data.game_status,uplist_data.player_size,uplist_data.player_max_size);
+    let update_body = format!("action=update&id={}&private_token={}&password_required={}&created_by={}&private_ip={}&port_number={}&game_map={}&game_mode=skirmishMap&game_status={}&player_count={}&max_player_count={}",uplist_data.server_uuid,uplist_data.token,uplist_data.passwd,uplist_data.created_by,uplist_data.private_ip,uplist_data.port,uplist_data.game_map,uplist_data.game_status,uplist_data.player_size,uplist_data.player_max_size);
 
     info!("update_body:\n{}", update_body);
     loop {
         uplist_update(&mut client, &update_body, &headers).await?;
-        std::thread::sleep(std::time::Duration::from_secs(3));
+        tokio::time::sleep(Duration::from_secs(3)).await;
     }
 }
 
@@ -0,0 +1,361 @@
+use std::{net::IpAddr, sync::Arc};
+
+use axum::{
+    extract::{Path, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use log::{info, warn};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+
+use crate::{
+    cluster::RemoteRoom,
+    connection::{permission_status::PermissionStatus, shared_connection::SharedConnection},
+    connection_manager::{By, ConnectionManager},
+    error::WebError,
+    relay_manager::relay::SharedRelayRoom,
+    server::{
+        reload::{reload_config, save_banlist, ReloadReport},
+        WebConfig,
+    },
+    NOW,
+};
+
+#[derive(Clone)]
+struct WebState {
+    connection_mg: Arc<ConnectionManager>,
+    token: Arc<String>,
+}
+
+#[derive(Serialize)]
+struct StatusView {
+    version: &'static str,
+    uptime_secs: u64,
+    connection_count: usize,
+    room_count: usize,
+}
+
+#[derive(Serialize)]
+struct ConnectionView {
+    addr: Option<String>,
+    player_name: String,
+    permission_status: PermissionStatus,
+    client_version: u32,
+    is_beta_version: bool,
+    room_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RoomPlayerView {
+    index: u32,
+    #[serde(flatten)]
+    connection: ConnectionView,
+}
+
+#[derive(Serialize)]
+struct RoomView {
+    id: String,
+    mods: bool,
+    version: u32,
+    max_player_size: i32,
+    start_game: bool,
+    host: Option<ConnectionView>,
+    players: Vec<RoomPlayerView>,
+}
+
+#[derive(Deserialize)]
+struct KickRequest {
+    addr: String,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BanRequest {
+    ip: IpAddr,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BroadcastRequest {
+    message: String,
+}
+
+impl From<&SharedConnection> for ConnectionView {
+    fn from(shared_con: &SharedConnection) -> Self {
+        let player_info = &shared_con.shared_data.player_info;
+        let connection_info = &shared_con.shared_data.connection_info;
+        Self {
+            addr: connection_info.addr.upgrade().map(|addr| addr.to_string()),
+            player_name: player_info
+                .player_name
+                .read()
+                .expect("read player name error")
+                .clone(),
+            permission_status: *player_info
+                .permission_status
+                .read()
+                .expect("read permission status error"),
+            client_version: connection_info
+                .client_version
+                .load(std::sync::atomic::Ordering::Relaxed),
+            is_beta_version: connection_info
+                .is_beta_version
+                .load(std::sync::atomic::Ordering::Relaxed),
+            room_id: player_info
+                .room_id
+                .read()
+                .expect("read room id error")
+                .clone(),
+        }
+    }
+}
+
+impl RoomView {
+    async fn new(shared_relay: &SharedRelayRoom) -> Self {
+        let (host, players) = shared_relay.get_room_member().await;
+        let custom = &shared_relay.shared_data.custom;
+        Self {
+            id: shared_relay.shared_data.id.clone(),
+            mods: custom.mods,
+            version: custom.version,
+            max_player_size: custom.max_player_size,
+            start_game: shared_relay
+                .shared_data
+                .start_game
+                .load(std::sync::atomic::Ordering::Relaxed),
+            host: host.as_deref().map(ConnectionView::from),
+            players: players
+                .iter()
+                .map(|(index, player)| RoomPlayerView {
+                    index: *index,
+                    connection: ConnectionView::from(player.as_ref()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl IntoResponse for WebError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            WebError::Unauthorized => StatusCode::UNAUTHORIZED,
+            WebError::NotFound(_) => StatusCode::NOT_FOUND,
+            WebError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+pub async fn start_web(
+    web_config: WebConfig,
+    connection_mg: Arc<ConnectionManager>,
+) -> anyhow::Result<()> {
+    let token = if web_config.token.is_empty() {
+        let token: String = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        warn!("未配置Web API的token,本次启动使用随机token:{}", token);
+        token
+    } else {
+        web_config.token
+    };
+
+    let app = router(connection_mg, token);
+
+    let listener = TcpListener::bind(&web_config.listen_addr).await?;
+    info!("Web API正在监听{}", web_config.listen_addr);
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            warn!("Web API已停止:{}", e);
+        }
+    });
+    Ok(())
+}
+
+//测试时直接在临时端口上启动
+pub fn router(connection_mg: Arc<ConnectionManager>, token: String) -> Router {
+    let state = WebState {
+        connection_mg,
+        token: Arc::new(token),
+    };
+
+    Router::new()
+        .route("/api/status", get(status))
+        .route("/api/connections", get(connection_list))
+        .route("/api/connections/kick", post(kick))
+        .route("/api/rooms", get(room_list))
+        .route("/api/rooms/:id/close", post(close_room))
+        .route("/api/cluster/rooms", get(cluster_room_list))
+        .route("/api/bans", get(ban_list).post(ban))
+        .route("/api/bans/:ip", delete(unban))
+        .route("/api/broadcast", post(broadcast))
+        .route("/api/config/reload", post(reload))
+        .layer(middleware::from_fn_with_state(state.clone(), auth))
+        .with_state(state)
+}
+
+async fn auth(State(state): State<WebState>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        //逐字节比较会泄露token前缀是否正确
+        .is_some_and(|token| token.as_bytes().ct_eq(state.token.as_bytes()).into());
+
+    if authorized {
+        next.run(request).await
+    } else {
+        WebError::Unauthorized.into_response()
+    }
+}
+
+async fn status(State(state): State<WebState>) -> Json<StatusView> {
+    Json(StatusView {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: NOW.elapsed().as_secs(),
+        connection_count: state.connection_mg.get_connection_list().await.len(),
+        room_count: state.connection_mg.get_relay_list().await.len(),
+    })
+}
+
+async fn connection_list(State(state): State<WebState>) -> Json<Vec<ConnectionView>> {
+    Json(
+        state
+            .connection_mg
+            .get_connection_list()
+            .await
+            .iter()
+            .map(|shared_con| ConnectionView::from(shared_con.as_ref()))
+            .collect(),
+    )
+}
+
+async fn room_list(State(state): State<WebState>) -> Json<Vec<RoomView>> {
+    let mut room_list = Vec::new();
+    for shared_relay in state.connection_mg.get_relay_list().await {
+        room_list.push(RoomView::new(&shared_relay).await);
+    }
+    room_list.sort_by(|a, b| a.id.cmp(&b.id));
+    Json(room_list)
+}
+
+async fn cluster_room_list(
+    State(state): State<WebState>,
+) -> Result<Json<Vec<RemoteRoom>>, WebError> {
+    let mut room_list = state
+        .connection_mg
+        .get_remote_relay_list()
+        .ok_or(WebError::NotFound("cluster".to_string()))?;
+    room_list.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(Json(room_list))
+}
+
+async fn kick(
+    State(state): State<WebState>,
+    Json(kick_request): Json<KickRequest>,
+) -> Result<StatusCode, WebError> {
+    let shared_con = state
+        .connection_mg
+        .get_connection_by(By::Addr(kick_request.addr.clone()))
+        .await
+        .ok_or(WebError::NotFound(kick_request.addr))?;
+
+    shared_con
+        .kick(
+            kick_request
+                .reason
+                .as_deref()
+                .unwrap_or("你已被管理员踢出"),
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn close_room(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, WebError> {
+    let id = id.strip_prefix('S').unwrap_or(&id);
+    if state
+        .connection_mg
+        .close_relay(id, "房间已被管理员关闭")
+        .await
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(WebError::NotFound(format!("S{}", id)))
+    }
+}
+
+async fn ban_list(State(state): State<WebState>) -> Json<Vec<IpAddr>> {
+    Json(state.connection_mg.get_banlist())
+}
+
+async fn ban(
+    State(state): State<WebState>,
+    Json(ban_request): Json<BanRequest>,
+) -> StatusCode {
+    state
+        .connection_mg
+        .ban(
+            ban_request.ip,
+            ban_request.reason.as_deref().unwrap_or("你已被封禁"),
+        )
+        .await;
+    persist_banlist(&state.connection_mg).await;
+    StatusCode::NO_CONTENT
+}
+
+async fn unban(
+    State(state): State<WebState>,
+    Path(ip): Path<String>,
+) -> Result<StatusCode, WebError> {
+    let ip = ip
+        .parse::<IpAddr>()
+        .map_err(|e| WebError::InvalidInput(e.to_string()))?;
+    if state.connection_mg.unban(&ip) {
+        persist_banlist(&state.connection_mg).await;
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(WebError::NotFound(ip.to_string()))
+    }
+}
+
+//写入失败时封禁仍然生效,只是重启后丢失
+async fn persist_banlist(connection_mg: &ConnectionManager) {
+    if let Err(e) = save_banlist(connection_mg).await {
+        warn!("封禁列表写入配置文件失败:{}", e);
+    }
+}
+
+async fn broadcast(
+    State(state): State<WebState>,
+    Json(broadcast_request): Json<BroadcastRequest>,
+) -> Result<StatusCode, WebError> {
+    if broadcast_request.message.is_empty() {
+        return Err(WebError::InvalidInput("message is empty".to_string()));
+    }
+    state
+        .connection_mg
+        .broadcast(&broadcast_request.message)
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn reload(State(state): State<WebState>) -> Result<Json<ReloadReport>, WebError> {
+    reload_config(&state.connection_mg)
+        .await
+        .map(Json)
+        .map_err(|e| WebError::InvalidInput(e.to_string()))
+}
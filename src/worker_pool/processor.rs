@@ -3,7 +3,7 @@ use std::sync::{atomic::{AtomicI64, Ordering}, Arc};
 
 
 use crate::{
-    connection::{permission_status::PermissionStatus, shared_connection::SharedConnection}, event::{Event, EventType, EVENT_CHANNEL}, packet::{Packet, PacketType}
+    chat_command::handle_chat_command, connection::{permission_status::PermissionStatus, shared_connection::SharedConnection}, event::{Event, EventType, EVENT_CHANNEL}, module::{PacketAction, MODULE_MANAGER}, packet::{Packet, PacketType}, server::reload::live_config
 };
 
 pub type ProcesseorData = (Arc<SharedConnection>, Packet);
@@ -11,8 +11,13 @@ pub type ProcesseorData = (Arc<SharedConnection>, Packet);
 pub async fn processor(data: async_channel::Receiver<(ProcesseorData,Arc<AtomicI64>)>) -> anyhow::Result<()> {
     loop {
         match data.recv().await {
-            Ok(((shared_con, packet),permit)) => {
+            Ok(((shared_con, mut packet),permit)) => {
                 permit.fetch_add(1, Ordering::Relaxed);
+                //模块可以修改或丢弃数据包
+                if MODULE_MANAGER.on_packet(&shared_con, &mut packet) == PacketAction::Drop {
+                    permit.fetch_add(-1, Ordering::Relaxed);
+                    continue;
+                }
                 let packet_type = packet.packet_type;
                 let player_info_arc = shared_con.shared_data.player_info.clone();
                 let connection_info_arc = shared_con.shared_data.connection_info.clone();
@@ -40,11 +45,11 @@ pub async fn processor(data: async_channel::Receiver<(ProcesseorData,Arc<AtomicI
 
                             let inspection_data = shared_con.relay_direct_inspection().await;
 
-                            if let Some(data) = inspection_data {
-                                if let (Some(name), None) =
-                                    (data.player_name.clone(), data.query_string)
-                                {
-                                    *player_info_arc.player_name.write().unwrap() = name;
+                            //带query_string的是查询服务器信息的连接,不是玩家
+                            match inspection_data {
+                                Some(data) if data.query_string.is_none() => {
+                                    let name = data.player_name.unwrap_or("unknown".to_string());
+                                    *player_info_arc.player_name.write().unwrap() = name.clone();
 
                                     connection_info_arc
                                         .client_version
@@ -52,15 +57,13 @@ pub async fn processor(data: async_channel::Receiver<(ProcesseorData,Arc<AtomicI
                                     connection_info_arc
                                         .is_beta_version
                                         .store(data.is_beta_version, Ordering::Relaxed);
-                                    shared_con.send_relay_hall_message(&format!(
-                                "[Relay CN]{} 欢迎使用RJR,这台服务是非官方的Relay房间\nThis server is CN's unofficial Relay room\n输入ID可进入房间,输入new/mods可创建房间\n输入/help可以获得更多帮助",
-                                data.player_name.unwrap_or("unkonwn".to_string())
-                                )).await;
-                                } else {
-                                    todo!()
+                                    let welcome = live_config().message.hall_welcome.replace(
+                                        "{player}",
+                                        &name,
+                                    );
+                                    shared_con.send_relay_hall_message(&welcome).await;
                                 }
-                            } else {
-                                shared_con.disconnect().await;
+                                _ => shared_con.disconnect().await,
                             }
                         }
                         PacketType::DISCONNECT => shared_con.disconnect().await,
@@ -93,8 +96,11 @@ pub async fn processor(data: async_channel::Receiver<(ProcesseorData,Arc<AtomicI
                         PacketType::ACCEPT_START_GAME => 
                             shared_con.send_packet_to_host(packet).await,
                         
-                        PacketType::CHAT_RECEIVE => 
-                            shared_con.send_packet_to_host(packet).await,
+                        //聊天命令只回复给玩家,不转发给房主
+                        PacketType::CHAT_RECEIVE => match handle_chat_command(&shared_con, &packet).await {
+                            Some(reply) => shared_con.send_system_message(&reply).await,
+                            None => shared_con.send_packet_to_host(packet).await,
+                        },
                         
                         PacketType::DISCONNECT => {
                             shared_con.disconnect().await;
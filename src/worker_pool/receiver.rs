@@ -1,17 +1,18 @@
-use std::io::Cursor;
-
 use std::sync::atomic::{AtomicI64, Ordering};
-use std::{sync::Arc, usize};
+use std::sync::Arc;
 
+use crate::capture::{capture_packet, format::Direction};
 use crate::connection::shared_connection::SharedConnection;
 
 use crate::core::ServerCommand;
-use crate::error::ReceiverError;
-use crate::packet::{Packet, PacketType};
+use crate::packet::codec::PacketCodec;
 
+use futures_util::StreamExt;
+use log::trace;
 use tokio::sync::{broadcast};
 
-use tokio::{io::AsyncReadExt, net::tcp::OwnedReadHalf};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio_util::codec::FramedRead;
 
 pub type ReceiverData = (
     Arc<SharedConnection>,
@@ -19,45 +20,31 @@ pub type ReceiverData = (
     broadcast::Receiver<ServerCommand>,
 );
 
-pub async fn receiver_fn(read_half: &mut OwnedReadHalf) -> anyhow::Result<Packet> {
-
-    let packet_length = read_half.read_i32().await?;
-
-    let packet_type = PacketType::try_from(read_half.read_u32().await?).unwrap_or_default();
-
-    if packet_length <= 0 {
-        return Err(ReceiverError::InvalidInput("packet length below zero".to_string()).into());
-    }
-    if packet_length >= 1024 * 50 || packet_type == PacketType::NOT_RESOLVED {
-        return Err(ReceiverError::InvalidInput("packet length too long".to_string()).into());
-    }
-
-    let mut packet_buffer = vec![0; packet_length as usize];
-    read_half.read_exact(&mut packet_buffer).await?;
-
-    Ok(Packet::decode_from_buffer(
-        packet_length as u32,
-        packet_type,
-        Cursor::new(packet_buffer),
-    ))
-}
-
 pub async fn receiver(
     data: async_channel::Receiver<(ReceiverData, Arc<AtomicI64>)>,
 ) -> anyhow::Result<()> {
     loop {
         match data.recv().await {
-            Ok(((shared_con, mut read_half, mut command_rx), permit)) => {
+            Ok(((shared_con, read_half, mut command_rx), permit)) => {
                 permit.fetch_add(1, Ordering::Relaxed);
+                //一次读取可能包含多个数据包,缓冲区在连接期间复用
+                let mut packets = FramedRead::new(read_half, PacketCodec);
                 loop {
                     tokio::select! {
-                        recv = receiver_fn(&mut read_half) => {
+                        recv = packets.next() => {
                             match recv {
-                                Ok(packet) => {
+                                Some(Ok(packet)) => {
+                                    capture_packet(
+                                        Direction::In,
+                                        &shared_con,
+                                        packet.packet_type as u32,
+                                        &packet.payload,
+                                    );
+                                    trace!(addr = shared_con.addr_string().as_str(); "收到{}", packet.decoded());
                                     shared_con.type_relay(shared_con.clone(), packet).await;
                                     continue;
                                 }
-                                Err(_) => {shared_con.disconnect().await;break;}
+                                _ => {shared_con.disconnect().await;break;}
                             };
                         }
 
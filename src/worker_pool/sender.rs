@@ -1,15 +1,18 @@
 use std::sync::{atomic::{AtomicI64, Ordering}, Arc};
 
+use futures_util::SinkExt;
+use log::trace;
 use tokio::{
-    io::AsyncWriteExt,
     net::tcp::OwnedWriteHalf,
     sync::{broadcast},
 };
+use tokio_util::codec::FramedWrite;
 
 use crate::{
+    capture::{capture_packet, format::Direction},
     connection::shared_connection::SharedConnection,
     core::ServerCommand,
-    packet::Packet,
+    packet::{codec::PacketCodec, Packet},
 };
 
 pub type SenderData = (
@@ -24,24 +27,27 @@ pub async fn sender(
 ) -> anyhow::Result<()> {
     loop {
         match data.recv().await {
-            Ok(((shared_con, packet_rx, mut write_half, mut command_rx), permit)) => {
+            Ok(((shared_con, packet_rx, write_half, mut command_rx), permit)) => {
                 permit.fetch_add(1, Ordering::Relaxed);
+                let mut packets = FramedWrite::new(write_half, PacketCodec);
                 loop {
                     tokio::select! {
                         data = packet_rx.recv() => {
                             match data {
-                                Ok(mut packet) => {
-
-                                    packet.prepare().await;
-
-                                    match write_half
-                                    .write_all(&packet.packet_buffer.into_inner())
-                                    .await {
-                                    Ok(_) => {
-                                    },
-                                    Err(_) => {shared_con.disconnect().await;break;},
+                                Ok(packet) => {
+                                    //把已经排队的数据包一起写入,只flush一次
+                                    let mut result = feed_packet(&shared_con, &mut packets, packet).await;
+                                    while result.is_ok() {
+                                        let Ok(packet) = packet_rx.try_recv() else {
+                                            break;
+                                        };
+                                        result = feed_packet(&shared_con, &mut packets, packet).await;
+                                    }
+                                    if result.is_err() || packets.flush().await.is_err() {
+                                        shared_con.disconnect().await;
+                                        break;
                                     }
-                                    },
+                                },
                                 Err(_) => {shared_con.disconnect().await;break;},
                             }
                         }
@@ -49,7 +55,14 @@ pub async fn sender(
                             match command {
                                 Ok(command) => {
                                     match command {
+                                        //断开前把已经排队的数据包发出去,比如踢出原因
                                         ServerCommand::Disconnect => {
+                                            while let Ok(packet) = packet_rx.try_recv() {
+                                                if feed_packet(&shared_con, &mut packets, packet).await.is_err() {
+                                                    break;
+                                                }
+                                            }
+                                            let _ = packets.flush().await;
                                             break;
                                         },
                                     }
@@ -65,3 +78,18 @@ pub async fn sender(
         }
     }
 }
+
+async fn feed_packet(
+    shared_con: &SharedConnection,
+    packets: &mut FramedWrite<OwnedWriteHalf, PacketCodec>,
+    packet: Packet,
+) -> std::io::Result<()> {
+    capture_packet(
+        Direction::Out,
+        shared_con,
+        packet.packet_type as u32,
+        &packet.payload,
+    );
+    trace!(addr = shared_con.addr_string().as_str(); "发送{}", packet.decoded());
+    packets.feed(packet).await
+}